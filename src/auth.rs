@@ -0,0 +1,251 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::network_handler::{FrameFormat, MsgType, Pdu, PduError};
+
+/// 服务器发出的挑战：`auth-challenge:<十六进制 nonce>`
+pub const AUTH_CHALLENGE: &str = "auth-challenge:";
+/// 客户端的应答：`auth-response:<密钥名>:<十六进制 HMAC-SHA256(key, nonce)>`
+pub const AUTH_RESPONSE: &str = "auth-response:";
+/// 认证通过：`auth-ok:<密钥名>`
+pub const AUTH_OK: &str = "auth-ok:";
+/// 服务器密钥文件路径的环境变量，设置后要求客户端先完成认证
+pub const ENV_PSK_FILE: &str = "SOCKET_PSK_FILE";
+/// 客户端使用的密钥名的环境变量，密钥文件中只有一个密钥时可以省略
+pub const ENV_PSK_KEY: &str = "SOCKET_PSK_KEY";
+/// 认证超时秒数的环境变量
+pub const ENV_PSK_TIMEOUT: &str = "SOCKET_PSK_TIMEOUT";
+/// nonce 的长度
+const NONCE_LEN: usize = 32;
+/// 默认的认证超时
+const DEFAULT_AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// 按名称保存的预共享密钥，同时保存多个密钥以便轮换
+#[derive(Clone, Default)]
+pub struct PskKeys {
+    keys: BTreeMap<String, Vec<u8>>,
+}
+
+impl fmt::Debug for PskKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // 不输出密钥内容
+        f.debug_struct("PskKeys").field("names", &self.keys.keys().collect::<Vec<_>>()).finish()
+    }
+}
+
+impl PskKeys {
+    pub fn new() -> Self {
+        PskKeys::default()
+    }
+
+    pub fn insert(&mut self, name: impl Into<String>, key: impl Into<Vec<u8>>) {
+        self.keys.insert(name.into(), key.into());
+    }
+
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.keys.get(name).map(Vec::as_slice)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.keys.keys().map(String::as_str)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// 从文件加载密钥，格式见 [`PskKeys::parse`]
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        PskKeys::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+    }
+
+    /// 每行一个密钥：`名称 = 十六进制密钥`，`#` 开头的行和空行会被忽略
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut keys = PskKeys::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((name, key)) = line.split_once('=') else {
+                return Err(format!("第 {} 行缺少 '='", i + 1));
+            };
+            let name = name.trim();
+            if name.is_empty() || name.contains(':') {
+                return Err(format!("第 {} 行的密钥名非法", i + 1));
+            }
+            let key = hex::decode(key.trim()).map_err(|e| format!("第 {} 行的密钥不是十六进制: {}", i + 1, e))?;
+            if key.is_empty() {
+                return Err(format!("第 {} 行的密钥为空", i + 1));
+            }
+            if keys.keys.contains_key(name) {
+                return Err(format!("第 {} 行的密钥名 {} 重复", i + 1, name));
+            }
+            keys.insert(name, key);
+        }
+        Ok(keys)
+    }
+}
+
+/// 预共享密钥认证失败的原因
+#[derive(Debug)]
+pub enum AuthError {
+    /// 超时前没有完成认证
+    Timeout,
+    /// 对端在认证完成前关闭了连接
+    Closed,
+    /// 第一个 PDU 不是认证应答
+    Unexpected,
+    /// 密钥名不存在
+    UnknownKey(String),
+    /// HMAC 不匹配
+    BadMac(String),
+    /// 读取或解析 PDU 失败
+    Pdu(PduError),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Timeout => write!(f, "认证超时"),
+            AuthError::Closed => write!(f, "认证完成前连接已关闭"),
+            AuthError::Unexpected => write!(f, "需要先完成认证"),
+            AuthError::UnknownKey(name) => write!(f, "未知的密钥 {}", name),
+            AuthError::BadMac(name) => write!(f, "密钥 {} 的认证码不匹配", name),
+            AuthError::Pdu(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl AuthError {
+    /// 发给客户端的错误说明
+    ///
+    /// 对端尚未通过认证，不回显密钥名，避免借此探测哪些密钥存在。
+    pub fn client_message(&self) -> String {
+        match self {
+            AuthError::UnknownKey(_) | AuthError::BadMac(_) => "认证失败".to_string(),
+            e => e.to_string(),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AuthError::Pdu(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for AuthError {
+    fn from(e: io::Error) -> Self {
+        PduError::Io(e).into()
+    }
+}
+
+impl From<PduError> for AuthError {
+    fn from(e: PduError) -> Self {
+        match e {
+            PduError::Io(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => AuthError::Timeout,
+            e => AuthError::Pdu(e),
+        }
+    }
+}
+
+/// 服务器端的预共享密钥认证配置
+///
+/// 连接建立后服务器先发送挑战，客户端必须在超时前用任意一个已配置的密钥应答，之后才开始处理业务 PDU。
+#[derive(Debug, Clone)]
+pub struct PskAuth {
+    pub keys: PskKeys,
+    /// 从连接建立到认证完成的最长时间
+    pub timeout: Duration,
+}
+
+impl PskAuth {
+    pub fn new(keys: PskKeys) -> Self {
+        PskAuth {
+            keys,
+            timeout: DEFAULT_AUTH_TIMEOUT,
+        }
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// 生成新的 nonce
+    pub fn nonce() -> io::Result<Vec<u8>> {
+        let mut nonce = vec![0; NONCE_LEN];
+        getrandom::getrandom(&mut nonce).map_err(io::Error::other)?;
+        Ok(nonce)
+    }
+
+    /// 校验客户端的应答，返回认证通过的密钥名
+    pub fn verify(&self, nonce: &[u8], pdu: &Pdu) -> Result<String, AuthError> {
+        let (name, mac) = parse_response(pdu).ok_or(AuthError::Unexpected)?;
+        let key = self.keys.get(&name).ok_or_else(|| AuthError::UnknownKey(name.clone()))?;
+        // verify_slice 以常数时间比较
+        let mut expected = HmacSha256::new_from_slice(key).expect("HMAC 接受任意长度的密钥");
+        expected.update(nonce);
+        match expected.verify_slice(&mac) {
+            Ok(()) => Ok(name),
+            Err(_) => Err(AuthError::BadMac(name)),
+        }
+    }
+}
+
+/// HMAC-SHA256(key, nonce)
+pub fn compute_mac(key: &[u8], nonce: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC 接受任意长度的密钥");
+    mac.update(nonce);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn control_text<'a>(pdu: &'a Pdu, prefix: &str) -> Option<&'a str> {
+    if pdu.kind != MsgType::Control {
+        return None;
+    }
+    std::str::from_utf8(&pdu.payload).ok()?.strip_prefix(prefix)
+}
+
+pub fn challenge_pdu(nonce: &[u8], format: &FrameFormat) -> Result<Pdu, PduError> {
+    let payload = format!("{}{}", AUTH_CHALLENGE, hex::encode(nonce));
+    Pdu::with_type(MsgType::Control, 0, payload.as_bytes(), format)
+}
+
+/// 解析服务器的挑战，返回 nonce
+pub fn parse_challenge(pdu: &Pdu) -> Option<Vec<u8>> {
+    hex::decode(control_text(pdu, AUTH_CHALLENGE)?).ok()
+}
+
+/// 客户端用指定的密钥应答挑战
+pub fn response_pdu(name: &str, key: &[u8], nonce: &[u8], format: &FrameFormat) -> Result<Pdu, PduError> {
+    let payload = format!("{}{}:{}", AUTH_RESPONSE, name, hex::encode(compute_mac(key, nonce)));
+    Pdu::with_type(MsgType::Control, 0, payload.as_bytes(), format)
+}
+
+fn parse_response(pdu: &Pdu) -> Option<(String, Vec<u8>)> {
+    let (name, mac) = control_text(pdu, AUTH_RESPONSE)?.split_once(':')?;
+    Some((name.to_string(), hex::decode(mac).ok()?))
+}
+
+pub fn ok_pdu(name: &str, format: &FrameFormat) -> Result<Pdu, PduError> {
+    Pdu::with_type(MsgType::Control, 0, format!("{}{}", AUTH_OK, name).as_bytes(), format)
+}
+
+/// 解析认证通过的消息，返回密钥名
+pub fn parse_ok(pdu: &Pdu) -> Option<&str> {
+    control_text(pdu, AUTH_OK)
+}
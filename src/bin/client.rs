@@ -1,29 +1,145 @@
-use std::net::{TcpStream};
 use std::io::{stdin, Read, Write};
 
-use socket::network_handler::Pdu;
+use clap::Parser;
+use socket::auth::{self, PskKeys};
+use socket::cli::{self, FrameArgs};
+use socket::codec::FrameAssembler;
+use socket::network_handler::{Compression, FrameFormat, MsgType, Pdu, Version};
+use socket::tls::{self, ClientTlsStream};
 
-const MAX_MSG_LEN: usize = 255;
-const BUFFER_SIZE: usize = MAX_MSG_LEN + 1;
+const BUFFER_SIZE: usize = 1024;
+
+/// 明文或 TLS 连接
+trait Stream: Read + Write {}
+
+impl<T: Read + Write> Stream for T {}
+
+/// 客户端的命令行参数，TLS 与认证相关的参数未指定时从环境变量读取
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// 服务器地址 host:port，主机名解析出的多个地址会依次尝试
+    #[arg(long, value_name = "HOST:PORT", default_value = "127.0.0.1:8080")]
+    connect: String,
+    /// 信任的 CA 证书，设置后使用 TLS
+    #[arg(long, value_name = "PEM", env = tls::ENV_TLS_CA)]
+    ca: Option<String>,
+    /// 校验服务器证书使用的域名
+    #[arg(long, env = tls::ENV_TLS_SERVER_NAME, default_value = "localhost")]
+    server_name: String,
+    /// mTLS 时出示的客户端证书
+    #[arg(long, value_name = "PEM", requires = "key")]
+    cert: Option<String>,
+    /// mTLS 时出示的客户端私钥
+    #[arg(long, value_name = "PEM", requires = "cert")]
+    key: Option<String>,
+    /// 预共享密钥文件，设置后先响应服务器的认证挑战
+    #[arg(long, value_name = "FILE", env = auth::ENV_PSK_FILE)]
+    psk_file: Option<String>,
+    /// 使用的密钥名，密钥文件中只有一个密钥时可以省略
+    #[arg(long, value_name = "NAME", env = auth::ENV_PSK_KEY)]
+    psk_key: Option<String>,
+    #[command(flatten)]
+    frame: FrameArgs,
+}
 
 fn main() {
+    let args = Args::parse();
+    // 帧格式必须与服务器保持一致
+    let mut format = match args.frame.format() {
+        Ok(format) => format,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+    if format.version == Version::V1 && args.psk_file.is_some() {
+        eprintln!("预共享密钥认证需要 v2 协议");
+        return;
+    }
+    // 与服务器一样，协商完成前发送的 PDU 不压缩
+    let preferred = format.compression;
+    format.compression = Compression::None;
+
     // 连接到服务器
-    let mut stream = TcpStream::connect("127.0.0.1:8080").expect("无法连接到服务器");
-    println!("[cli] server[{}] is connected!", stream.peer_addr().unwrap());
+    let tcp = cli::connect(&args.connect).expect("无法连接到服务器");
+    println!("[cli] server[{}] is connected!", tcp.peer_addr().unwrap());
+
+    // 设置了 CA 证书路径时使用 TLS
+    let mut stream: Box<dyn Stream> = match &args.ca {
+        Some(ca) => {
+            let config = match (&args.cert, &args.key) {
+                (Some(cert), Some(key)) => tls::client_config_with_cert(ca, cert, key).expect("无法加载客户端证书"),
+                _ => tls::client_config(ca).expect("无法加载 CA 证书"),
+            };
+            let stream = ClientTlsStream::connect(tcp.try_clone().unwrap(), &args.server_name, config).expect("TLS 握手失败");
+            println!("[cli] TLS 握手完成");
+            Box::new(stream)
+        }
+        None => Box::new(tcp.try_clone().unwrap()),
+    };
 
     let stdin = stdin();
     let mut input_buffer = String::new();
     let mut buffer = [0_u8; BUFFER_SIZE];
+    // 每个请求使用递增的请求ID，用于与响应对应
+    let mut request_id: u32 = 0;
+    // 在整个会话中保留，避免丢弃已接收但尚未解析的数据
+    let mut assembler = FrameAssembler::new(format);
+
+    // 设置了密钥文件时先完成预共享密钥认证
+    if let Some(path) = &args.psk_file {
+        let keys = match PskKeys::load(path) {
+            Ok(keys) => keys,
+            Err(e) => {
+                eprintln!("无法加载预共享密钥: {}", e);
+                return;
+            }
+        };
+        let names: Vec<&str> = keys.names().collect();
+        let name = match (&args.psk_key, names.as_slice()) {
+            (Some(name), _) => name.as_str(),
+            (None, [name]) => name,
+            (None, _) => {
+                eprintln!("密钥文件中有多个密钥，需要用 --psk-key 指定");
+                return;
+            }
+        };
+        let Some(key) = keys.get(name) else {
+            eprintln!("密钥文件中没有密钥 {}", name);
+            return;
+        };
+        if let Err(e) = authenticate(stream.as_mut(), &mut assembler, &format, name, key) {
+            eprintln!("认证失败: {}", e);
+            return;
+        }
+        println!("[cli] 使用密钥 {} 认证通过", name);
+    }
+
+    // 与服务器协商压缩算法，之后发送的 PDU 使用服务器选定的算法；v1 没有控制消息，不协商
+    if format.version == Version::V2 {
+        match negotiate_compression(stream.as_mut(), &mut assembler, &format, preferred) {
+            Ok(accepted) => {
+                println!("[cli] 协商的压缩算法: {:?}", accepted);
+                format.compression = accepted.first().copied().unwrap_or(Compression::None);
+            }
+            Err(e) => {
+                eprintln!("压缩协商失败: {}", e);
+                return;
+            }
+        }
+    }
 
     println!("请输入要发送到服务器的消息（输入 'EXIT' 退出）:");
 
-    loop {
+    'session: loop {
         input_buffer.clear();
         stdin.read_line(&mut input_buffer).expect("读取输入失败");
 
         if input_buffer.trim() == "EXIT" {
-            // 关闭TCP连接
-            match stream.shutdown(std::net::Shutdown::Both) { // 关闭连接的读写两端
+            // 先释放 TLS 连接以发送 close_notify，再关闭TCP连接
+            drop(stream);
+            match tcp.shutdown(std::net::Shutdown::Both) { // 关闭连接的读写两端
                 Ok(_) => println!("[cli] stream is closed!"),
                 Err(e) => eprintln!("关闭连接时出错: {}", e),
             }
@@ -32,8 +148,16 @@ fn main() {
 
         if !input_buffer.is_empty() {
             println!("[ECH_RQT]{}", input_buffer);
-            let pdu = Pdu::new(input_buffer.as_bytes());
-            match stream.write_all(pdu.unwrap().to_vec().as_slice()) {
+            request_id = request_id.wrapping_add(1);
+            let bytes = match Pdu::with_type(MsgType::Data, request_id, input_buffer.as_bytes(), &format)
+                .and_then(|pdu| pdu.to_vec(&format)) {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    eprintln!("消息未发送: {}", e);
+                    continue;
+                }
+            };
+            match stream.write_all(bytes.as_slice()) {
                 Ok(_) => {
                     // 消息已发送
                 }
@@ -44,42 +168,106 @@ fn main() {
             }
         }
 
-        let mut received_data: Vec<u8> = Vec::new(); // 存储已接收但尚未构成完整PDU的数据
-        let mut pdu: Option<Pdu> = None;
-        loop {
+        // 等待服务器的响应，一次读取可能包含多个 PDU，也可能只有半个
+        let mut responded = false;
+        while !responded {
             match stream.read(&mut buffer) {
                 Ok(0) => {
                     println!("服务器已关闭连接");
-                    break;
+                    break 'session;
                 }
                 Ok(size) => {
                     println!("从服务器接收到 {} 字节数据", size);
-
-                    received_data.extend_from_slice(&buffer[..size]);
-                    // 解析自定义应用层协议PDU
-                    if !Pdu::is_complete_pdu(&received_data) {
-                        continue; // 接收到的数据不完整，等待下一次接收
-                    }
-                    match Pdu::payload_size(&received_data) {
-                        Some(expected_size) => {
-                            pdu = Pdu::from_bytes(&received_data[..expected_size]);
-                            received_data.clear();
-                        }
-                        None => { // 输入的buffer没有内容
-                            continue;
-                        }
-                    }
-
-                    println!("收到PDU: {}", pdu.as_ref().unwrap());
-                    break;
+                    assembler.push(&buffer[..size]);
                 }
                 Err(e) => {
                     eprintln!("读取服务器消息失败: {}", e);
-                    break;
+                    break 'session;
+                }
+            }
+
+            loop {
+                let pdu = match assembler.next_pdu() {
+                    Ok(Some(pdu)) => pdu,
+                    Ok(None) => break, // 接收到的数据不完整，等待下一次接收
+                    Err(e) => {
+                        // 拒绝未知版本或非法的头部，继续解析只会得到错乱的数据
+                        eprintln!("服务器响应非法: {}", e);
+                        break 'session;
+                    }
+                };
+                if pdu.is_server_busy() {
+                    println!("[cli] 服务器繁忙，请稍后重试");
+                    break 'session;
+                }
+                if pdu.is_going_away() {
+                    // 服务器处理完当前请求后会关闭连接
+                    println!("[cli] 服务器正在关闭，不再处理新的请求");
+                    continue;
+                }
+                match pdu.kind {
+                    MsgType::Error => eprintln!("服务器返回错误: {}", String::from_utf8_lossy(&pdu.payload)),
+                    _ => println!("收到PDU: {}", pdu),
+                }
+                // v1 没有请求ID，按顺序对应
+                if format.version == Version::V1 || pdu.request_id == request_id {
+                    responded = true;
+                } else {
+                    eprintln!("响应的请求ID {} 与请求 {} 不一致", pdu.request_id, request_id);
                 }
             }
         }
     }
 
     println!("[cli] client is to return!");
-}
\ No newline at end of file
+}
+
+/// 用指定的密钥应答服务器的挑战，等待认证通过
+fn authenticate(stream: &mut dyn Stream, assembler: &mut FrameAssembler, format: &FrameFormat, name: &str, key: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut buffer = [0_u8; BUFFER_SIZE];
+    loop {
+        while let Some(pdu) = assembler.next_pdu()? {
+            if let Some(nonce) = auth::parse_challenge(&pdu) {
+                stream.write_all(&auth::response_pdu(name, key, &nonce, format)?.to_vec(format)?)?;
+            } else if auth::parse_ok(&pdu).is_some() {
+                return Ok(());
+            } else if pdu.kind == MsgType::Error {
+                return Err(String::from_utf8_lossy(&pdu.payload).into_owned().into());
+            }
+        }
+        match stream.read(&mut buffer)? {
+            0 => return Err("服务器已关闭连接".into()),
+            size => assembler.push(&buffer[..size]),
+        }
+    }
+}
+
+/// 发送本端支持的压缩算法（preferred 排在最前），等待服务器返回双方都支持的算法
+fn negotiate_compression(
+    stream: &mut dyn Stream,
+    assembler: &mut FrameAssembler,
+    format: &FrameFormat,
+    preferred: Compression,
+) -> Result<Vec<Compression>, Box<dyn std::error::Error>> {
+    let payload = Compression::handshake_payload(&Compression::negotiate(preferred, &Compression::supported()));
+    // 协商请求使用请求ID 0，业务请求从 1 开始
+    stream.write_all(&Pdu::with_type(MsgType::Control, 0, &payload, format)?.to_vec(format)?)?;
+
+    let mut buffer = [0_u8; BUFFER_SIZE];
+    loop {
+        while let Some(pdu) = assembler.next_pdu()? {
+            if let Some(accepted) = Compression::parse_handshake(&pdu) {
+                return Ok(accepted);
+            }
+            if pdu.kind == MsgType::Error {
+                // 服务器不支持协商时不压缩
+                eprintln!("服务器返回错误: {}", String::from_utf8_lossy(&pdu.payload));
+                return Ok(Vec::new());
+            }
+        }
+        match stream.read(&mut buffer)? {
+            0 => return Err("服务器已关闭连接".into()),
+            size => assembler.push(&buffer[..size]),
+        }
+    }
+}
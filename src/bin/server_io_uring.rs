@@ -0,0 +1,21 @@
+use clap::Parser;
+use socket::cli::ServerArgs;
+use socket::network_handler::Echo;
+use socket::server::Model;
+
+fn main() {
+    // 设置优先级：命令行参数 > 环境变量 > 配置文件
+    let builder = match ServerArgs::parse().builder(Model::IoUring) {
+        Ok(builder) => builder,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    let server = builder.handler(Echo).handle_signals(true).build().expect("无法绑定到地址");
+
+    server.run().expect("服务器运行失败");
+
+    // 正常退出服务器
+    println!("服务器关闭");
+}
@@ -0,0 +1,170 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use clap::{Args, Parser};
+
+use crate::config::{self, Config, ConfigError};
+use crate::log::Level;
+use crate::network_handler::{Checksum, Compression, CorruptPolicy, FrameFormat, LengthFormat, Version};
+use crate::server::{Model, ServerBuilder};
+use crate::log_info;
+
+/// 地址中没有端口时使用的默认端口
+pub const DEFAULT_PORT: u16 = 8080;
+
+/// 未指定监听地址时使用的地址
+pub const DEFAULT_BIND: &str = "0.0.0.0";
+
+/// 所有服务器程序共用的监听参数，未指定的参数由配置文件或默认值决定
+#[derive(Debug, Clone, Args)]
+pub struct ListenArgs {
+    /// 监听地址，可以重复指定以同时监听多个地址；不带端口时使用 --port，端口为 0 时由系统选择 [默认: 0.0.0.0]
+    #[arg(long = "bind", value_name = "ADDR")]
+    pub bind: Vec<String>,
+    /// 地址中没有端口时使用的端口 [默认: 8080]
+    #[arg(long)]
+    pub port: Option<u16>,
+    /// listen() 队列长度 [默认: 128]
+    #[arg(long)]
+    pub backlog: Option<i32>,
+}
+
+/// 服务器与客户端共用的帧格式参数，双方必须一致
+#[derive(Debug, Clone, Default, Args)]
+pub struct FrameArgs {
+    /// 协议版本，v1 为兼容旧客户端的 `[len][payload]` 格式，没有消息类型与控制消息 [默认: v2]
+    #[arg(long, value_name = "VERSION")]
+    pub protocol_version: Option<Version>,
+    /// 长度字段的编码方式 [默认: u32]
+    #[arg(long, value_name = "FORMAT")]
+    pub length_format: Option<LengthFormat>,
+    /// 单个 PDU 的最大 payload 字节数，不能超过长度字段能表示的范围 [默认: 1048576]
+    #[arg(long, value_name = "BYTES")]
+    pub max_payload: Option<usize>,
+    /// 发送的 PDU 末尾附加的校验和（仅 v2），接收时按每个 PDU 的 flags 校验 [默认: none]
+    #[arg(long, value_name = "ALGORITHM")]
+    pub checksum: Option<Checksum>,
+    /// 收到损坏 PDU 时的处理方式，resync 需要同时指定 --checksum [默认: disconnect]
+    #[arg(long, value_name = "POLICY")]
+    pub on_corrupt: Option<CorruptPolicy>,
+    /// 协商压缩时的首选算法（仅 v2），none 表示没有偏好 [默认: none]
+    #[arg(long, value_name = "ALGORITHM")]
+    pub compression: Option<Compression>,
+    /// payload 不短于该字节数时才压缩 [默认: 256]
+    #[arg(long, value_name = "BYTES")]
+    pub compress_threshold: Option<usize>,
+}
+
+impl FrameArgs {
+    /// 不读取配置文件的帧格式，未指定的参数使用默认值
+    pub fn format(&self) -> Result<FrameFormat, ConfigError> {
+        let mut config = Config::default();
+        config.apply_frame_args(self);
+        config.validate()?;
+        Ok(config.frame_format())
+    }
+}
+
+/// 服务器程序的命令行参数
+#[derive(Debug, Clone, Parser)]
+#[command(version)]
+pub struct ServerArgs {
+    #[command(flatten)]
+    pub listen: ListenArgs,
+    #[command(flatten)]
+    pub frame: FrameArgs,
+    /// 线程池模型的工作线程数或预派生模型的工作进程数 [默认: 8]
+    #[arg(long)]
+    pub workers: Option<usize>,
+    /// 线程池模型中排队等待的连接数上限，超出时通知客户端服务器繁忙 [默认: 64]
+    #[arg(long)]
+    pub queue_depth: Option<usize>,
+    /// 每个工作线程、工作进程或 accept 循环打开自己的 SO_REUSEPORT 监听器，由内核分配连接
+    #[arg(long)]
+    pub reuse_port: bool,
+    /// TOML 配置文件，命令行参数与环境变量优先于其中的设置
+    #[arg(long, value_name = "PATH", env = config::ENV_CONFIG)]
+    pub config: Option<PathBuf>,
+    /// 日志级别
+    #[arg(long, value_name = "LEVEL")]
+    pub log_level: Option<Level>,
+}
+
+impl ServerArgs {
+    /// 按 命令行参数 > 环境变量 > 配置文件 的优先级合并出最终的配置
+    pub fn config(&self) -> Result<Config, ConfigError> {
+        let mut config = match &self.config {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+        config.apply_env()?;
+        config.apply_args(self);
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// 合并配置并创建服务器构建器，配置中没有指定模型时使用 `default_model`
+    ///
+    /// 收到 SIGHUP 时按同样的优先级重新合并配置，只更新 [`Settings`](crate::server::Settings) 中的设置。
+    pub fn builder(&self, default_model: Model) -> Result<ServerBuilder, ConfigError> {
+        let initial = self.config()?;
+        let builder = initial.builder(default_model)?;
+        let args = self.clone();
+        Ok(builder.reload(move || {
+            let config = args.config()?;
+            let settings = config.settings()?;
+            if config.server != initial.server || config.tls != initial.tls {
+                log_info!("[srv] [server] 与 [tls] 中的设置需要重启服务器才能生效");
+            }
+            Ok(settings)
+        }))
+    }
+}
+
+/// 为每个地址补全端口，没有地址时使用 [`DEFAULT_BIND`]
+pub fn listen_addrs(bind: &[String], port: Option<u16>) -> Vec<String> {
+    let port = port.unwrap_or(DEFAULT_PORT);
+    if bind.is_empty() {
+        return vec![with_port(DEFAULT_BIND, port)];
+    }
+    bind.iter().map(|addr| with_port(addr, port)).collect()
+}
+
+/// 为没有端口的地址补上端口：`0.0.0.0`、`::1`、`[::1]`、`localhost` 以及只有端口的 `:0`
+pub fn with_port(addr: &str, port: u16) -> String {
+    if let Some(port) = addr.strip_prefix(':').filter(|port| port.parse::<u16>().is_ok()) {
+        return format!("0.0.0.0:{}", port);
+    }
+    if addr.parse::<SocketAddr>().is_ok() {
+        return addr.to_string();
+    }
+    let host = addr.strip_prefix('[').and_then(|addr| addr.strip_suffix(']')).unwrap_or(addr);
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return SocketAddr::new(ip, port).to_string();
+    }
+    match addr.rsplit_once(':') {
+        Some((_, port)) if port.parse::<u16>().is_ok() => addr.to_string(),
+        _ => format!("{}:{}", addr, port),
+    }
+}
+
+/// 解析 `host:port`，按解析结果的顺序依次尝试连接，返回第一个成功的连接
+pub fn connect(target: &str) -> io::Result<TcpStream> {
+    let addrs: Vec<SocketAddr> = target.to_socket_addrs()?.collect();
+    connect_any(&addrs)
+}
+
+/// 按顺序依次尝试连接每个地址
+pub fn connect_any(addrs: &[SocketAddr]) -> io::Result<TcpStream> {
+    let mut last_error = None;
+    for addr in addrs {
+        match TcpStream::connect(addr) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => {
+                eprintln!("[cli] 连接 {} 失败: {}", addr, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "没有可以连接的地址")))
+}
@@ -0,0 +1,360 @@
+use std::io::{self, Read, Write};
+use bytes::{Buf, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::network_handler::{next_sync_offset, Checksum, CorruptPolicy, FrameFormat, Pdu, PduError, Version};
+
+/// [`PduReader`] 每次从连接读取的默认字节数
+pub const DEFAULT_READ_BUFFER: usize = 1024;
+
+/// 供 `tokio_util::codec::Framed` 使用的 PDU 编解码器
+///
+/// ```no_run
+/// # async fn demo(stream: tokio::net::TcpStream) -> Result<(), socket::network_handler::PduError> {
+/// use futures::{SinkExt, StreamExt};
+/// use tokio_util::codec::Framed;
+/// use socket::codec::PduCodec;
+/// use socket::network_handler::FrameFormat;
+///
+/// let mut framed = Framed::new(stream, PduCodec::new(FrameFormat::default()));
+/// while let Some(pdu) = framed.next().await {
+///     framed.send(pdu?).await?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PduCodec {
+    decoder: FrameDecoder,
+}
+
+impl PduCodec {
+    pub fn new(format: FrameFormat) -> Self {
+        PduCodec { decoder: FrameDecoder::new(format) }
+    }
+
+    pub fn format(&self) -> &FrameFormat {
+        &self.decoder.format
+    }
+
+    /// 因损坏或重新同步而丢弃的字节总数
+    pub fn skipped_bytes(&self) -> u64 {
+        self.decoder.skipped_bytes
+    }
+}
+
+/// PduCodec 与 FrameAssembler 共用的解码状态
+#[derive(Debug, Clone, Default)]
+struct FrameDecoder {
+    format: FrameFormat,
+    /// 是否正在按 CorruptPolicy::Resync 查找下一个有效的 PDU
+    resyncing: bool,
+    skipped_bytes: u64,
+}
+
+impl FrameDecoder {
+    fn new(format: FrameFormat) -> Self {
+        FrameDecoder {
+            format,
+            resyncing: false,
+            skipped_bytes: 0,
+        }
+    }
+
+    /// 从缓冲区开头解码一个 PDU，返回应当从缓冲区移除的字节数以及解码结果
+    ///
+    /// 按 [`CorruptPolicy`] 可以恢复的错误返回后，已经跳过了损坏的数据，可以继续调用。
+    fn decode(&mut self, buffer: &[u8]) -> (usize, Result<Option<Pdu>, PduError>) {
+        let mut pos = 0;
+        loop {
+            let rest = &buffer[pos..];
+            let error = match Pdu::decode(rest, &self.format) {
+                Ok(None) => return (pos, Ok(None)), // 接收到的数据不完整，等待下一次接收
+                Ok(Some((pdu, used))) => {
+                    // 重新同步时只有校验通过的 PDU 才能证明找到了真正的帧边界
+                    if !self.resyncing || matches!(pdu.checksum(), Ok(checksum) if checksum != Checksum::None) {
+                        self.resyncing = false;
+                        return (pos + used, Ok(Some(pdu)));
+                    }
+                    None
+                }
+                Err(e) => Some(e),
+            };
+
+            if self.resyncing {
+                let skip = next_sync_offset(rest);
+                self.skipped_bytes += skip as u64;
+                pos += skip;
+                continue;
+            }
+
+            let e = error.expect("不在重新同步时解码成功已经返回");
+            let resync = self.format.on_corrupt == CorruptPolicy::Resync && self.format.version == Version::V2;
+            let skip = if e.is_frame_local() && !(resync && matches!(e, PduError::BadChecksum { .. })) {
+                // 头部合法，按声明的长度跳过整个 PDU
+                Pdu::payload_size(rest, &self.format).unwrap_or(0)
+            } else if self.format.recovers_from(&e) {
+                // 校验和不匹配时长度字段也可能已损坏，从下一个魔数开始查找
+                self.resyncing = true;
+                next_sync_offset(rest)
+            } else {
+                0
+            };
+            self.skipped_bytes += skip as u64;
+            return (pos + skip, Err(e));
+        }
+    }
+}
+
+fn decode_frame(decoder: &mut FrameDecoder, src: &mut BytesMut) -> Result<Option<Pdu>, PduError> {
+    let (used, result) = decoder.decode(src);
+    src.advance(used); // 移除已处理的数据
+    result
+}
+
+impl Decoder for PduCodec {
+    type Item = Pdu;
+    type Error = PduError;
+
+    /// `Framed` 在解码器返回错误后会结束整个流，需要按 [`CorruptPolicy`] 继续处理时使用 [`RecoverableCodec`]
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Pdu>, PduError> {
+        decode_frame(&mut self.decoder, src)
+    }
+}
+
+impl Encoder<Pdu> for PduCodec {
+    type Error = PduError;
+
+    fn encode(&mut self, pdu: Pdu, dst: &mut BytesMut) -> Result<(), PduError> {
+        dst.extend_from_slice(&pdu.to_vec(self.format())?);
+        Ok(())
+    }
+}
+
+/// 将可跳过的错误作为流中的元素返回的 [`PduCodec`]
+///
+/// 按帧格式的 [`CorruptPolicy`] 可以恢复的错误以 `Ok(Some(Err(e)))` 返回，流不会因此结束。
+#[derive(Debug, Clone, Default)]
+pub struct RecoverableCodec {
+    inner: PduCodec,
+}
+
+impl RecoverableCodec {
+    pub fn new(format: FrameFormat) -> Self {
+        RecoverableCodec { inner: PduCodec::new(format) }
+    }
+
+    pub fn format(&self) -> &FrameFormat {
+        self.inner.format()
+    }
+
+    /// 因损坏或重新同步而丢弃的字节总数
+    pub fn skipped_bytes(&self) -> u64 {
+        self.inner.skipped_bytes()
+    }
+}
+
+impl Decoder for RecoverableCodec {
+    type Item = Result<Pdu, PduError>;
+    type Error = PduError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, PduError> {
+        match decode_frame(&mut self.inner.decoder, src) {
+            Err(e) if self.inner.format().recovers_from(&e) => Ok(Some(Err(e))),
+            result => result.map(|pdu| pdu.map(Ok)),
+        }
+    }
+}
+
+impl Encoder<Pdu> for RecoverableCodec {
+    type Error = PduError;
+
+    fn encode(&mut self, pdu: Pdu, dst: &mut BytesMut) -> Result<(), PduError> {
+        self.inner.encode(pdu, dst)
+    }
+}
+
+/// 帧重组器：接收任意切分的字节块，按顺序产出其中所有完整的 PDU
+///
+/// ```
+/// use socket::codec::FrameAssembler;
+/// use socket::network_handler::{FrameFormat, Pdu};
+///
+/// let format = FrameFormat::default();
+/// let mut bytes = Pdu::new(b"hello", &format).unwrap().to_vec(&format).unwrap();
+/// bytes.extend(Pdu::new(b"world", &format).unwrap().to_vec(&format).unwrap());
+///
+/// let mut assembler = FrameAssembler::new(format);
+/// assembler.push(&bytes[..3]);
+/// assert!(assembler.next_pdu().unwrap().is_none());
+/// assembler.push(&bytes[3..]);
+/// assert_eq!(assembler.next_pdu().unwrap().unwrap().payload, b"hello");
+/// assert_eq!(assembler.next_pdu().unwrap().unwrap().payload, b"world");
+/// assert!(assembler.next_pdu().unwrap().is_none());
+/// ```
+pub struct FrameAssembler {
+    decoder: FrameDecoder,
+    /// 存储已接收但尚未构成完整PDU的数据
+    buffer: Vec<u8>,
+    /// buffer 中已解析数据的结束位置，避免每解析一个 PDU 都移动剩余数据
+    start: usize,
+}
+
+impl FrameAssembler {
+    pub fn new(format: FrameFormat) -> Self {
+        FrameAssembler {
+            decoder: FrameDecoder::new(format),
+            buffer: Vec::new(),
+            start: 0,
+        }
+    }
+
+    pub fn format(&self) -> &FrameFormat {
+        &self.decoder.format
+    }
+
+    /// 追加新收到的字节
+    pub fn push(&mut self, chunk: &[u8]) {
+        if self.start > 0 {
+            self.buffer.drain(..self.start); // 移除已处理的数据
+            self.start = 0;
+        }
+        self.buffer.extend_from_slice(chunk);
+    }
+
+    /// 取出下一个完整的 PDU，数据不完整时返回 `Ok(None)`
+    ///
+    /// 返回的错误若按 [`FrameFormat::recovers_from`] 可以恢复，损坏的数据已被跳过，可以继续调用；
+    /// 返回其他错误后数据流已无法继续解析，调用方应当断开连接。
+    pub fn next_pdu(&mut self) -> Result<Option<Pdu>, PduError> {
+        let (used, result) = self.decoder.decode(&self.buffer[self.start..]);
+        self.consume(used);
+        result
+    }
+
+    fn consume(&mut self, used: usize) {
+        self.start += used;
+        if self.start == self.buffer.len() {
+            self.buffer.clear();
+            self.start = 0;
+        }
+    }
+
+    /// 尚未构成完整 PDU 的字节数
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len() - self.start
+    }
+
+    /// 构成下一个完整 PDU 至少还需要多少字节
+    pub fn missing_bytes(&self) -> Result<usize, PduError> {
+        self.decoder.format.missing_bytes(&self.buffer[self.start..])
+    }
+
+    /// 因损坏或重新同步而丢弃的字节总数
+    pub fn skipped_bytes(&self) -> u64 {
+        self.decoder.skipped_bytes
+    }
+}
+
+/// 从 `Read` 中逐个读取 PDU，与 [`PduCodec`] 共用解析逻辑
+pub struct PduReader<R> {
+    inner: R,
+    assembler: FrameAssembler,
+    /// 每次读取使用的缓冲区
+    chunk: Vec<u8>,
+}
+
+impl<R: Read> PduReader<R> {
+    pub fn new(inner: R, format: FrameFormat) -> Self {
+        PduReader {
+            inner,
+            assembler: FrameAssembler::new(format),
+            chunk: vec![0; DEFAULT_READ_BUFFER],
+        }
+    }
+
+    /// 每次从连接读取的字节数，默认 [`DEFAULT_READ_BUFFER`]
+    pub fn read_buffer(mut self, size: usize) -> Self {
+        self.chunk = vec![0; size.max(1)];
+        self
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// 因损坏或重新同步而丢弃的字节总数
+    pub fn skipped_bytes(&self) -> u64 {
+        self.assembler.skipped_bytes()
+    }
+
+    /// 读取下一个完整的 PDU，对端正常关闭连接时返回 `Ok(None)`
+    pub fn read_pdu(&mut self) -> Result<Option<Pdu>, PduError> {
+        self.read_pdu_with(|_| Ok(()))
+    }
+
+    /// 与 [`read_pdu`](Self::read_pdu) 相同，但每次从连接读取之前先调用 `before_read`，
+    /// 例如按截止时间设置剩余的读取超时；`before_read` 返回错误时停止读取
+    pub fn read_pdu_with(&mut self, mut before_read: impl FnMut(&R) -> io::Result<()>) -> Result<Option<Pdu>, PduError> {
+        loop {
+            // 缓冲区中可能已经有完整的 PDU，先解析再读取
+            if let Some(pdu) = self.assembler.next_pdu()? {
+                return Ok(Some(pdu));
+            }
+
+            before_read(&self.inner)?;
+            match self.inner.read(&mut self.chunk) {
+                Ok(0) if self.assembler.buffered_len() == 0 => return Ok(None),
+                // 连接在 PDU 传输中途关闭
+                Ok(0) => return Err(PduError::Incomplete { needed: self.assembler.missing_bytes()? }),
+                Ok(size) => self.assembler.push(&self.chunk[..size]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for PduReader<R> {
+    type Item = Result<Pdu, PduError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_pdu().transpose()
+    }
+}
+
+/// 向 `Write` 中写入 PDU
+pub struct PduWriter<W> {
+    inner: W,
+    format: FrameFormat,
+}
+
+impl<W: Write> PduWriter<W> {
+    pub fn new(inner: W, format: FrameFormat) -> Self {
+        PduWriter { inner, format }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// 编码并写入一个完整的 PDU，返回写入的字节数
+    pub fn write_pdu(&mut self, pdu: &Pdu) -> Result<usize, PduError> {
+        let vec = pdu.to_vec(&self.format)?;
+        self.inner.write_all(&vec)?;
+        Ok(vec.len())
+    }
+
+    pub fn flush(&mut self) -> Result<(), PduError> {
+        self.inner.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,529 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use serde::de::{DeserializeOwned, IntoDeserializer};
+use serde::Deserialize;
+
+use crate::auth::{self, PskAuth, PskKeys};
+use crate::cli::{self, FrameArgs, ServerArgs};
+use crate::log::{self, Level};
+use crate::network_handler::{AccessList, Checksum, Compression, CorruptPolicy, FrameFormat, LengthFormat, Version};
+use crate::server::{Model, ServerBuilder, Settings};
+use crate::tls;
+
+/// 配置文件路径的环境变量，`--config` 优先
+pub const ENV_CONFIG: &str = "SOCKET_CONFIG";
+/// 日志级别的环境变量
+pub const ENV_LOG_LEVEL: &str = "SOCKET_LOG_LEVEL";
+/// 监听地址的环境变量，多个地址用逗号分隔
+pub const ENV_LISTEN: &str = "SOCKET_LISTEN";
+pub const ENV_PORT: &str = "SOCKET_PORT";
+pub const ENV_BACKLOG: &str = "SOCKET_BACKLOG";
+pub const ENV_MODEL: &str = "SOCKET_MODEL";
+pub const ENV_WORKERS: &str = "SOCKET_WORKERS";
+pub const ENV_QUEUE_DEPTH: &str = "SOCKET_QUEUE_DEPTH";
+pub const ENV_MAX_CONNECTIONS: &str = "SOCKET_MAX_CONNECTIONS";
+pub const ENV_MAX_PAYLOAD: &str = "SOCKET_MAX_PAYLOAD";
+pub const ENV_READ_BUFFER: &str = "SOCKET_READ_BUFFER";
+pub const ENV_PROTOCOL_VERSION: &str = "SOCKET_PROTOCOL_VERSION";
+pub const ENV_LENGTH_FORMAT: &str = "SOCKET_LENGTH_FORMAT";
+pub const ENV_CHECKSUM: &str = "SOCKET_CHECKSUM";
+pub const ENV_ON_CORRUPT: &str = "SOCKET_ON_CORRUPT";
+pub const ENV_COMPRESSION: &str = "SOCKET_COMPRESSION";
+pub const ENV_COMPRESS_THRESHOLD: &str = "SOCKET_COMPRESS_THRESHOLD";
+
+/// `Config::apply_env` 读取的全部环境变量
+pub const ENV_OVERRIDES: &[&str] = &[
+    tls::ENV_TLS_CERT,
+    tls::ENV_TLS_KEY,
+    tls::ENV_TLS_CLIENT_CA,
+    auth::ENV_PSK_FILE,
+    auth::ENV_PSK_TIMEOUT,
+    ENV_LOG_LEVEL,
+    ENV_LISTEN,
+    ENV_PORT,
+    ENV_BACKLOG,
+    ENV_MODEL,
+    ENV_WORKERS,
+    ENV_QUEUE_DEPTH,
+    ENV_MAX_CONNECTIONS,
+    ENV_MAX_PAYLOAD,
+    ENV_READ_BUFFER,
+    ENV_PROTOCOL_VERSION,
+    ENV_LENGTH_FORMAT,
+    ENV_CHECKSUM,
+    ENV_ON_CORRUPT,
+    ENV_COMPRESSION,
+    ENV_COMPRESS_THRESHOLD,
+];
+
+/// 加载或校验配置失败的原因
+#[derive(Debug)]
+pub enum ConfigError {
+    /// 读取配置文件或其中引用的证书、密钥文件失败
+    Io(PathBuf, io::Error),
+    /// TOML 语法错误、未知的键或类型不符
+    Parse(String),
+    /// 取值不合法
+    Invalid(String),
+    /// 证书与私钥都能读取，但无法组成 TLS 配置，例如两者不匹配
+    Tls(io::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "无法读取 {}: {}", path.display(), e),
+            ConfigError::Parse(e) => write!(f, "配置文件格式错误: {}", e),
+            ConfigError::Invalid(e) => write!(f, "配置无效: {}", e),
+            ConfigError::Tls(e) => write!(f, "TLS 配置无效: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io(_, e) | ConfigError::Tls(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// `[server]`：监听地址与并发模型
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSection {
+    /// 监听地址，写法与 `--bind` 相同
+    pub listen: Vec<String>,
+    /// 地址中没有端口时使用的端口
+    pub port: Option<u16>,
+    pub backlog: Option<i32>,
+    /// 未设置时使用各服务器程序自己的模型
+    pub model: Option<Model>,
+    /// 线程池模型的工作线程数或预派生模型的工作进程数
+    pub workers: Option<usize>,
+    /// 线程池模型中排队等待的连接数上限
+    pub queue_depth: Option<usize>,
+    /// 每个工作线程或工作进程打开自己的 SO_REUSEPORT 监听器
+    pub reuse_port: Option<bool>,
+}
+
+/// `[limits]`，收到 SIGHUP 时重新加载
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsSection {
+    pub max_connections: Option<usize>,
+    /// 单个 PDU 的最大 payload 字节数
+    pub max_payload: Option<usize>,
+    /// 每个连接每次读取的字节数
+    pub read_buffer: Option<usize>,
+}
+
+/// `[protocol]`：PDU 帧格式，客户端必须使用相同的设置，收到 SIGHUP 时重新加载
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProtocolSection {
+    /// v1 为兼容旧客户端的 `[len][payload]` 格式，默认 v2
+    pub version: Option<Version>,
+    /// 长度字段的编码方式，默认 u32
+    pub length_format: Option<LengthFormat>,
+    /// 发送的 PDU 使用的校验和算法（仅 v2），默认 none；接收时按每个 PDU 的 flags 校验
+    pub checksum: Option<Checksum>,
+    /// 收到损坏 PDU 时的处理方式，默认 disconnect；resync 需要同时设置 checksum
+    pub on_corrupt: Option<CorruptPolicy>,
+    /// 协商压缩时的首选算法（仅 v2），默认 none，即按客户端提供的顺序选择
+    pub compression: Option<Compression>,
+    /// payload 不短于该字节数时才压缩，默认 256
+    pub compress_threshold: Option<usize>,
+}
+
+/// `[timeouts]`，单位为秒
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutsSection {
+    /// 预共享密钥认证的超时
+    pub auth_secs: Option<u64>,
+    /// 收到 SIGTERM 后等待连接处理完当前请求的时间，0 表示立即关闭
+    pub drain_secs: Option<u64>,
+}
+
+/// `[tls]`：PEM 文件路径
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsSection {
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    /// 设置后要求客户端出示由该 CA 签发的证书
+    pub client_ca: Option<PathBuf>,
+}
+
+/// `[auth]`
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthSection {
+    /// 预共享密钥文件，设置后要求客户端先完成认证
+    pub psk_file: Option<PathBuf>,
+}
+
+/// `[access]`：按客户端证书身份接受或拒绝连接，收到 SIGHUP 时重新加载
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccessSection {
+    /// 非空时只接受其中的身份
+    pub allow: Vec<String>,
+    /// 优先于 allow
+    pub deny: Vec<String>,
+}
+
+/// `[log]`，收到 SIGHUP 时重新加载
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogSection {
+    pub level: Option<Level>,
+}
+
+/// 服务器配置
+///
+/// 每一项的取值按以下优先级决定：命令行参数 > 环境变量 > 配置文件 > 默认值。
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerSection,
+    pub limits: LimitsSection,
+    pub protocol: ProtocolSection,
+    pub timeouts: TimeoutsSection,
+    pub tls: TlsSection,
+    pub auth: AuthSection,
+    pub access: AccessSection,
+    pub log: LogSection,
+}
+
+fn invalid(message: String) -> ConfigError {
+    ConfigError::Invalid(message)
+}
+
+/// 读取并解析环境变量，未设置时返回 None
+fn env_value<T: FromStr>(name: &str) -> Result<Option<T>, ConfigError> {
+    match std::env::var(name) {
+        Ok(value) => value.trim().parse().map(Some).map_err(|_| invalid(format!("{} 的取值不合法: {}", name, value))),
+        Err(_) => Ok(None),
+    }
+}
+
+/// 按配置文件中的写法解析枚举类型的环境变量，例如 `SOCKET_MODEL=thread_pool`
+fn env_enum<T: DeserializeOwned>(name: &str) -> Result<Option<T>, ConfigError> {
+    match std::env::var(name) {
+        Ok(value) => {
+            let parsed = T::deserialize(value.trim().into_deserializer());
+            parsed.map(Some).map_err(|e: serde::de::value::Error| invalid(format!("{}: {}", name, e)))
+        }
+        Err(_) => Ok(None),
+    }
+}
+
+impl Config {
+    /// 解析 TOML 文本，未知的键与不合法的取值都会报错
+    pub fn parse(text: &str) -> Result<Self, ConfigError> {
+        let config: Config = toml::from_str(text).map_err(|e| ConfigError::Parse(e.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+        Config::parse(&text).map_err(|e| match e {
+            ConfigError::Parse(e) => ConfigError::Parse(format!("{}: {}", path.display(), e)),
+            e => e,
+        })
+    }
+
+    /// 检查取值范围以及相互依赖的设置
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.server.listen.iter().any(|addr| addr.trim().is_empty()) {
+            return Err(invalid("server.listen 中有空地址".to_string()));
+        }
+        if let Some(backlog) = self.server.backlog.filter(|backlog| *backlog <= 0) {
+            return Err(invalid(format!("server.backlog 必须大于 0，实际为 {}", backlog)));
+        }
+        if self.server.workers == Some(0) {
+            return Err(invalid("server.workers 必须大于 0".to_string()));
+        }
+        if self.limits.max_connections == Some(0) {
+            return Err(invalid("limits.max_connections 必须大于 0".to_string()));
+        }
+        if let Some(max) = self.limits.max_payload {
+            let length = self.protocol.length_format.unwrap_or(FrameFormat::default().length);
+            if max == 0 || max > length.max_len() {
+                return Err(invalid(format!(
+                    "limits.max_payload 必须在 1 到 {} 之间（长度字段为 {:?}），实际为 {}",
+                    length.max_len(),
+                    length,
+                    max
+                )));
+            }
+        }
+        if self.protocol.version == Some(Version::V1) && self.protocol.checksum.is_some_and(|c| c != Checksum::None) {
+            return Err(invalid("校验和需要 v2 协议，不能与 protocol.version = \"v1\" 一起使用".to_string()));
+        }
+        if self.protocol.on_corrupt == Some(CorruptPolicy::Resync) && self.protocol.checksum.unwrap_or(Checksum::None) == Checksum::None {
+            return Err(invalid("protocol.on_corrupt = \"resync\" 需要同时设置 protocol.checksum".to_string()));
+        }
+        if let Some(compression) = self.protocol.compression.filter(|c| *c != Compression::None) {
+            if self.protocol.version == Some(Version::V1) {
+                return Err(invalid("压缩需要 v2 协议，不能与 protocol.version = \"v1\" 一起使用".to_string()));
+            }
+            if !compression.is_supported() {
+                return Err(invalid(format!("protocol.compression = \"{}\" 在编译时没有启用", compression.name())));
+            }
+        }
+        if self.protocol.version == Some(Version::V1) && self.auth.psk_file.is_some() {
+            return Err(invalid("预共享密钥认证需要 v2 协议，不能与 protocol.version = \"v1\" 一起使用".to_string()));
+        }
+        if self.limits.read_buffer == Some(0) {
+            return Err(invalid("limits.read_buffer 必须大于 0".to_string()));
+        }
+        if self.timeouts.auth_secs == Some(0) {
+            return Err(invalid("timeouts.auth_secs 必须大于 0".to_string()));
+        }
+        match (&self.tls.cert, &self.tls.key) {
+            (Some(_), None) => return Err(invalid("设置了 tls.cert 但没有设置 tls.key".to_string())),
+            (None, Some(_)) => return Err(invalid("设置了 tls.key 但没有设置 tls.cert".to_string())),
+            _ => {}
+        }
+        if self.tls.client_ca.is_some() && self.tls.cert.is_none() {
+            return Err(invalid("tls.client_ca 需要同时设置服务器证书".to_string()));
+        }
+        if !(self.access.allow.is_empty() && self.access.deny.is_empty()) && self.tls.client_ca.is_none() {
+            return Err(invalid("access 需要同时设置 tls.client_ca 以验证客户端证书".to_string()));
+        }
+        Ok(())
+    }
+
+    /// 用环境变量覆盖配置文件中的值
+    pub fn apply_env(&mut self) -> Result<(), ConfigError> {
+        let path = |name| std::env::var_os(name).map(PathBuf::from);
+        if let Some(cert) = path(tls::ENV_TLS_CERT) {
+            self.tls.cert = Some(cert);
+        }
+        if let Some(key) = path(tls::ENV_TLS_KEY) {
+            self.tls.key = Some(key);
+        }
+        if let Some(client_ca) = path(tls::ENV_TLS_CLIENT_CA) {
+            self.tls.client_ca = Some(client_ca);
+        }
+        if let Some(psk_file) = path(auth::ENV_PSK_FILE) {
+            self.auth.psk_file = Some(psk_file);
+        }
+        if let Ok(timeout) = std::env::var(auth::ENV_PSK_TIMEOUT) {
+            let secs = timeout.parse().map_err(|_| invalid(format!("{} 必须是秒数: {}", auth::ENV_PSK_TIMEOUT, timeout)))?;
+            self.timeouts.auth_secs = Some(secs);
+        }
+        if let Ok(level) = std::env::var(ENV_LOG_LEVEL) {
+            self.log.level = Some(level.parse().map_err(|e| invalid(format!("{}: {}", ENV_LOG_LEVEL, e)))?);
+        }
+        if let Ok(listen) = std::env::var(ENV_LISTEN) {
+            self.server.listen = listen.split(',').map(|addr| addr.trim().to_string()).collect();
+        }
+        if let Some(port) = env_value(ENV_PORT)? {
+            self.server.port = Some(port);
+        }
+        if let Some(backlog) = env_value(ENV_BACKLOG)? {
+            self.server.backlog = Some(backlog);
+        }
+        if let Some(model) = env_enum(ENV_MODEL)? {
+            self.server.model = Some(model);
+        }
+        if let Some(workers) = env_value(ENV_WORKERS)? {
+            self.server.workers = Some(workers);
+        }
+        if let Some(depth) = env_value(ENV_QUEUE_DEPTH)? {
+            self.server.queue_depth = Some(depth);
+        }
+        if let Some(max) = env_value(ENV_MAX_CONNECTIONS)? {
+            self.limits.max_connections = Some(max);
+        }
+        if let Some(max) = env_value(ENV_MAX_PAYLOAD)? {
+            self.limits.max_payload = Some(max);
+        }
+        if let Some(size) = env_value(ENV_READ_BUFFER)? {
+            self.limits.read_buffer = Some(size);
+        }
+        if let Some(version) = env_enum(ENV_PROTOCOL_VERSION)? {
+            self.protocol.version = Some(version);
+        }
+        if let Some(length) = env_enum(ENV_LENGTH_FORMAT)? {
+            self.protocol.length_format = Some(length);
+        }
+        if let Some(checksum) = env_enum(ENV_CHECKSUM)? {
+            self.protocol.checksum = Some(checksum);
+        }
+        if let Some(policy) = env_enum(ENV_ON_CORRUPT)? {
+            self.protocol.on_corrupt = Some(policy);
+        }
+        if let Some(compression) = env_enum(ENV_COMPRESSION)? {
+            self.protocol.compression = Some(compression);
+        }
+        if let Some(threshold) = env_value(ENV_COMPRESS_THRESHOLD)? {
+            self.protocol.compress_threshold = Some(threshold);
+        }
+        Ok(())
+    }
+
+    /// 用命令行参数覆盖环境变量与配置文件中的值
+    pub fn apply_args(&mut self, args: &ServerArgs) {
+        if !args.listen.bind.is_empty() {
+            self.server.listen = args.listen.bind.clone();
+        }
+        if let Some(port) = args.listen.port {
+            self.server.port = Some(port);
+        }
+        if let Some(backlog) = args.listen.backlog {
+            self.server.backlog = Some(backlog);
+        }
+        self.apply_frame_args(&args.frame);
+        if let Some(workers) = args.workers {
+            self.server.workers = Some(workers);
+        }
+        if let Some(depth) = args.queue_depth {
+            self.server.queue_depth = Some(depth);
+        }
+        if args.reuse_port {
+            self.server.reuse_port = Some(true);
+        }
+        if let Some(level) = args.log_level {
+            self.log.level = Some(level);
+        }
+    }
+
+    /// 用帧格式参数覆盖 `[protocol]` 与 `limits.max_payload`
+    pub fn apply_frame_args(&mut self, args: &FrameArgs) {
+        if let Some(version) = args.protocol_version {
+            self.protocol.version = Some(version);
+        }
+        if let Some(length) = args.length_format {
+            self.protocol.length_format = Some(length);
+        }
+        if let Some(max) = args.max_payload {
+            self.limits.max_payload = Some(max);
+        }
+        if let Some(checksum) = args.checksum {
+            self.protocol.checksum = Some(checksum);
+        }
+        if let Some(policy) = args.on_corrupt {
+            self.protocol.on_corrupt = Some(policy);
+        }
+        if let Some(compression) = args.compression {
+            self.protocol.compression = Some(compression);
+        }
+        if let Some(threshold) = args.compress_threshold {
+            self.protocol.compress_threshold = Some(threshold);
+        }
+    }
+
+    /// 补全端口后的监听地址
+    pub fn addrs(&self) -> Vec<String> {
+        cli::listen_addrs(&self.server.listen, self.server.port)
+    }
+
+    pub fn frame_format(&self) -> FrameFormat {
+        let default = FrameFormat::default();
+        let length = self.protocol.length_format.unwrap_or(default.length);
+        let max_payload = self.limits.max_payload.unwrap_or(default.max_payload);
+        let format = match self.protocol.version.unwrap_or(default.version) {
+            Version::V1 => FrameFormat::legacy(length, max_payload),
+            Version::V2 => FrameFormat::new(length, max_payload),
+        };
+        format
+            .with_checksum(self.protocol.checksum.unwrap_or(default.checksum))
+            .on_corrupt(self.protocol.on_corrupt.unwrap_or(default.on_corrupt))
+            .with_compression(self.protocol.compression.unwrap_or(default.compression))
+            .compress_threshold(self.protocol.compress_threshold.unwrap_or(default.compress_threshold))
+    }
+
+    /// 加载证书与私钥，未配置时返回 None
+    pub fn tls_config(&self) -> Result<Option<Arc<rustls::ServerConfig>>, ConfigError> {
+        let (Some(cert), Some(key)) = (&self.tls.cert, &self.tls.key) else {
+            return Ok(None);
+        };
+        let certs = tls::load_certs(cert).map_err(|e| ConfigError::Io(cert.clone(), e))?;
+        let key = tls::load_private_key(key).map_err(|e| ConfigError::Io(key.clone(), e))?;
+        let client_roots = match &self.tls.client_ca {
+            Some(client_ca) => Some(tls::root_store(client_ca).map_err(|e| ConfigError::Io(client_ca.clone(), e))?),
+            None => None,
+        };
+        tls::build_server_config(certs, key, client_roots).map(Some).map_err(ConfigError::Tls)
+    }
+
+    /// 加载预共享密钥，未配置时返回 None
+    pub fn psk(&self) -> Result<Option<PskAuth>, ConfigError> {
+        let Some(path) = &self.auth.psk_file else {
+            return Ok(None);
+        };
+        let keys = PskKeys::load(path).map_err(|e| ConfigError::Io(path.clone(), e))?;
+        if keys.is_empty() {
+            return Err(invalid(format!("密钥文件 {} 中没有密钥", path.display())));
+        }
+        let mut auth = PskAuth::new(keys);
+        if let Some(secs) = self.timeouts.auth_secs {
+            auth = auth.timeout(Duration::from_secs(secs));
+        }
+        Ok(Some(auth))
+    }
+
+    /// 运行期间可以更新的设置，未配置日志级别时恢复为默认级别
+    pub fn settings(&self) -> Result<Settings, ConfigError> {
+        let mut settings = Settings {
+            max_connections: self.limits.max_connections,
+            format: self.frame_format(),
+            psk: self.psk()?.map(Arc::new),
+            access: AccessList {
+                allow: self.access.allow.clone(),
+                deny: self.access.deny.clone(),
+            },
+            log_level: Some(self.log.level.unwrap_or(log::DEFAULT_LEVEL)),
+            ..Settings::default()
+        };
+        if let Some(size) = self.limits.read_buffer {
+            settings.read_buffer = size;
+        }
+        if let Some(secs) = self.timeouts.drain_secs {
+            settings.drain_timeout = Duration::from_secs(secs);
+        }
+        Ok(settings)
+    }
+
+    /// 设置日志级别，并按配置创建服务器构建器；配置中没有指定模型时使用 `default_model`
+    pub fn builder(&self, default_model: Model) -> Result<ServerBuilder, ConfigError> {
+        let settings = self.settings()?;
+        if let Some(level) = settings.log_level {
+            log::set_level(level);
+        }
+        let mut builder = self
+            .addrs()
+            .into_iter()
+            .fold(ServerBuilder::default(), ServerBuilder::bind)
+            .model(self.server.model.unwrap_or(default_model))
+            .settings(settings);
+        if let Some(backlog) = self.server.backlog {
+            builder = builder.backlog(backlog);
+        }
+        if let Some(workers) = self.server.workers {
+            builder = builder.workers(workers);
+        }
+        if let Some(depth) = self.server.queue_depth {
+            builder = builder.queue_depth(depth);
+        }
+        if let Some(reuse_port) = self.server.reuse_port {
+            builder = builder.reuse_port(reuse_port);
+        }
+        if let Some(config) = self.tls_config()? {
+            builder = builder.tls(config);
+        }
+        Ok(builder)
+    }
+}
@@ -1 +1,13 @@
-pub mod network_handler;
\ No newline at end of file
+pub mod auth;
+pub mod cli;
+pub mod codec;
+pub mod config;
+pub mod log;
+pub mod network_handler;
+pub mod reactor;
+pub mod server;
+pub mod systemd;
+pub mod tls;
+pub mod upgrade;
+#[cfg(feature = "io-uring")]
+pub mod uring;
@@ -1,249 +1,1342 @@
 use std::fmt;
-use std::io::{Read, Write};
+use std::future::Future;
+use std::io::{self, Read, Write};
 use std::net::{SocketAddr, TcpStream};
-use std::thread::ThreadId;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::Framed;
 
-const MAX_PAYLOAD_LEN: usize = 255;
-const BUFFER_SIZE: usize = 1024;
+use crate::auth::{self, AuthError, PskAuth};
+use crate::codec::{FrameAssembler, PduReader, PduWriter, RecoverableCodec, DEFAULT_READ_BUFFER};
+use crate::tls::PeerIdentity;
+use crate::{log_debug, log_info};
+
+/// PDU 头部中长度字段的编码方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LengthFormat {
+    /// 1 字节，最多 255 字节 payload（最初的协议格式）
+    U8,
+    /// 2 字节大端序
+    U16,
+    /// 4 字节大端序
+    U32,
+    /// LEB128 变长编码，1~5 字节
+    Varint,
+}
+
+impl LengthFormat {
+    /// 该编码方式能够表示的最大长度
+    pub fn max_len(self) -> usize {
+        match self {
+            LengthFormat::U8 => u8::MAX as usize,
+            LengthFormat::U16 => u16::MAX as usize,
+            LengthFormat::U32 | LengthFormat::Varint => u32::MAX as usize,
+        }
+    }
+
+    fn encode(self, len: usize, out: &mut Vec<u8>) {
+        match self {
+            LengthFormat::U8 => out.push(len as u8),
+            LengthFormat::U16 => out.extend_from_slice(&(len as u16).to_be_bytes()),
+            LengthFormat::U32 => out.extend_from_slice(&(len as u32).to_be_bytes()),
+            LengthFormat::Varint => {
+                let mut value = len as u32;
+                loop {
+                    let byte = (value & 0x7f) as u8;
+                    value >>= 7;
+                    if value == 0 {
+                        out.push(byte);
+                        break;
+                    }
+                    out.push(byte | 0x80);
+                }
+            }
+        }
+    }
+
+    /// 解析长度字段，返回 (长度字段字节数, payload 长度)；数据不足时返回 None
+    fn decode(self, buffer: &[u8]) -> Option<(usize, usize)> {
+        match self {
+            LengthFormat::U8 => buffer.first().map(|len| (1, *len as usize)),
+            LengthFormat::U16 => {
+                let bytes: [u8; 2] = buffer.get(..2)?.try_into().ok()?;
+                Some((2, u16::from_be_bytes(bytes) as usize))
+            }
+            LengthFormat::U32 => {
+                let bytes: [u8; 4] = buffer.get(..4)?.try_into().ok()?;
+                Some((4, u32::from_be_bytes(bytes) as usize))
+            }
+            LengthFormat::Varint => {
+                let mut value: u64 = 0;
+                // u32 最多需要 5 个字节，超出部分视为超长，由调用方按最大长度拒绝
+                for (i, byte) in buffer.iter().take(5).enumerate() {
+                    value |= ((byte & 0x7f) as u64) << (7 * i);
+                    if byte & 0x80 == 0 {
+                        return Some((i + 1, value as usize));
+                    }
+                }
+                if buffer.len() >= 5 {
+                    return Some((5, usize::MAX));
+                }
+                None
+            }
+        }
+    }
+}
+
+/// 协议版本
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Version {
+    /// 兼容模式：最初的 `[len][payload]` 格式，没有消息类型和请求ID
+    V1,
+    /// `[magic|version][type][flags][request_id][len][payload]`
+    V2,
+}
+
+/// v2 头部第一个字节的高 4 位固定为魔数，低 4 位为版本号
+const PDU_MAGIC: u8 = 0xA0;
+const PDU_VERSION_2: u8 = 2;
+/// v2 头部中长度字段之前的固定部分：magic/version、type、flags、request_id
+const V2_FIXED_HEADER_LEN: usize = 1 + 1 + 1 + 4;
+
+/// PDU 的帧格式，客户端与服务器必须使用相同的配置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameFormat {
+    /// 协议版本
+    pub version: Version,
+    /// 长度字段的编码方式
+    pub length: LengthFormat,
+    /// 允许的最大 payload 长度，不会超过长度字段能表示的范围
+    pub max_payload: usize,
+    /// 新建 PDU 默认使用的校验和算法（仅 v2），接收时按每个 PDU 的 flags 校验
+    pub checksum: Checksum,
+    /// 收到校验和错误的 PDU 时的处理方式
+    pub on_corrupt: CorruptPolicy,
+    /// 新建 PDU 默认使用的压缩算法（仅 v2），服务器按连接协商的结果设置
+    pub compression: Compression,
+    /// payload 短于该长度时不压缩
+    pub compress_threshold: usize,
+}
+
+/// PDU 尾部的校验和算法，由 flags 的低 2 位表示
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Checksum {
+    None,
+    /// CRC32C (Castagnoli)
+    Crc32c,
+    /// xxHash32，seed 为 0
+    #[serde(rename = "xxh32")]
+    #[value(name = "xxh32")]
+    XxHash32,
+}
+
+/// flags 中表示校验和算法的位
+pub const FLAG_CHECKSUM_MASK: u8 = 0x03;
+/// 校验和尾部的长度
+const CHECKSUM_LEN: usize = 4;
+
+impl Checksum {
+    pub fn from_flags(flags: u8) -> Result<Self, PduError> {
+        match flags & FLAG_CHECKSUM_MASK {
+            0 => Ok(Checksum::None),
+            1 => Ok(Checksum::Crc32c),
+            2 => Ok(Checksum::XxHash32),
+            _ => Err(PduError::UnsupportedFlags(flags)),
+        }
+    }
+
+    pub fn flag(self) -> u8 {
+        match self {
+            Checksum::None => 0,
+            Checksum::Crc32c => 1,
+            Checksum::XxHash32 => 2,
+        }
+    }
+
+    fn trailer_len(self) -> usize {
+        match self {
+            Checksum::None => 0,
+            _ => CHECKSUM_LEN,
+        }
+    }
+
+    fn compute(self, data: &[u8]) -> u32 {
+        match self {
+            Checksum::None => 0,
+            Checksum::Crc32c => crc32c::crc32c(data),
+            Checksum::XxHash32 => xxhash_rust::xxh32::xxh32(data, 0),
+        }
+    }
+}
+
+/// payload 的压缩算法，由 flags 的第 2、3 位表示
+///
+/// zstd 与 lz4 分别由同名的 cargo feature 启用，未启用的算法在收到时按不支持的标志位处理。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    Zstd,
+    Lz4,
+}
+
+/// flags 中表示压缩算法的位
+pub const FLAG_COMPRESSION_MASK: u8 = 0x0C;
+/// 压缩协商控制消息的 payload 前缀，后跟逗号分隔的算法名，按偏好排序
+pub const COMPRESSION_HANDSHAKE: &str = "compression:";
+/// 服务器优雅关闭时通知客户端的控制消息，之后的请求不会再被处理
+pub const GOING_AWAY: &str = "going-away";
+/// 服务器过载、没有处理连接的空闲容量时发送的控制消息，之后连接被关闭
+pub const SERVER_BUSY: &str = "server-busy";
+/// 默认的压缩阈值
+const DEFAULT_COMPRESS_THRESHOLD: usize = 256;
+
+impl Compression {
+    pub fn from_flags(flags: u8) -> Result<Self, PduError> {
+        let compression = match (flags & FLAG_COMPRESSION_MASK) >> 2 {
+            0 => Compression::None,
+            1 => Compression::Zstd,
+            2 => Compression::Lz4,
+            _ => return Err(PduError::UnsupportedFlags(flags)),
+        };
+        if !compression.is_supported() {
+            return Err(PduError::UnsupportedFlags(flags));
+        }
+        Ok(compression)
+    }
+
+    pub fn flag(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Zstd => 1 << 2,
+            Compression::Lz4 => 2 << 2,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Zstd => "zstd",
+            Compression::Lz4 => "lz4",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(Compression::None),
+            "zstd" => Some(Compression::Zstd),
+            "lz4" => Some(Compression::Lz4),
+            _ => None,
+        }
+    }
+
+    /// 编译时是否启用了该算法
+    pub fn is_supported(self) -> bool {
+        match self {
+            Compression::None => true,
+            Compression::Zstd => cfg!(feature = "zstd"),
+            Compression::Lz4 => cfg!(feature = "lz4"),
+        }
+    }
+
+    /// 本端支持的所有压缩算法，不含 None
+    pub fn supported() -> Vec<Compression> {
+        [Compression::Zstd, Compression::Lz4].into_iter().filter(|c| c.is_supported()).collect()
+    }
+
+    /// 从对端提供的算法中选出本端也支持的，preferred 排在最前，其余保持对端的顺序
+    pub fn negotiate(preferred: Compression, offered: &[Compression]) -> Vec<Compression> {
+        let mut accepted = Vec::new();
+        for compression in offered {
+            if *compression != Compression::None && compression.is_supported() && !accepted.contains(compression) {
+                accepted.push(*compression);
+            }
+        }
+        if let Some(i) = accepted.iter().position(|c| *c == preferred) {
+            accepted[..=i].rotate_right(1);
+        }
+        accepted
+    }
+
+    /// 构造压缩协商消息的 payload
+    pub fn handshake_payload(algorithms: &[Compression]) -> Vec<u8> {
+        let names: Vec<&str> = algorithms.iter().map(|c| c.name()).collect();
+        format!("{}{}", COMPRESSION_HANDSHAKE, names.join(",")).into_bytes()
+    }
+
+    /// 解析压缩协商消息，不是协商消息时返回 None；未知的算法名会被忽略
+    pub fn parse_handshake(pdu: &Pdu) -> Option<Vec<Compression>> {
+        if pdu.kind != MsgType::Control {
+            return None;
+        }
+        let list = std::str::from_utf8(&pdu.payload).ok()?.strip_prefix(COMPRESSION_HANDSHAKE)?;
+        Some(list.split(',').filter_map(|name| Compression::from_name(name.trim())).collect())
+    }
+
+    fn compress(self, data: &[u8]) -> Result<Vec<u8>, PduError> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => zstd::bulk::compress(data, 0).map_err(|e| PduError::Compression(e.to_string())),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+            #[allow(unreachable_patterns)]
+            _ => Err(PduError::Compression(format!("未启用 {} 压缩", self.name()))),
+        }
+    }
+
+    /// 解压 payload，解压后的长度不能超过 max_len
+    #[cfg_attr(not(any(feature = "zstd", feature = "lz4")), allow(unused_variables))]
+    fn decompress(self, data: &[u8], max_len: usize) -> Result<Vec<u8>, PduError> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => zstd::bulk::decompress(data, max_len).map_err(|e| PduError::Compression(e.to_string())),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => {
+                // 开头 4 字节为小端序的原始长度，先检查再分配内存
+                let Some((size, data)) = data.split_first_chunk::<4>() else {
+                    return Err(PduError::Compression("lz4 数据缺少长度前缀".to_string()));
+                };
+                let size = u32::from_le_bytes(*size) as usize;
+                if size > max_len {
+                    return Err(PduError::Oversize { len: size, max: max_len });
+                }
+                lz4_flex::decompress(data, size).map_err(|e| PduError::Compression(e.to_string()))
+            }
+            #[allow(unreachable_patterns)]
+            _ => Err(PduError::Compression(format!("未启用 {} 压缩", self.name()))),
+        }
+    }
+}
+
+/// 收到损坏 PDU 时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum CorruptPolicy {
+    /// 回复错误 PDU 后断开连接
+    Disconnect,
+    /// 校验和不匹配时回复错误 PDU，按头部声明的长度丢弃该 PDU 后继续处理后续数据
+    ///
+    /// 长度字段本身损坏时后续数据仍会错位。
+    SkipFrame,
+    /// 校验和不匹配或头部非法时回复错误 PDU，然后向后逐字节查找下一个 v2 魔数/版本字节（仅用于 v2），
+    /// 直到找到一个带校验和且校验通过的 PDU 后恢复正常解析
+    ///
+    /// 重新同步期间不带校验和的 PDU 都会被丢弃，因此发送方应当启用校验和。
+    /// 若候选位置的长度字段恰好合法，会先等待足够的数据到达再校验。
+    Resync,
+}
+
+/// 重新同步时，buffer[1..] 中下一个可能的 v2 帧起点；找不到时返回 buffer 的长度
+pub(crate) fn next_sync_offset(buffer: &[u8]) -> usize {
+    buffer.iter()
+        .skip(1)
+        .position(|byte| *byte == PDU_MAGIC | PDU_VERSION_2)
+        .map_or(buffer.len(), |i| i + 1)
+}
+
+impl Default for FrameFormat {
+    fn default() -> Self {
+        FrameFormat::new(LengthFormat::U32, 1024 * 1024)
+    }
+}
+
+impl FrameFormat {
+    /// 创建 v2 帧格式
+    pub fn new(length: LengthFormat, max_payload: usize) -> Self {
+        FrameFormat {
+            version: Version::V2,
+            length,
+            max_payload: max_payload.min(length.max_len()),
+            checksum: Checksum::None,
+            on_corrupt: CorruptPolicy::Disconnect,
+            compression: Compression::None,
+            compress_threshold: DEFAULT_COMPRESS_THRESHOLD,
+        }
+    }
+
+    /// 设置新建 PDU 默认使用的校验和算法
+    pub fn with_checksum(mut self, checksum: Checksum) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// 设置新建 PDU 默认使用的压缩算法
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// 设置压缩阈值，payload 短于该长度时不压缩
+    pub fn compress_threshold(mut self, threshold: usize) -> Self {
+        self.compress_threshold = threshold;
+        self
+    }
+
+    /// 设置收到损坏 PDU 时的处理方式
+    pub fn on_corrupt(mut self, policy: CorruptPolicy) -> Self {
+        self.on_corrupt = policy;
+        self
+    }
+
+    /// 按 [`CorruptPolicy`]，解码器遇到此错误后是否已跳过损坏的数据、可以继续解析
+    pub fn recovers_from(&self, e: &PduError) -> bool {
+        match self.on_corrupt {
+            CorruptPolicy::Disconnect => false,
+            CorruptPolicy::SkipFrame => e.is_frame_local(),
+            // v1 没有魔数，无法重新同步
+            CorruptPolicy::Resync if self.version == Version::V1 => e.is_frame_local(),
+            CorruptPolicy::Resync => !matches!(e, PduError::Io(_) | PduError::Incomplete { .. }),
+        }
+    }
+
+    /// 创建兼容旧客户端的 v1 帧格式
+    pub fn legacy(length: LengthFormat, max_payload: usize) -> Self {
+        FrameFormat {
+            version: Version::V1,
+            ..FrameFormat::new(length, max_payload)
+        }
+    }
+
+    /// 头部的最小长度（变长编码按 1 字节计算）
+    fn min_header_len(&self) -> usize {
+        let fixed = match self.version {
+            Version::V1 => 0,
+            Version::V2 => V2_FIXED_HEADER_LEN,
+        };
+        let length = match self.length {
+            LengthFormat::U8 | LengthFormat::Varint => 1,
+            LengthFormat::U16 => 2,
+            LengthFormat::U32 => 4,
+        };
+        fixed + length
+    }
+
+    /// 构成完整 PDU 至少还需要多少字节；缓冲区中已有完整 PDU 时返回 0
+    pub fn missing_bytes(&self, buffer: &[u8]) -> Result<usize, PduError> {
+        Ok(match self.parse_header(buffer)? {
+            Some(header) => header.total_len().saturating_sub(buffer.len()),
+            // 变长编码的长度字段尚未结束时，至少还需要 1 字节
+            None => self.min_header_len().saturating_sub(buffer.len()).max(1),
+        })
+    }
+
+    /// 解析头部；数据不足时返回 `Ok(None)`
+    fn parse_header(&self, buffer: &[u8]) -> Result<Option<Header>, PduError> {
+        let mut header = Header {
+            kind: MsgType::Data,
+            flags: 0,
+            request_id: 0,
+            header_len: 0,
+            payload_len: 0,
+            checksum: Checksum::None,
+            compression: Compression::None,
+        };
+
+        if self.version == Version::V2 {
+            let Some(&magic_version) = buffer.first() else {
+                return Ok(None);
+            };
+            if magic_version & 0xF0 != PDU_MAGIC {
+                return Err(PduError::BadMagic(magic_version));
+            }
+            if magic_version & 0x0F != PDU_VERSION_2 {
+                return Err(PduError::BadVersion(magic_version & 0x0F));
+            }
+            if buffer.len() < V2_FIXED_HEADER_LEN {
+                return Ok(None);
+            }
+            header.kind = MsgType::from_u8(buffer[1]).ok_or(PduError::UnknownType(buffer[1]))?;
+            header.flags = buffer[2];
+            if header.flags & !(FLAG_CHECKSUM_MASK | FLAG_COMPRESSION_MASK) != 0 {
+                return Err(PduError::UnsupportedFlags(header.flags));
+            }
+            header.checksum = Checksum::from_flags(header.flags)?;
+            header.compression = Compression::from_flags(header.flags)?;
+            header.request_id = u32::from_be_bytes(buffer[3..7].try_into().unwrap());
+            header.header_len = V2_FIXED_HEADER_LEN;
+        }
+
+        let Some((len_bytes, payload_len)) = self.length.decode(&buffer[header.header_len..]) else {
+            return Ok(None);
+        };
+        if payload_len > self.max_payload {
+            return Err(PduError::Oversize { len: payload_len, max: self.max_payload });
+        }
+        header.header_len += len_bytes;
+        header.payload_len = payload_len;
+        Ok(Some(header))
+    }
+}
+
+/// PDU 编解码错误
+#[derive(Debug)]
+pub enum PduError {
+    /// payload 长度超过帧格式允许的最大长度
+    Oversize { len: usize, max: usize },
+    /// v2 头部的魔数不正确
+    BadMagic(u8),
+    /// 不支持的协议版本
+    BadVersion(u8),
+    /// 未知的消息类型
+    UnknownType(u8),
+    /// flags 中包含不支持的位
+    UnsupportedFlags(u8),
+    /// 数据不完整，至少还需要 `needed` 字节
+    Incomplete { needed: usize },
+    /// 校验和不匹配
+    ///
+    /// 解码器会按头部声明的长度跳过该 PDU，是否继续处理后续数据由 [`CorruptPolicy`] 决定。
+    BadChecksum { expected: u32, actual: u32 },
+    /// 压缩或解压失败
+    Compression(String),
+    /// 底层 I/O 错误
+    Io(io::Error),
+}
+
+impl fmt::Display for PduError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PduError::Oversize { len, max } => write!(f, "PDU 长度 {} 超过上限 {}", len, max),
+            PduError::BadMagic(magic) => write!(f, "非法的魔数 0x{:02x}", magic),
+            PduError::BadVersion(version) => write!(f, "不支持的协议版本 {}", version),
+            PduError::UnknownType(kind) => write!(f, "未知的消息类型 {}", kind),
+            PduError::UnsupportedFlags(flags) => write!(f, "不支持的标志位 0x{:02x}", flags),
+            PduError::Incomplete { needed } => write!(f, "PDU 不完整，还需要 {} 字节", needed),
+            PduError::BadChecksum { expected, actual } => {
+                write!(f, "校验和不匹配: 期望 0x{:08x}，实际 0x{:08x}", expected, actual)
+            }
+            PduError::Compression(e) => write!(f, "压缩或解压失败: {}", e),
+            PduError::Io(e) => write!(f, "I/O 错误: {}", e),
+        }
+    }
+}
+
+impl PduError {
+    /// 错误是否只影响当前 PDU：头部合法，可以按声明的长度跳过该 PDU
+    pub(crate) fn is_frame_local(&self) -> bool {
+        matches!(self, PduError::BadChecksum { .. } | PduError::Compression(_))
+    }
+}
+
+impl std::error::Error for PduError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PduError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PduError {
+    fn from(e: io::Error) -> Self {
+        PduError::Io(e)
+    }
+}
+
+struct Header {
+    kind: MsgType,
+    flags: u8,
+    request_id: u32,
+    header_len: usize,
+    payload_len: usize,
+    checksum: Checksum,
+    compression: Compression,
+}
+
+impl Header {
+    /// 头部、payload 与校验和尾部的总长度
+    fn total_len(&self) -> usize {
+        self.header_len + self.payload_len + self.checksum.trailer_len()
+    }
+}
+
+/// 消息类型（仅 v2）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MsgType {
+    /// 业务数据
+    Data = 0,
+    /// 控制消息
+    Control = 1,
+    /// 错误消息，payload 为错误描述
+    Error = 2,
+    /// 心跳
+    Heartbeat = 3,
+}
+
+impl MsgType {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(MsgType::Data),
+            1 => Some(MsgType::Control),
+            2 => Some(MsgType::Error),
+            3 => Some(MsgType::Heartbeat),
+            _ => None,
+        }
+    }
+}
 
 pub struct Pdu {
-    /// Payload 长度 (最多255字节)
-    pub length: u8,
+    /// 消息类型，v1 格式下总是 Data
+    pub kind: MsgType,
+    /// 标志位
+    pub flags: u8,
+    /// 请求ID，响应 PDU 使用与请求相同的ID；v1 格式下总是 0
+    pub request_id: u32,
+    /// Payload 长度 (最多为 FrameFormat::max_payload)，压缩时为解压后的长度
+    pub length: u32,
     /// 实际数据内容
     pub payload: Vec<u8>,
 }
 
 impl fmt::Display for Pdu {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "PDU[length={}, payload=\"{}\"]",
+        write!(f, "PDU[type={:?}, flags=0x{:02x}, request_id={}, length={}, payload=\"{}\"]",
+               self.kind,
+               self.flags,
+               self.request_id,
                self.length,
                String::from_utf8_lossy(&self.payload))
     }
 }
 
 impl Pdu {
-    /// 创建新的 PDU 实例
-    pub fn new(data: &[u8]) -> Option<Self> {
-        if data.len() > MAX_PAYLOAD_LEN {
-            // 超过 u8 最大值 MAX_MSG_LEN，无法表示长度
-            return None;
+    /// 创建新的数据 PDU 实例
+    pub fn new(data: &[u8], format: &FrameFormat) -> Result<Self, PduError> {
+        Pdu::with_type(MsgType::Data, 0, data, format)
+    }
+
+    /// 创建指定消息类型和请求ID的 PDU 实例
+    pub fn with_type(kind: MsgType, request_id: u32, data: &[u8], format: &FrameFormat) -> Result<Self, PduError> {
+        if data.len() > format.max_payload {
+            // 超过帧格式允许的最大长度，无法发送
+            return Err(PduError::Oversize { len: data.len(), max: format.max_payload });
         }
 
-        Some(Pdu {
-            length: data.len() as u8,
+        let flags = match format.version {
+            Version::V1 => 0,
+            Version::V2 => format.checksum.flag() | format.compression.flag(),
+        };
+
+        Ok(Pdu {
+            kind,
+            flags,
+            request_id,
+            length: data.len() as u32,
             payload: data.to_vec(),
         })
     }
 
-    pub fn to_vec(&self) -> Vec<u8> {
-        let mut vec = Vec::with_capacity(1 + self.length as usize);
-        vec.push(self.length);
-        vec.extend_from_slice(&self.payload);
-        vec
+    /// 创建错误 PDU，过长的错误描述会被截断
+    pub fn error(request_id: u32, message: &str, format: &FrameFormat) -> Self {
+        let message = &message.as_bytes()[..message.len().min(format.max_payload)];
+        Pdu::with_type(MsgType::Error, request_id, message, format).unwrap()
     }
 
-    pub fn from_bytes(buffer: &[u8]) -> Option<Self> {
-        if buffer.is_empty() {
+    /// 创建通知客户端服务器即将关闭的控制 PDU，v1 格式没有消息类型，返回 None
+    pub fn going_away(format: &FrameFormat) -> Option<Self> {
+        if format.version != Version::V2 {
             return None;
         }
+        Pdu::with_type(MsgType::Control, 0, GOING_AWAY.as_bytes(), format).ok()
+    }
+
+    pub fn is_going_away(&self) -> bool {
+        self.kind == MsgType::Control && self.payload == GOING_AWAY.as_bytes()
+    }
 
-        let length = buffer[0];
-        if buffer.len() < (1 + length as usize) {
-            // 缓冲区长度不足以容纳声明的 payload 长度
+    /// 创建通知客户端服务器繁忙的控制 PDU，v1 格式没有消息类型，返回 None
+    pub fn server_busy(format: &FrameFormat) -> Option<Self> {
+        if format.version != Version::V2 {
             return None;
         }
+        Pdu::with_type(MsgType::Control, 0, SERVER_BUSY.as_bytes(), format).ok()
+    }
 
-        Some(Pdu {
-            length,
-            payload: buffer[1..(1 + length as usize)].to_vec(),
-        })
+    pub fn is_server_busy(&self) -> bool {
+        self.kind == MsgType::Control && self.payload == SERVER_BUSY.as_bytes()
     }
 
-    /// 检查缓冲区是否包含完整的 PDU 数据
-    pub fn is_complete_pdu(buffer: &[u8]) -> bool {
-        if buffer.is_empty() {
-            return false;
+    /// PDU 使用的校验和算法（由 flags 决定）
+    pub fn checksum(&self) -> Result<Checksum, PduError> {
+        Checksum::from_flags(self.flags)
+    }
+
+    /// PDU 使用的压缩算法（由 flags 决定）
+    pub fn compression(&self) -> Result<Compression, PduError> {
+        Compression::from_flags(self.flags)
+    }
+
+    /// 按帧格式编码，payload 超过 `max_payload` 时返回 [`PduError::Oversize`]
+    ///
+    /// v2 格式下若 flags 中设置了压缩算法，payload 不短于 `compress_threshold` 且压缩后更短时才压缩，
+    /// 否则清除压缩标志发送原始数据；若设置了校验和算法，会在末尾追加 4 字节大端序的校验和，覆盖头部与 payload。
+    pub fn to_vec(&self, format: &FrameFormat) -> Result<Vec<u8>, PduError> {
+        if self.payload.len() > format.max_payload {
+            return Err(PduError::Oversize { len: self.payload.len(), max: format.max_payload });
+        }
+
+        let mut compressed = None;
+        let mut checksum = Checksum::None;
+        let mut flags = self.flags;
+        if format.version == Version::V2 {
+            checksum = self.checksum()?;
+            let compression = self.compression()?;
+            if compression != Compression::None && self.payload.len() >= format.compress_threshold {
+                compressed = Some(compression.compress(&self.payload)?).filter(|data| data.len() < self.payload.len());
+            }
+            if compressed.is_none() {
+                flags &= !FLAG_COMPRESSION_MASK;
+            }
         }
+        let payload = compressed.as_deref().unwrap_or(&self.payload);
 
-        buffer.len() >= (1 + buffer[0] as usize)
+        let mut vec = Vec::with_capacity(V2_FIXED_HEADER_LEN + 5 + payload.len() + CHECKSUM_LEN);
+        if format.version == Version::V2 {
+            vec.push(PDU_MAGIC | PDU_VERSION_2);
+            vec.push(self.kind as u8);
+            vec.push(flags);
+            vec.extend_from_slice(&self.request_id.to_be_bytes());
+        }
+        format.length.encode(payload.len(), &mut vec);
+        vec.extend_from_slice(payload);
+        if checksum != Checksum::None {
+            let value = checksum.compute(&vec);
+            vec.extend_from_slice(&value.to_be_bytes());
+        }
+        Ok(vec)
+    }
+
+    /// 从缓冲区开头解码一个完整的 PDU
+    ///
+    /// 缓冲区长度不足以容纳声明的 payload 长度时返回 [`PduError::Incomplete`]。
+    pub fn from_bytes(buffer: &[u8], format: &FrameFormat) -> Result<Self, PduError> {
+        match Pdu::decode(buffer, format)? {
+            Some((pdu, _)) => Ok(pdu),
+            None => Err(PduError::Incomplete { needed: format.missing_bytes(buffer)? }),
+        }
+    }
+
+    /// 检查缓冲区是否包含完整的 PDU 数据
+    pub fn is_complete_pdu(buffer: &[u8], format: &FrameFormat) -> bool {
+        match Pdu::payload_size(buffer, format) {
+            Some(size) => buffer.len() >= size,
+            None => false,
+        }
     }
 
     /// 获取完整 PDU 所需的总字节数（包括头部）
-    pub fn payload_size(buffer: &[u8]) -> Option<usize> {
-        if buffer.is_empty() {
+    ///
+    /// 头部尚不完整或非法时返回 None，可以用 [`Pdu::validate_header`] 区分这两种情况。
+    pub fn payload_size(buffer: &[u8], format: &FrameFormat) -> Option<usize> {
+        format.parse_header(buffer).ok()?.map(|header| header.total_len())
+    }
+
+    /// 检查缓冲区开头的头部是否合法（魔数、版本、消息类型、长度上限）
+    ///
+    /// 头部尚不完整时视为合法。
+    pub fn validate_header(buffer: &[u8], format: &FrameFormat) -> Result<(), PduError> {
+        format.parse_header(buffer).map(|_| ())
+    }
+
+    /// 从缓冲区开头解码一个 PDU，返回 PDU 及其占用的字节数
+    ///
+    /// 数据不完整时返回 `Ok(None)`；这是 `PduReader` 和 `PduCodec` 共用的解析逻辑。
+    pub fn decode(buffer: &[u8], format: &FrameFormat) -> Result<Option<(Pdu, usize)>, PduError> {
+        let Some(header) = format.parse_header(buffer)? else {
+            return Ok(None);
+        };
+        let total_len = header.total_len();
+        if buffer.len() < total_len {
+            return Ok(None);
+        }
+
+        let payload_end = header.header_len + header.payload_len;
+        if header.checksum != Checksum::None {
+            let expected = u32::from_be_bytes(buffer[payload_end..total_len].try_into().unwrap());
+            let actual = header.checksum.compute(&buffer[..payload_end]);
+            if expected != actual {
+                return Err(PduError::BadChecksum { expected, actual });
+            }
+        }
+
+        let payload = header.compression.decompress(&buffer[header.header_len..payload_end], format.max_payload)?;
+        let pdu = Pdu {
+            kind: header.kind,
+            flags: header.flags,
+            request_id: header.request_id,
+            length: payload.len() as u32,
+            payload,
+        };
+        Ok(Some((pdu, total_len)))
+    }
+}
+
+/// 连接上下文，随每个 PDU 一起交给 Handler
+#[derive(Debug, Clone)]
+pub struct ConnContext {
+    /// 连接标识（线程ID、进程ID或任务编号），用于日志前缀
+    pub id: String,
+    /// 对端地址
+    pub peer_addr: SocketAddr,
+    /// 对端客户端证书中的身份，仅在 mTLS 连接上存在
+    pub peer_identity: Option<PeerIdentity>,
+    /// 要求的预共享密钥认证，由服务器设置
+    pub psk: Option<Arc<PskAuth>>,
+    /// 认证通过时使用的密钥名
+    pub psk_name: Option<String>,
+    /// 该连接使用的帧格式，构造响应 PDU 时使用；其中的压缩算法为与客户端协商的结果
+    pub format: FrameFormat,
+    /// 每次从连接读取的字节数
+    pub read_buffer: usize,
+    /// 服务器开始优雅关闭时变为 true，由服务器设置
+    pub drain: Option<tokio::sync::watch::Receiver<bool>>,
+}
+
+impl ConnContext {
+    pub fn new(id: impl Into<String>, peer_addr: SocketAddr, format: FrameFormat) -> Self {
+        ConnContext {
+            id: id.into(),
+            peer_addr,
+            peer_identity: None,
+            psk: None,
+            psk_name: None,
+            format,
+            read_buffer: DEFAULT_READ_BUFFER,
+            drain: None,
+        }
+    }
+}
+
+/// 同步业务处理接口：收到一个已解码的 PDU，返回零个或多个响应 PDU
+pub trait Handler: Send + Sync {
+    fn handle(&self, pdu: Pdu, ctx: &ConnContext) -> Vec<Pdu>;
+}
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// 异步业务处理接口，供 tokio 模型使用
+///
+/// 所有实现了 [`Handler`] 的类型都自动实现该接口。
+pub trait AsyncHandler: Send + Sync {
+    fn handle_async<'a>(&'a self, pdu: Pdu, ctx: &'a ConnContext) -> BoxFuture<'a, Vec<Pdu>>;
+}
+
+impl<H: Handler + ?Sized> AsyncHandler for H {
+    fn handle_async<'a>(&'a self, pdu: Pdu, ctx: &'a ConnContext) -> BoxFuture<'a, Vec<Pdu>> {
+        Box::pin(async move { self.handle(pdu, ctx) })
+    }
+}
+
+/// 内置的 echo 实现：将接收到的 PDU 原样返回
+pub struct Echo;
+
+impl Handler for Echo {
+    fn handle(&self, pdu: Pdu, _ctx: &ConnContext) -> Vec<Pdu> {
+        vec![pdu]
+    }
+}
+
+/// 按客户端证书身份的允许列表与拒绝列表
+///
+/// 拒绝列表优先；允许列表为空时允许所有未被拒绝的身份。没有证书的连接只有在允许列表为空时才会被放行。
+/// 名称可以是完整主题、CN 或任意一个备用名称，见 [`PeerIdentity::matches`]。
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccessList {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+impl AccessList {
+    /// 该连接的对端是否有权限
+    pub fn is_allowed(&self, identity: Option<&PeerIdentity>) -> bool {
+        match identity {
+            Some(identity) => {
+                !self.deny.iter().any(|name| identity.matches(name))
+                    && (self.allow.is_empty() || self.allow.iter().any(|name| identity.matches(name)))
+            }
+            None => self.allow.is_empty(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+}
+
+/// 按客户端证书身份授权的处理器：拒绝的请求回复错误 PDU，允许的请求交给内部处理器
+///
+/// 规则见 [`AccessList`]。
+pub struct Authorize<H> {
+    inner: H,
+    access: AccessList,
+}
+
+impl<H> Authorize<H> {
+    pub fn new(inner: H) -> Self {
+        Authorize {
+            inner,
+            access: AccessList::default(),
+        }
+    }
+
+    pub fn allow(mut self, name: impl Into<String>) -> Self {
+        self.access.allow.push(name.into());
+        self
+    }
+
+    pub fn deny(mut self, name: impl Into<String>) -> Self {
+        self.access.deny.push(name.into());
+        self
+    }
+
+    /// 该连接的对端是否有权限
+    pub fn is_allowed(&self, identity: Option<&PeerIdentity>) -> bool {
+        self.access.is_allowed(identity)
+    }
+}
+
+impl<H: Handler> Handler for Authorize<H> {
+    fn handle(&self, pdu: Pdu, ctx: &ConnContext) -> Vec<Pdu> {
+        if self.is_allowed(ctx.peer_identity.as_ref()) {
+            return self.inner.handle(pdu, ctx);
+        }
+        match &ctx.peer_identity {
+            Some(identity) => eprintln!("[{}] 拒绝客户端 {} 的请求: {}", ctx.id, ctx.peer_addr, identity),
+            None => eprintln!("[{}] 拒绝客户端 {} 的请求: 没有客户端证书", ctx.id, ctx.peer_addr),
+        }
+        vec![Pdu::error(pdu.request_id, "未授权", &ctx.format)]
+    }
+}
+
+/// 记录读取或解析失败的原因，并在协议允许时返回回复给对端的错误 PDU
+///
+/// v1 格式没有消息类型，无法表示错误；I/O 错误或对端已断开时也无需回复。
+fn report_error(ctx: &ConnContext, e: &PduError) -> Option<Pdu> {
+    match e {
+        PduError::Oversize { len, max } => {
+            eprintln!("[{}] 客户端 {} 发送的 PDU 长度 {} 超过上限 {}", ctx.id, ctx.peer_addr, len, max);
+        }
+        PduError::BadMagic(magic) => {
+            eprintln!("[{}] 客户端 {} 发送的数据魔数非法 0x{:02x}，可能不是本协议的客户端", ctx.id, ctx.peer_addr, magic);
+        }
+        PduError::BadVersion(version) => {
+            eprintln!("[{}] 客户端 {} 使用了不支持的协议版本 {}", ctx.id, ctx.peer_addr, version);
+        }
+        PduError::UnknownType(kind) => {
+            eprintln!("[{}] 客户端 {} 发送了未知的消息类型 {}", ctx.id, ctx.peer_addr, kind);
+        }
+        PduError::UnsupportedFlags(flags) => {
+            eprintln!("[{}] 客户端 {} 使用了不支持的标志位 0x{:02x}", ctx.id, ctx.peer_addr, flags);
+        }
+        PduError::BadChecksum { expected, actual } => {
+            eprintln!("[{}] 客户端 {} 的 PDU 校验和不匹配: 期望 0x{:08x}，实际 0x{:08x}", ctx.id, ctx.peer_addr, expected, actual);
+        }
+        PduError::Compression(e) => {
+            eprintln!("[{}] 客户端 {} 的 PDU 解压失败: {}", ctx.id, ctx.peer_addr, e);
+        }
+        PduError::Incomplete { needed } => {
+            eprintln!("[{}] 客户端 {} 在 PDU 传输中途断开，还缺少 {} 字节", ctx.id, ctx.peer_addr, needed);
+            return None;
+        }
+        PduError::Io(e) => {
+            eprintln!("[{}] 读取客户端 {} 数据失败: {}", ctx.id, ctx.peer_addr, e);
             return None;
         }
+    }
 
-        Some(1 + buffer[0] as usize)
+    if ctx.format.version != Version::V2 {
+        return None;
     }
+    Some(Pdu::error(0, &e.to_string(), &ctx.format))
 }
 
-pub fn handle_client(mut stream: TcpStream, peer_addr: SocketAddr, tid: ThreadId) {
-    let mut buffer= [0_u8; BUFFER_SIZE];
-    let mut received_data: Vec<u8> = Vec::new(); // 存储已接收但尚未构成完整PDU的数据
-    let mut pdu: Option<Pdu> = None;
+/// 解码器跳过了新的字节时记录日志，reported 为上次记录时的累计值
+fn report_skipped(ctx: &ConnContext, reported: &mut u64, skipped: u64) {
+    if skipped != *reported {
+        eprintln!("[{}] 已跳过客户端 {} 的损坏数据 {} 字节，累计 {} 字节", ctx.id, ctx.peer_addr, skipped - *reported, skipped);
+        *reported = skipped;
+    }
+}
+
+/// 该错误是否只影响单个 PDU，按策略可以继续处理后续数据
+fn is_skippable(ctx: &ConnContext, e: &PduError) -> bool {
+    ctx.format.recovers_from(e)
+}
+
+/// 连接上与客户端协商的压缩算法
+struct Negotiation {
+    /// 服务器配置的首选算法，协商时排在最前
+    preferred: Compression,
+    /// 双方都支持的算法，为空时不压缩
+    accepted: Vec<Compression>,
+}
+
+impl Negotiation {
+    /// 协商完成前不压缩，因此先清除连接上下文中的压缩算法
+    fn new(ctx: &mut ConnContext) -> Self {
+        let preferred = ctx.format.compression;
+        ctx.format.compression = Compression::None;
+        Negotiation {
+            preferred,
+            accepted: Vec::new(),
+        }
+    }
+
+    /// 处理客户端的压缩协商请求并返回应答，不是协商请求时返回 None
+    fn handshake(&mut self, pdu: &Pdu, ctx: &mut ConnContext) -> Option<Pdu> {
+        let offered = Compression::parse_handshake(pdu)?;
+        self.accepted = Compression::negotiate(self.preferred, &offered);
+        ctx.format.compression = self.accepted.first().copied().unwrap_or(Compression::None);
+        log_info!("[{}] 与客户端 {} 协商压缩算法: {:?}", ctx.id, ctx.peer_addr, self.accepted);
+        let payload = Compression::handshake_payload(&self.accepted);
+        Pdu::with_type(MsgType::Control, pdu.request_id, &payload, &ctx.format).ok()
+    }
+
+    /// 响应只能使用协商过的算法（echo 沿用请求的 flags），否则发送原始数据
+    fn restrict(&self, pdu: &mut Pdu) {
+        if let Ok(compression) = pdu.compression()
+            && compression != Compression::None
+            && !self.accepted.contains(&compression) {
+            pdu.flags &= !FLAG_COMPRESSION_MASK;
+        }
+    }
+}
+
+/// 服务器是否已开始优雅关闭
+fn is_draining(ctx: &ConnContext) -> bool {
+    ctx.drain.as_ref().is_some_and(|drain| *drain.borrow())
+}
+
+/// 等待服务器开始优雅关闭，没有设置时一直等待
+async fn wait_drain(drain: &mut Option<tokio::sync::watch::Receiver<bool>>) {
+    match drain {
+        Some(drain) => wait_shutdown(drain).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// 可以设置读取超时的阻塞连接，认证阶段用它限制等待应答的时间
+pub trait ReadTimeout {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl ReadTimeout for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+/// 发送挑战并校验第一个 PDU，返回认证通过的密钥名
+///
+/// 超时作用于整个认证过程：每次读取前按同一个截止时间设置剩余的超时，
+/// 逐字节发送应答的客户端也会在超时后被断开，与异步路径的 `tokio::time::timeout` 一致。
+fn authenticate<S: ReadTimeout>(
+    stream: &S,
+    reader: &mut PduReader<impl Read>,
+    writer: &mut PduWriter<impl Write>,
+    ctx: &ConnContext,
+    psk: &PskAuth,
+) -> Result<String, AuthError> {
+    let deadline = Instant::now() + psk.timeout;
+    let nonce = PskAuth::nonce()?;
+    writer.write_pdu(&auth::challenge_pdu(&nonce, &ctx.format)?)?;
+    let pdu = reader
+        .read_pdu_with(|_| {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            stream.set_read_timeout(Some(remaining))
+        })?
+        .ok_or(AuthError::Closed)?;
+    stream.set_read_timeout(None)?;
+    psk.verify(&nonce, &pdu)
+}
+
+async fn authenticate_async<S: AsyncRead + AsyncWrite + Unpin>(
+    framed: &mut Framed<S, RecoverableCodec>,
+    ctx: &ConnContext,
+    psk: &PskAuth,
+) -> Result<String, AuthError> {
+    let nonce = PskAuth::nonce()?;
+    framed.send(auth::challenge_pdu(&nonce, &ctx.format)?).await?;
+    let pdu = match framed.next().await {
+        Some(Ok(Ok(pdu))) => pdu,
+        Some(Ok(Err(e))) | Some(Err(e)) => return Err(e.into()),
+        None => return Err(AuthError::Closed),
+    };
+    psk.verify(&nonce, &pdu)
+}
+
+/// 记录认证结果；失败时返回发给客户端的错误 PDU
+fn report_auth(ctx: &mut ConnContext, result: Result<String, AuthError>) -> Option<Pdu> {
+    match result {
+        Ok(name) => {
+            log_info!("[{}] 客户端 {} 使用密钥 {} 认证通过", ctx.id, ctx.peer_addr, name);
+            let response = auth::ok_pdu(&name, &ctx.format).ok();
+            ctx.psk_name = Some(name);
+            response
+        }
+        Err(e) => {
+            eprintln!("[{}] 客户端 {} 认证失败: {}", ctx.id, ctx.peer_addr, e);
+            Some(Pdu::error(0, &e.client_message(), &ctx.format))
+        }
+    }
+}
+
+/// 处理一个阻塞模式的连接，stream 可以是 `TcpStream` 或 [`TlsStream`](crate::tls::TlsStream)
+///
+/// 配置了预共享密钥时，先完成认证再处理业务 PDU，认证失败或超时会发送错误 PDU 并关闭连接。
+/// 服务器优雅关闭时处理完当前的请求，再发送 [`GOING_AWAY`] 控制消息并关闭连接。
+pub fn handle_client<S, H: Handler + ?Sized>(stream: S, ctx: &ConnContext, handler: &H)
+where
+    S: ReadTimeout,
+    for<'a> &'a S: Read + Write,
+{
+    let mut ctx = ctx.clone();
+    let mut negotiation = Negotiation::new(&mut ctx);
+    let mut reader = PduReader::new(&stream, ctx.format).read_buffer(ctx.read_buffer);
+    let mut writer = PduWriter::new(&stream, ctx.format);
+    let mut skipped = 0;
+    if let Some(psk) = ctx.psk.clone() {
+        let result = authenticate(&stream, &mut reader, &mut writer, &ctx, &psk);
+        let authenticated = result.is_ok();
+        if let Some(response) = report_auth(&mut ctx, result) {
+            let _ = writer.write_pdu(&response);
+        }
+        if !authenticated {
+            log_info!("[{}] 与客户端 {} 的连接已关闭", ctx.id, ctx.peer_addr);
+            return;
+        }
+    }
     // 收发业务数据的小循环
-    loop {
-        // 接收来自客户端的数据
-        match stream.read(&mut buffer) {
-            Ok(0) => {
+    'conn: loop {
+        // 接收来自客户端的 PDU
+        let pdu = match reader.read_pdu() {
+            Ok(Some(pdu)) => pdu,
+            // 服务器关闭了读端，不再接收新的请求
+            _ if is_draining(&ctx) => break,
+            Ok(None) => {
                 // 客户端正常关闭连接
-                println!("[{:?}] client[{}] is closed!", tid, peer_addr);
+                log_info!("[{}] client[{}] is closed!", ctx.id, ctx.peer_addr);
                 break;
             }
-            Ok(size) => {
-                println!("[{:?}] 从客户端 {} 接收到 {} 字节数据", tid, peer_addr, size);
-                received_data.extend_from_slice(&buffer[..size]);
-                // 解析自定义应用层协议PDU
-                if !Pdu::is_complete_pdu(&received_data) {
-                    continue; // 接收到的数据不完整，等待下一次接收
+            Err(e) => {
+                if let Some(response) = report_error(&ctx, &e) {
+                    let _ = writer.write_pdu(&response);
                 }
-                match Pdu::payload_size(&received_data) {
-                    Some(expected_size) => {
-                        pdu = Pdu::from_bytes(&received_data[..expected_size]);
-                        received_data.drain(..expected_size); // 移除已处理的数据
-                    }
-                    None => { // 输入的buffer没有内容
-                        continue;
-                    }
+                if is_skippable(&ctx, &e) {
+                    continue;
                 }
+                break;
+            }
+        };
+        report_skipped(&ctx, &mut skipped, reader.skipped_bytes());
 
-                println!("{}", pdu.as_ref().unwrap());
-                // 将接收到的数据原样发送回客户端（实现echo功能）
-                let vec = pdu.unwrap().to_vec();
-                match stream.write_all(vec.as_slice()) {
-                    Ok(_) => {
-                        println!("[{:?}] 向客户端 {} 发送 {} 字节数据", tid, peer_addr, vec.len());
-                    }
-                    Err(e) => {
-                        eprintln!("[{:?}] 写入客户端 {} 失败: {}", tid, peer_addr, e);
-                        break;
-                    }
+        log_debug!("[{}] {}", ctx.id, pdu);
+        // 压缩协商请求由连接自己应答，其余交给业务处理器，并将响应依次发送回客户端
+        let responses = match negotiation.handshake(&pdu, &mut ctx) {
+            Some(response) => vec![response],
+            None => handler.handle(pdu, &ctx),
+        };
+        for mut response in responses {
+            negotiation.restrict(&mut response);
+            match writer.write_pdu(&response) {
+                Ok(size) => {
+                    log_debug!("[{}] 向客户端 {} 发送 {} 字节数据", ctx.id, ctx.peer_addr, size);
+                }
+                Err(e) => {
+                    eprintln!("[{}] 写入客户端 {} 失败: {}", ctx.id, ctx.peer_addr, e);
+                    break 'conn;
                 }
-            }
-            Err(e) => {
-                eprintln!("[{:?}] 读取客户端 {} 数据失败: {}", tid, peer_addr, e);
-                break;
             }
         }
+        if is_draining(&ctx) {
+            break;
+        }
     }
 
-    // std::thread::sleep(std::time::Duration::from_secs(5)); // 模拟子线程退出的延迟
+    if is_draining(&ctx) && let Some(pdu) = Pdu::going_away(&ctx.format) {
+        log_info!("[{}] 服务器正在关闭，通知客户端 {}", ctx.id, ctx.peer_addr);
+        let _ = writer.write_pdu(&pdu);
+    }
 
     // 连接会在drop时自动关闭
-    println!("[{:?}] 与客户端 {} 的连接已关闭", tid, peer_addr);
+    log_info!("[{}] 与客户端 {} 的连接已关闭", ctx.id, ctx.peer_addr);
 }
 
-pub fn handle_client2(mut stream: TcpStream, peer_addr: SocketAddr, id: u32) {
-    let mut buffer= [0_u8; BUFFER_SIZE];
-    let mut received_data: Vec<u8> = Vec::new(); // 存储已接收但尚未构成完整PDU的数据
-    let mut pdu: Option<Pdu> = None;
-    // 收发业务数据的小循环
-    loop {
-        // 接收来自客户端的数据
-        match stream.read(&mut buffer) {
-            Ok(0) => {
-                // 客户端正常关闭连接
-                println!("[{:?}] client[{}] is closed!", id, peer_addr);
-                break;
-            }
-            Ok(size) => {
-                println!("[{:?}] 从客户端 {} 接收到 {} 字节数据", id, peer_addr, size);
-                received_data.extend_from_slice(&buffer[..size]);
-                // 解析自定义应用层协议PDU
-                if !Pdu::is_complete_pdu(&received_data) {
-                    continue; // 接收到的数据不完整，等待下一次接收
-                }
-                match Pdu::payload_size(&received_data) {
-                    Some(expected_size) => {
-                        pdu = Pdu::from_bytes(&received_data[..expected_size]);
-                        received_data.drain(..expected_size); // 移除已处理的数据
+/// 非阻塞连接上的协议处理：由调用方负责收发字节，事件驱动的服务器用它逐段处理到达的数据
+///
+/// 与 [`handle_client`] 一样应答压缩协商、按 `CorruptPolicy` 跳过损坏的数据，但不支持预共享密钥认证。
+pub struct Session {
+    ctx: ConnContext,
+    negotiation: Negotiation,
+    assembler: FrameAssembler,
+    skipped: u64,
+}
+
+impl Session {
+    pub fn new(ctx: &ConnContext) -> Self {
+        let mut ctx = ctx.clone();
+        let negotiation = Negotiation::new(&mut ctx);
+        let assembler = FrameAssembler::new(ctx.format);
+        Session { ctx, negotiation, assembler, skipped: 0 }
+    }
+
+    pub fn ctx(&self) -> &ConnContext {
+        &self.ctx
+    }
+
+    /// 处理新收到的字节，把编码好的响应追加到 out；返回 false 时数据流已无法继续解析，应当关闭连接
+    pub fn receive<H: Handler + ?Sized>(&mut self, bytes: &[u8], handler: &H, out: &mut Vec<u8>) -> bool {
+        self.assembler.push(bytes);
+        loop {
+            let pdu = match self.assembler.next_pdu() {
+                Ok(Some(pdu)) => pdu,
+                // 数据不完整，等待下一次接收
+                Ok(None) => return true,
+                Err(e) => {
+                    if let Some(response) = report_error(&self.ctx, &e) {
+                        self.encode(&response, out);
                     }
-                    None => { // 输入的buffer没有内容
+                    if is_skippable(&self.ctx, &e) {
                         continue;
                     }
+                    return false;
                 }
+            };
+            report_skipped(&self.ctx, &mut self.skipped, self.assembler.skipped_bytes());
 
-                println!("{}", pdu.as_ref().unwrap());
-                // 将接收到的数据原样发送回客户端（实现echo功能）
-                let vec = pdu.unwrap().to_vec();
-                match stream.write_all(vec.as_slice()) {
-                    Ok(_) => {
-                        println!("[{:?}] 向客户端 {} 发送 {} 字节数据", id, peer_addr, vec.len());
-                    }
-                    Err(e) => {
-                        eprintln!("[{:?}] 写入客户端 {} 失败: {}", id, peer_addr, e);
-                        break;
-                    }
-                }
-            }
-            Err(e) => {
-                eprintln!("[{:?}] 读取客户端 {} 数据失败: {}", id, peer_addr, e);
-                break;
+            log_debug!("[{}] {}", self.ctx.id, pdu);
+            let responses = match self.negotiation.handshake(&pdu, &mut self.ctx) {
+                Some(response) => vec![response],
+                None => handler.handle(pdu, &self.ctx),
+            };
+            for mut response in responses {
+                self.negotiation.restrict(&mut response);
+                self.encode(&response, out);
             }
         }
     }
 
-    // 连接会在drop时自动关闭
-    println!("[{:?}] 与客户端 {} 的连接已关闭", id, peer_addr);
+    /// 把 [`GOING_AWAY`] 追加到 out，v1 格式下什么也不做
+    pub fn going_away(&self, out: &mut Vec<u8>) {
+        if let Some(pdu) = Pdu::going_away(&self.ctx.format) {
+            log_info!("[{}] 服务器正在关闭，通知客户端 {}", self.ctx.id, self.ctx.peer_addr);
+            self.encode(&pdu, out);
+        }
+    }
+
+    fn encode(&self, pdu: &Pdu, out: &mut Vec<u8>) {
+        match pdu.to_vec(&self.ctx.format) {
+            Ok(bytes) => out.extend_from_slice(&bytes),
+            Err(e) => eprintln!("[{}] 无法编码发给客户端 {} 的响应: {}", self.ctx.id, self.ctx.peer_addr, e),
+        }
+    }
 }
 
-pub async fn handle_client_async(mut stream: tokio::net::TcpStream, peer_addr: SocketAddr, id: u32, shutdown_notify: &tokio::sync::Notify) {
-    let mut buffer= [0_u8; BUFFER_SIZE];
-    let mut received_data: Vec<u8> = Vec::new(); // 存储已接收但尚未构成完整PDU的数据
-    let mut pdu: Option<Pdu> = None;
+/// 等待关闭通知（watch 通道的值变为 true）
+pub(crate) async fn wait_shutdown(shutdown: &mut tokio::sync::watch::Receiver<bool>) {
+    // 不能把 wait_for 返回的 Ref 带到 select! 分支中，否则 future 不再是 Send
+    let _ = shutdown.wait_for(|shutdown| *shutdown).await;
+}
 
-    loop {
+/// 处理一个异步连接，stream 可以是 `tokio::net::TcpStream` 或 TLS 连接
+///
+/// 服务器优雅关闭时处理完当前的请求，再发送 [`GOING_AWAY`] 控制消息并关闭连接。
+pub async fn handle_client_async<S, H>(stream: S, ctx: &ConnContext, handler: &H, mut shutdown: tokio::sync::watch::Receiver<bool>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    H: AsyncHandler + ?Sized,
+{
+    let mut ctx = ctx.clone();
+    let mut negotiation = Negotiation::new(&mut ctx);
+    let mut framed = Framed::with_capacity(stream, RecoverableCodec::new(ctx.format), ctx.read_buffer);
+    let mut drain = ctx.drain.clone();
+    let mut skipped = 0;
+    if let Some(psk) = ctx.psk.clone() {
+        let result = tokio::select! {
+            result = tokio::time::timeout(psk.timeout, authenticate_async(&mut framed, &ctx, &psk)) => {
+                result.unwrap_or(Err(AuthError::Timeout))
+            }
+            _ = wait_shutdown(&mut shutdown) => return,
+        };
+        let authenticated = result.is_ok();
+        if let Some(response) = report_auth(&mut ctx, result) {
+            let _ = framed.send(response).await;
+        }
+        if !authenticated {
+            return;
+        }
+    }
+
+    'conn: loop {
         tokio::select! {
-            result = stream.read(&mut buffer) => {
-                match result {
-                    Ok(0) => {
+            result = framed.next() => {
+                let pdu = match result {
+                    Some(Ok(Ok(pdu))) => pdu,
+                    None => {
                         // 客户端正常关闭连接
-                        println!("[{}] client[{}] is closed!", id, peer_addr);
+                        log_info!("[{}] client[{}] is closed!", ctx.id, ctx.peer_addr);
                         break;
                     }
-                    Ok(size) => {
-                        println!("[{:?}] 从客户端 {} 接收到 {} 字节数据", id, peer_addr, size);
-                        received_data.extend_from_slice(&buffer[..size]);
-                        // 解析自定义应用层协议PDU
-                        if !Pdu::is_complete_pdu(&received_data) {
-                            continue; // 接收到的数据不完整，等待下一次接收
-                        }
-                        match Pdu::payload_size(&received_data) {
-                            Some(expected_size) => {
-                                pdu = Pdu::from_bytes(&received_data[..expected_size]);
-                                received_data.drain(..expected_size); // 移除已处理的数据
-                            }
-                            None => { // 输入的buffer没有内容
-                                continue;
-                            }
-                        }
-
-                        println!("{}", pdu.as_ref().unwrap());
-                        // 将接收到的数据原样发送回客户端（实现echo功能）
-                        let vec = pdu.unwrap().to_vec();
-                        match stream.write_all(vec.as_slice()).await {
-                            Ok(_) => {
-                                println!("[{:?}] 向客户端 {} 发送 {} 字节数据", id, peer_addr, vec.len());
-                            }
-                            Err(e) => {
-                                eprintln!("[{:?}] 写入客户端 {} 失败: {}", id, peer_addr, e);
-                                break;
-                            }
+                    // 按 CorruptPolicy 跳过的损坏数据
+                    Some(Ok(Err(e))) => {
+                        if let Some(response) = report_error(&ctx, &e) {
+                            let _ = framed.send(response).await;
                         }
+                        continue;
                     }
-                    Err(e) => {
-                        eprintln!("[{}] 读取客户端 {} 数据失败: {}", id, peer_addr, e);
+                    Some(Err(e)) => {
+                        if let Some(response) = report_error(&ctx, &e) {
+                            let _ = framed.send(response).await;
+                        }
                         break;
                     }
+                };
+                report_skipped(&ctx, &mut skipped, framed.codec().skipped_bytes());
+
+                log_debug!("[{}] {}", ctx.id, pdu);
+                // 压缩协商请求由连接自己应答，其余交给业务处理器，并将响应依次发送回客户端
+                let responses = match negotiation.handshake(&pdu, &mut ctx) {
+                    Some(response) => vec![response],
+                    None => handler.handle_async(pdu, &ctx).await,
+                };
+                for mut response in responses {
+                    negotiation.restrict(&mut response);
+                    if let Err(e) = framed.feed(response).await {
+                        eprintln!("[{}] 写入客户端 {} 失败: {}", ctx.id, ctx.peer_addr, e);
+                        break 'conn;
+                    }
+                }
+                if let Err(e) = framed.flush().await {
+                    eprintln!("[{}] 写入客户端 {} 失败: {}", ctx.id, ctx.peer_addr, e);
+                    break;
                 }
             }
+            // 等待优雅关闭通知，正在处理的请求已在上一个分支中完成
+            _ = wait_drain(&mut drain) => {
+                if let Some(pdu) = Pdu::going_away(&ctx.format) {
+                    log_info!("[{}] 服务器正在关闭，通知客户端 {}", ctx.id, ctx.peer_addr);
+                    let _ = framed.send(pdu).await;
+                }
+                break;
+            }
             // 等待关闭通知
-            _ = shutdown_notify.notified() => {
-                println!("[async] 收到关闭通知，断开客户端 {}", peer_addr);
+            _ = wait_shutdown(&mut shutdown) => {
+                log_info!("[async] 收到关闭通知，断开客户端 {}", ctx.peer_addr);
                 break;
             }
         }
     }
-}
\ No newline at end of file
+}
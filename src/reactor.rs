@@ -0,0 +1,281 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::time::Duration;
+
+use crate::network_handler::{ConnContext, Handler, Session};
+use crate::log_info;
+
+/// 边缘触发的读写事件，每次通知后都必须读写到 `WouldBlock` 为止
+pub const EDGE_READ_WRITE: u32 = (libc::EPOLLIN | libc::EPOLLOUT | libc::EPOLLRDHUP | libc::EPOLLET) as u32;
+/// 边缘触发的读事件
+pub const EDGE_READ: u32 = (libc::EPOLLIN | libc::EPOLLET) as u32;
+/// 写缓冲超过该长度时暂停读取，等对端取走数据后再继续，避免不读取响应的客户端占满内存
+pub const WRITE_HIGH_WATER: usize = 1 << 20;
+
+fn cvt(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result < 0 { Err(io::Error::last_os_error()) } else { Ok(result) }
+}
+
+/// epoll 实例的简单封装
+pub struct Epoll {
+    fd: OwnedFd,
+}
+
+impl Epoll {
+    pub fn new() -> io::Result<Self> {
+        let fd = cvt(unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) })?;
+        Ok(Epoll { fd: unsafe { OwnedFd::from_raw_fd(fd) } })
+    }
+
+    /// 注册描述符，事件发生时 [`wait`](Self::wait) 返回的 `u64` 为 token
+    pub fn add(&self, fd: RawFd, token: u64, events: u32) -> io::Result<()> {
+        let mut event = libc::epoll_event { events, u64: token };
+        cvt(unsafe { libc::epoll_ctl(self.fd.as_raw_fd(), libc::EPOLL_CTL_ADD, fd, &mut event) })?;
+        Ok(())
+    }
+
+    /// 修改已注册描述符关注的事件；对边缘触发的描述符，已经就绪时会重新产生一次事件
+    pub fn modify(&self, fd: RawFd, token: u64, events: u32) -> io::Result<()> {
+        let mut event = libc::epoll_event { events, u64: token };
+        cvt(unsafe { libc::epoll_ctl(self.fd.as_raw_fd(), libc::EPOLL_CTL_MOD, fd, &mut event) })?;
+        Ok(())
+    }
+
+    pub fn delete(&self, fd: RawFd) -> io::Result<()> {
+        cvt(unsafe { libc::epoll_ctl(self.fd.as_raw_fd(), libc::EPOLL_CTL_DEL, fd, std::ptr::null_mut()) })?;
+        Ok(())
+    }
+
+    /// 等待事件，返回就绪的 token 与事件；被信号中断时返回空列表
+    pub fn wait(&self, events: &mut [libc::epoll_event], timeout: Option<Duration>) -> io::Result<Vec<(u64, u32)>> {
+        let timeout = timeout.map_or(-1, |timeout| timeout.as_millis().min(i32::MAX as u128) as i32);
+        let count = unsafe { libc::epoll_wait(self.fd.as_raw_fd(), events.as_mut_ptr(), events.len() as i32, timeout) };
+        if count < 0 {
+            let e = io::Error::last_os_error();
+            return if e.kind() == io::ErrorKind::Interrupted { Ok(Vec::new()) } else { Err(e) };
+        }
+        Ok(events[..count as usize].iter().map(|event| (event.u64, event.events)).collect())
+    }
+}
+
+/// 水平触发的多路复用中一个描述符关注的事件
+#[derive(Debug, Clone, Copy)]
+pub struct Interest {
+    pub fd: RawFd,
+    pub read: bool,
+    pub write: bool,
+}
+
+/// 水平触发的多路复用接口，每次等待都传入完整的关注列表
+pub trait Multiplexer {
+    /// 能够等待的描述符上限（不含），`None` 表示只受进程的描述符数量限制
+    fn fd_limit(&self) -> Option<RawFd> {
+        None
+    }
+
+    /// 等待任意一个描述符就绪，返回就绪项在 interests 中的下标；被信号中断时返回空列表
+    fn wait(&mut self, interests: &[Interest]) -> io::Result<Vec<usize>>;
+}
+
+/// 基于 `poll(2)` 的多路复用，描述符数量只受 `RLIMIT_NOFILE` 限制
+#[derive(Default)]
+pub struct Poll {
+    fds: Vec<libc::pollfd>,
+}
+
+impl Multiplexer for Poll {
+    fn wait(&mut self, interests: &[Interest]) -> io::Result<Vec<usize>> {
+        self.fds.clear();
+        self.fds.extend(interests.iter().map(|interest| {
+            let mut events = 0;
+            if interest.read {
+                events |= libc::POLLIN;
+            }
+            if interest.write {
+                events |= libc::POLLOUT;
+            }
+            libc::pollfd { fd: interest.fd, events, revents: 0 }
+        }));
+        if unsafe { libc::poll(self.fds.as_mut_ptr(), self.fds.len() as libc::nfds_t, -1) } < 0 {
+            let e = io::Error::last_os_error();
+            return if e.kind() == io::ErrorKind::Interrupted { Ok(Vec::new()) } else { Err(e) };
+        }
+        // 对端关闭或出错时 revents 中是 POLLHUP/POLLERR，同样交给调用方读写后发现
+        Ok(self.fds.iter().enumerate().filter(|(_, fd)| fd.revents != 0).map(|(index, _)| index).collect())
+    }
+}
+
+/// 基于 `select(2)` 的多路复用，只能等待小于 `FD_SETSIZE` 的描述符
+#[derive(Default)]
+pub struct Select;
+
+impl Multiplexer for Select {
+    fn fd_limit(&self) -> Option<RawFd> {
+        Some(libc::FD_SETSIZE as RawFd)
+    }
+
+    fn wait(&mut self, interests: &[Interest]) -> io::Result<Vec<usize>> {
+        let mut readfds: libc::fd_set = unsafe { std::mem::zeroed() };
+        let mut writefds: libc::fd_set = unsafe { std::mem::zeroed() };
+        let mut nfds = 0;
+        for interest in interests {
+            // fd_set 是固定大小的位图，FD_SET 超出范围的描述符会写越界
+            if interest.fd >= libc::FD_SETSIZE as RawFd {
+                return Err(fd_setsize_exceeded(interest.fd));
+            }
+            unsafe {
+                if interest.read {
+                    libc::FD_SET(interest.fd, &mut readfds);
+                }
+                if interest.write {
+                    libc::FD_SET(interest.fd, &mut writefds);
+                }
+            }
+            nfds = nfds.max(interest.fd + 1);
+        }
+        let count = unsafe { libc::select(nfds, &mut readfds, &mut writefds, std::ptr::null_mut(), std::ptr::null_mut()) };
+        if count < 0 {
+            let e = io::Error::last_os_error();
+            return if e.kind() == io::ErrorKind::Interrupted { Ok(Vec::new()) } else { Err(e) };
+        }
+        Ok(interests
+            .iter()
+            .enumerate()
+            .filter(|(_, interest)| unsafe {
+                (interest.read && libc::FD_ISSET(interest.fd, &readfds)) || (interest.write && libc::FD_ISSET(interest.fd, &writefds))
+            })
+            .map(|(index, _)| index)
+            .collect())
+    }
+}
+
+/// 描述符超出 select() 能够处理的范围
+pub fn fd_setsize_exceeded(fd: RawFd) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("描述符 {} 超出了 select() 的上限 FD_SETSIZE ({})", fd, libc::FD_SETSIZE),
+    )
+}
+
+/// 进程的描述符已经用完（EMFILE/ENFILE），附带当前的 `RLIMIT_NOFILE`
+pub fn fd_exhausted(e: &io::Error) -> Option<io::Error> {
+    if !matches!(e.raw_os_error(), Some(libc::EMFILE | libc::ENFILE)) {
+        return None;
+    }
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) };
+    Some(io::Error::new(
+        e.kind(),
+        format!("{}: 已达到进程的描述符上限 RLIMIT_NOFILE ({})，有连接关闭后再继续接受", e, limit.rlim_cur),
+    ))
+}
+
+/// 事件循环中的一个非阻塞连接：读缓冲由 [`Session`] 拼接 PDU，写缓冲保存尚未发出的响应
+pub struct Connection {
+    stream: TcpStream,
+    session: Session,
+    /// 尚未写入内核的响应
+    out: Vec<u8>,
+    /// out 中已经写入的字节数
+    written: usize,
+    /// 写完 out 后关闭连接
+    closing: bool,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream, ctx: &ConnContext) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        Ok(Connection {
+            stream,
+            session: Session::new(ctx),
+            out: Vec::new(),
+            written: 0,
+            closing: false,
+        })
+    }
+
+    pub fn stream(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    pub fn ctx(&self) -> &ConnContext {
+        self.session.ctx()
+    }
+
+    /// 水平触发的多路复用中该连接关注的事件：有待写的响应时关注可写，写缓冲积压或正在关闭时不再关注可读
+    pub fn interest(&self) -> Interest {
+        let pending = self.out.len() - self.written;
+        Interest {
+            fd: self.stream.as_raw_fd(),
+            read: !self.closing && pending < WRITE_HIGH_WATER,
+            write: pending > 0,
+        }
+    }
+
+    /// 不再读取新的请求，发送 going-away 后关闭
+    pub fn going_away(&mut self) {
+        if !self.closing {
+            self.session.going_away(&mut self.out);
+            self.closing = true;
+        }
+    }
+
+    /// 在读写事件之后调用：交替写出响应、读取并处理请求，直到读写都返回 `WouldBlock`
+    ///
+    /// 边缘触发模式下没有读完的数据不会再次通知，所以一直处理到内核缓冲为空或写不出去为止。
+    /// 返回 false 时连接已经结束，调用方应当移除它。
+    pub fn drive<H: Handler + ?Sized>(&mut self, handler: &H, scratch: &mut Vec<u8>) -> bool {
+        loop {
+            if let Err(e) = self.flush() {
+                eprintln!("[{}] 写入客户端 {} 失败: {}", self.ctx().id, self.ctx().peer_addr, e);
+                return false;
+            }
+            let pending = self.out.len() - self.written;
+            if self.closing {
+                // 写完剩余的响应后关闭，写不出去时等待下一次可写事件
+                return pending > 0;
+            }
+            if pending >= WRITE_HIGH_WATER {
+                // 对端没有及时读取响应，可写事件到来后再继续读取
+                return true;
+            }
+
+            scratch.resize(self.ctx().read_buffer, 0);
+            match (&self.stream).read(scratch) {
+                Ok(0) => {
+                    // 客户端正常关闭连接
+                    log_info!("[{}] client[{}] is closed!", self.ctx().id, self.ctx().peer_addr);
+                    self.closing = true;
+                }
+                Ok(size) => {
+                    if !self.session.receive(&scratch[..size], handler, &mut self.out) {
+                        self.closing = true;
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return true,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    eprintln!("[{}] 读取客户端 {} 数据失败: {}", self.ctx().id, self.ctx().peer_addr, e);
+                    return false;
+                }
+            }
+        }
+    }
+
+    /// 尽量写出写缓冲中的数据，写不出去时保留剩余部分
+    fn flush(&mut self) -> io::Result<()> {
+        while self.written < self.out.len() {
+            match (&self.stream).write(&self.out[self.written..]) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(size) => self.written += size,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.out.clear();
+        self.written = 0;
+        Ok(())
+    }
+}
@@ -0,0 +1,2092 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::Poll;
+use std::time::{Duration, Instant};
+use signal_hook::consts::{SIGCHLD, SIGHUP, SIGINT, SIGTERM, SIGTTIN, SIGTTOU, SIGUSR2};
+use signal_hook::iterator::Signals;
+use rustls::ServerConfig;
+use serde::Deserialize;
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+use tokio::sync::watch;
+use tokio_rustls::TlsAcceptor;
+
+use crate::network_handler::{
+    handle_client, handle_client_async, wait_shutdown, AccessList, AsyncHandler, Checksum, ConnContext, CorruptPolicy, Echo,
+    FrameFormat, Handler, Pdu, Version,
+};
+use crate::auth::PskAuth;
+use crate::codec::DEFAULT_READ_BUFFER;
+use crate::config::ConfigError;
+use crate::log::{self, Level};
+use crate::reactor::{self, Connection, Epoll, Interest, Multiplexer, EDGE_READ, EDGE_READ_WRITE};
+use crate::systemd;
+use crate::tls::{self, ServerTlsStream};
+use crate::upgrade;
+use crate::log_info;
+
+/// 未指定监听地址时使用的地址
+pub const DEFAULT_ADDR: &str = "0.0.0.0:8080";
+/// 默认的 listen() 队列长度
+pub const DEFAULT_BACKLOG: i32 = 128;
+/// 线程池模型默认的工作线程数
+pub const DEFAULT_WORKERS: usize = 8;
+/// 线程池模型中等待空闲工作线程的连接数的默认上限
+pub const DEFAULT_QUEUE_DEPTH: usize = 64;
+/// 优雅关闭时等待连接处理完当前请求的默认时间
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 服务器的并发模型，配置文件中分别写作 `single`、`thread`、`process`、`tokio`、`epoll`、`poll`、`select`、`io_uring`、`thread_pool`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Model {
+    /// 单线程模型: 一次只能处理一个客户端连接
+    #[serde(rename = "single")]
+    Single,
+    /// 多线程模型: 为每个客户端连接创建一个线程
+    #[serde(rename = "thread")]
+    ThreadPerConnection,
+    /// 多进程模型: 为每个客户端连接创建一个进程
+    #[serde(rename = "process")]
+    ProcessPerConnection,
+    /// tokio 模型: 为每个客户端连接创建一个异步任务
+    #[serde(rename = "tokio")]
+    Tokio,
+    /// epoll 模型: 单线程的边缘触发事件循环处理所有连接，不支持 TLS 与预共享密钥认证
+    #[serde(rename = "epoll")]
+    Epoll,
+    /// poll 模型: 单线程的 poll(2) 事件循环，描述符数量受 RLIMIT_NOFILE 限制，不支持 TLS 与预共享密钥认证
+    #[serde(rename = "poll")]
+    Poll,
+    /// select 模型: 单线程的 select(2) 事件循环，只能处理小于 FD_SETSIZE 的描述符，不支持 TLS 与预共享密钥认证
+    #[serde(rename = "select")]
+    Select,
+    /// io_uring 模型: 单线程提交 multishot accept/recv 的完成式 I/O，需要启用 `io-uring` 特性与 Linux 6.0 以上的内核，
+    /// 不支持 TLS 与预共享密钥认证
+    #[serde(rename = "io_uring")]
+    IoUring,
+    /// 线程池模型: 固定数量的工作线程处理连接，排队的连接超过上限时通知客户端服务器繁忙
+    #[serde(rename = "thread_pool")]
+    ThreadPool,
+    /// 预派生模型: 主进程预先创建固定数量的工作进程，每个工作进程在共用的监听套接字上逐个接受连接，
+    /// 退出的工作进程会被重新创建，收到 SIGTTIN / SIGTTOU 时增加或减少一个工作进程
+    #[serde(rename = "prefork")]
+    Prefork,
+}
+
+impl Model {
+    /// 在单线程的事件循环中处理所有连接的模型
+    fn is_event_loop(self) -> bool {
+        matches!(self, Model::Epoll | Model::Poll | Model::Select | Model::IoUring)
+    }
+}
+
+enum HandlerKind {
+    Sync(Arc<dyn Handler>),
+    Async(Arc<dyn AsyncHandler>),
+}
+
+/// 运行期间可以更新的设置
+///
+/// 更新只对之后接受的连接生效，已有的连接继续使用建立时的设置。
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// 同时处理的最大连接数，超出的连接会被直接关闭
+    pub max_connections: Option<usize>,
+    pub format: FrameFormat,
+    /// 每次从连接读取的字节数
+    pub read_buffer: usize,
+    pub psk: Option<Arc<PskAuth>>,
+    /// 按客户端证书身份接受或拒绝连接，仅在 mTLS 连接上有意义
+    pub access: AccessList,
+    /// 更新设置时同时切换的日志级别，None 表示保持不变
+    pub log_level: Option<Level>,
+    /// 优雅关闭时最多等待多久，之后强制关闭剩余的连接
+    pub drain_timeout: Duration,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            max_connections: None,
+            format: FrameFormat::default(),
+            read_buffer: DEFAULT_READ_BUFFER,
+            psk: None,
+            access: AccessList::default(),
+            log_level: None,
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+        }
+    }
+}
+
+impl Settings {
+    fn at_capacity(&self, active: usize) -> bool {
+        self.max_connections.is_some_and(|max| active >= max)
+    }
+
+    /// 检查相互冲突的设置：v1 格式没有消息类型，无法表示认证的挑战与应答；
+    /// 重新同步只在找到校验通过的 PDU 后恢复，没有校验和时无法恢复
+    fn validate(&self) -> Result<(), String> {
+        if self.psk.is_some() && self.format.version == Version::V1 {
+            return Err("预共享密钥认证需要 v2 帧格式".to_string());
+        }
+        if self.format.on_corrupt == CorruptPolicy::Resync && self.format.checksum == Checksum::None {
+            return Err("CorruptPolicy::Resync 需要启用校验和".to_string());
+        }
+        Ok(())
+    }
+
+    /// 按当前设置创建连接上下文
+    fn context(&self, id: impl Into<String>, peer_addr: SocketAddr, drain: watch::Receiver<bool>) -> ConnContext {
+        let mut ctx = ConnContext::new(id, peer_addr, self.format);
+        ctx.psk = self.psk.clone();
+        ctx.read_buffer = self.read_buffer;
+        ctx.drain = Some(drain);
+        ctx
+    }
+}
+
+/// 重新读取设置的函数，收到 SIGHUP 时调用
+type ReloadFn = Box<dyn Fn() -> Result<Settings, ConfigError> + Send + Sync>;
+
+/// [`Server`] 的构建器
+pub struct ServerBuilder {
+    addrs: Vec<String>,
+    backlog: i32,
+    model: Model,
+    handler: HandlerKind,
+    handle_signals: bool,
+    tls: Option<Arc<ServerConfig>>,
+    settings: Settings,
+    reload: Option<ReloadFn>,
+    workers: usize,
+    queue_depth: Option<usize>,
+    reuse_port: bool,
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        ServerBuilder {
+            addrs: Vec::new(),
+            backlog: DEFAULT_BACKLOG,
+            model: Model::Single,
+            handler: HandlerKind::Sync(Arc::new(Echo)),
+            handle_signals: false,
+            tls: None,
+            settings: Settings::default(),
+            reload: None,
+            workers: DEFAULT_WORKERS,
+            queue_depth: None,
+            reuse_port: false,
+        }
+    }
+}
+
+impl ServerBuilder {
+    /// 监听地址，默认 [`DEFAULT_ADDR`]；多次调用时同时监听所有地址，端口为 0 时由系统选择
+    pub fn bind(mut self, addr: impl Into<String>) -> Self {
+        self.addrs.push(addr.into());
+        self
+    }
+
+    /// listen() 队列长度，默认 [`DEFAULT_BACKLOG`]
+    pub fn backlog(mut self, backlog: i32) -> Self {
+        self.backlog = backlog;
+        self
+    }
+
+    /// 并发模型，默认 [`Model::Single`]
+    pub fn model(mut self, model: Model) -> Self {
+        self.model = model;
+        self
+    }
+
+    /// 线程池模型的工作线程数或预派生模型的工作进程数，默认 [`DEFAULT_WORKERS`]
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    /// 线程池模型中等待空闲工作线程的连接数上限，默认 [`DEFAULT_QUEUE_DEPTH`]；为 0 时只接受能立即处理的连接
+    ///
+    /// 不能与 [`reuse_port`](Self::reuse_port) 一起使用。
+    pub fn queue_depth(mut self, depth: usize) -> Self {
+        self.queue_depth = Some(depth);
+        self
+    }
+
+    /// 每个工作线程、工作进程或 tokio 的 accept 循环在监听地址上打开自己的 SO_REUSEPORT 监听器，
+    /// 由内核在它们之间分配新连接，仅可用于 [`Model::ThreadPool`]、[`Model::Prefork`] 与 [`Model::Tokio`]
+    ///
+    /// 第一个工作线程、工作进程或 accept 循环使用共用的监听套接字，其余的各自新打开一个，监听套接字总数等于工作数。
+    /// 线程池模型启用后不再排队，也不发送服务器繁忙通知，连接在内核的监听队列中等待，因此不能同时设置 `queue_depth`。
+    pub fn reuse_port(mut self, enable: bool) -> Self {
+        self.reuse_port = enable;
+        self
+    }
+
+    /// 同步业务处理器，可用于所有并发模型，默认 [`Echo`]
+    pub fn handler(mut self, handler: impl Handler + 'static) -> Self {
+        self.handler = HandlerKind::Sync(Arc::new(handler));
+        self
+    }
+
+    /// 异步业务处理器，仅可用于 [`Model::Tokio`]
+    pub fn async_handler(mut self, handler: impl AsyncHandler + 'static) -> Self {
+        self.handler = HandlerKind::Async(Arc::new(handler));
+        self
+    }
+
+    /// 同时处理的最大连接数，超出的连接会被直接关闭
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.settings.max_connections = Some(max);
+        self
+    }
+
+    /// PDU 帧格式，必须与客户端一致，默认 [`FrameFormat::default`]
+    pub fn frame_format(mut self, format: FrameFormat) -> Self {
+        self.settings.format = format;
+        self
+    }
+
+    /// 每个连接每次读取的字节数，默认 [`DEFAULT_READ_BUFFER`]
+    pub fn read_buffer(mut self, size: usize) -> Self {
+        self.settings.read_buffer = size;
+        self
+    }
+
+    /// 在所有连接上使用 TLS，配置可由 [`tls::server_config`](crate::tls::server_config) 从 PEM 文件创建
+    pub fn tls(mut self, config: Arc<ServerConfig>) -> Self {
+        self.tls = Some(config);
+        self
+    }
+
+    /// 要求客户端在发送业务 PDU 之前用预共享密钥完成挑战/应答认证，只能与 v2 帧格式一起使用
+    pub fn psk(mut self, auth: PskAuth) -> Self {
+        self.settings.psk = Some(Arc::new(auth));
+        self
+    }
+
+    /// 优雅关闭时最多等待多久，默认 [`DEFAULT_DRAIN_TIMEOUT`]
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.settings.drain_timeout = timeout;
+        self
+    }
+
+    /// 只接受客户端证书身份符合 `access` 的连接，需要同时使用要求客户端证书的 TLS 配置
+    pub fn access(mut self, access: AccessList) -> Self {
+        self.settings.access = access;
+        self
+    }
+
+    /// 一次性设置所有运行期可更新的设置
+    pub fn settings(mut self, settings: Settings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// 收到 SIGHUP 时调用 `reload` 重新读取设置，失败时继续使用原来的设置
+    ///
+    /// 只在 [`handle_signals`](Self::handle_signals) 开启时由服务器自己调用，也可以通过 [`Server::reload`] 手动触发。
+    pub fn reload(mut self, reload: impl Fn() -> Result<Settings, ConfigError> + Send + Sync + 'static) -> Self {
+        self.reload = Some(Box::new(reload));
+        self
+    }
+
+    /// 是否由服务器自己处理信号：SIGINT 触发 [`Server::shutdown`]，SIGTERM 触发 [`Server::drain`]，
+    /// SIGHUP 触发 [`Server::reload`]；关闭过程中再次收到 SIGINT 或 SIGTERM 时立即退出进程
+    ///
+    /// 嵌入到其他程序时通常关闭此项，由宿主程序调用 `shutdown()`。
+    pub fn handle_signals(mut self, enable: bool) -> Self {
+        self.handle_signals = enable;
+        self
+    }
+
+    /// 绑定监听地址并创建服务器
+    pub fn build(self) -> io::Result<Server> {
+        if matches!(self.handler, HandlerKind::Async(_)) && self.model != Model::Tokio {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("异步处理器不能用于 {:?} 模型", self.model),
+            ));
+        }
+        if self.workers == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "工作线程数必须大于 0"));
+        }
+        if self.model == Model::IoUring && !cfg!(feature = "io-uring") {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "io_uring 模型需要在编译时启用 io-uring 特性"));
+        }
+        // 在接受任何连接之前确认内核支持 multishot accept/recv，而不是在运行中才失败
+        #[cfg(feature = "io-uring")]
+        if self.model == Model::IoUring {
+            crate::uring::check_support()?;
+        }
+        if self.model.is_event_loop() && (self.tls.is_some() || self.settings.psk.is_some()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} 模型不支持 TLS 与预共享密钥认证", self.model),
+            ));
+        }
+        self.settings.validate().map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        if self.reuse_port && !matches!(self.model, Model::ThreadPool | Model::Prefork | Model::Tokio) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} 模型不支持 SO_REUSEPORT 分片", self.model),
+            ));
+        }
+        if self.reuse_port && self.queue_depth.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "SO_REUSEPORT 分片时连接在内核的监听队列中等待，不能设置 queue_depth",
+            ));
+        }
+
+        let listeners = listeners(self.addrs, self.backlog, self.reuse_port)?;
+        if self.reuse_port {
+            // 继承的监听套接字没有设置 SO_REUSEPORT 时，无法在同一地址上再打开监听器
+            for listener in &listeners {
+                if !SockRef::from(listener).reuse_port()? {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("继承的监听套接字[{}]没有设置 SO_REUSEPORT", listener.local_addr()?),
+                    ));
+                }
+            }
+        }
+        // 监听套接字可能与热升级的新进程共用，就绪的连接可能被对方取走，accept() 不能阻塞
+        for listener in &listeners {
+            listener.set_nonblocking(true)?;
+        }
+        let listen_fds = listeners.iter().map(AsRawFd::as_raw_fd).collect();
+        let (waker, wake_rx) = UnixStream::pair()?;
+        waker.set_nonblocking(true)?;
+        wake_rx.set_nonblocking(true)?;
+        let (shutdown_tx, _) = watch::channel(false);
+        let (drain_tx, _) = watch::channel(false);
+
+        Ok(Server {
+            listeners,
+            model: self.model,
+            handler: self.handler,
+            handle_signals: self.handle_signals,
+            tls: self.tls,
+            backlog: self.backlog,
+            workers: self.workers,
+            queue_depth: self.queue_depth.unwrap_or(DEFAULT_QUEUE_DEPTH),
+            reuse_port: self.reuse_port,
+            wake_rx,
+            shared: Arc::new(Shared {
+                shutdown: AtomicBool::new(false),
+                upgrading: AtomicBool::new(false),
+                handed_over: AtomicBool::new(false),
+                listen_fds,
+                waker,
+                connections: Mutex::new(HashMap::new()),
+                child_pids: Mutex::new(HashSet::new()),
+                shutdown_tx,
+                drain_tx,
+                settings: RwLock::new(Arc::new(self.settings)),
+                reload: self.reload,
+            }),
+        })
+    }
+}
+
+/// 热升级启动的新进程使用旧进程交出的监听套接字，由 systemd 激活时使用 systemd 传入的监听套接字，
+/// 否则绑定到指定地址和端口
+fn listeners(addrs: Vec<String>, backlog: i32, reuse_port: bool) -> io::Result<Vec<TcpListener>> {
+    let (listeners, source) = if let Some(listeners) = upgrade::inherited_listeners()? {
+        (listeners, "旧进程交出")
+    } else if let Some(listeners) = systemd::listeners()? {
+        (listeners, "systemd 传入")
+    } else {
+        let addrs = if addrs.is_empty() { vec![DEFAULT_ADDR.to_string()] } else { addrs };
+        return addrs.iter().map(|addr| bind_listener(addr, backlog, reuse_port)).collect();
+    };
+    for listener in &listeners {
+        log_info!("[srv] 使用{}的监听套接字[{}]", source, listener.local_addr()?);
+    }
+    Ok(listeners)
+}
+
+/// 向 systemd 发送状态通知，不是由 systemd 启动时什么也不做
+fn notify_systemd(state: &str) {
+    if let Err(e) = systemd::notify(state) {
+        eprintln!("[srv] 向 systemd 发送 {} 失败: {}", state.replace('\n', " "), e);
+    }
+}
+
+/// 在 fork() 出的子进程中恢复默认的信号处理
+fn reset_signal_handlers() {
+    // signal-hook 库会包装原本的信号处理器、缓存收到的信号（缓存在Signals变量中）。
+    // 当 fork() 被调用时，子进程继承了包装后的信号处理器和Signals变量，但是没有专门的线程
+    // 去消耗缓存的信号，这会产生非预期的行为，因此必须要重置相关的信号处理器。
+    for sig in [SIGINT, SIGTERM, SIGCHLD] {
+        unsafe { libc::signal(sig, libc::SIG_DFL); }
+    }
+    // 重新加载、热升级与调整工作进程数只由主进程处理。子进程与主进程在同一个进程组中，
+    // 这些信号的默认动作会终止或暂停进程，发给整个进程组时（例如终端挂断）子进程必须忽略它们
+    for sig in [SIGHUP, SIGUSR2, SIGTTIN, SIGTTOU] {
+        unsafe { libc::signal(sig, libc::SIG_IGN); }
+    }
+}
+
+/// 持有标准输出与标准错误的锁调用 fork()
+///
+/// 其他线程可能正在输出日志，fork() 时被持有的锁在子进程中会一直处于锁定状态，子进程第一次输出就会卡住。
+fn fork() -> libc::pid_t {
+    let output = (io::stdout().lock(), io::stderr().lock());
+    let pid = unsafe { libc::fork() };
+    drop(output);
+    pid
+}
+
+/// 解析地址并创建监听套接字，依次尝试解析出的每个地址
+fn bind_listener(addr: &str, backlog: i32, reuse_port: bool) -> io::Result<TcpListener> {
+    let mut last_error = None;
+    for resolved in addr.to_socket_addrs()? {
+        match bind_addr(resolved, backlog, reuse_port) {
+            Ok(listener) => return Ok(listener),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("地址 {} 没有解析出任何结果", addr))))
+}
+
+fn bind_addr(addr: SocketAddr, backlog: i32, reuse_port: bool) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    // 与标准库一致，允许重启后立即绑定仍处于 TIME_WAIT 的端口
+    socket.set_reuse_address(true)?;
+    // 同一地址上的所有监听器都设置了 SO_REUSEPORT 时才能同时绑定
+    socket.set_reuse_port(reuse_port)?;
+    socket.bind(&addr.into())?;
+    socket.listen(backlog)?;
+    Ok(socket.into())
+}
+
+/// 接受任意一个 tokio 监听器上的连接
+async fn accept_any(listeners: &[tokio::net::TcpListener]) -> io::Result<(tokio::net::TcpStream, SocketAddr)> {
+    std::future::poll_fn(|cx| {
+        for listener in listeners {
+            if let Poll::Ready(result) = listener.poll_accept(cx) {
+                return Poll::Ready(result);
+            }
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+/// tokio 模型中多个 accept 循环共用的连接计数
+#[derive(Default)]
+struct AsyncTasks {
+    /// 分配连接ID
+    connection_id: AtomicU32,
+    /// 还没有结束的连接任务
+    active: AtomicUsize,
+}
+
+/// 连接任务结束时减少计数，任务被取消或 panic 时同样生效
+struct ActiveTask(Arc<AsyncTasks>);
+
+impl ActiveTask {
+    fn new(tasks: Arc<AsyncTasks>) -> Self {
+        tasks.active.fetch_add(1, Ordering::SeqCst);
+        ActiveTask(tasks)
+    }
+}
+
+impl Drop for ActiveTask {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// tokio 模型的 accept 循环：接受连接并为每个连接创建任务，关闭时等待自己创建的任务退出
+async fn accept_async<H: AsyncHandler + ?Sized + 'static>(
+    std_listeners: Vec<TcpListener>,
+    shared: Arc<Shared>,
+    acceptor: Option<TlsAcceptor>,
+    handler: Arc<H>,
+    tasks: Arc<AsyncTasks>,
+) -> io::Result<()> {
+    let mut listeners = Vec::with_capacity(std_listeners.len());
+    for std_listener in std_listeners {
+        std_listener.set_nonblocking(true)?;
+        listeners.push(tokio::net::TcpListener::from_std(std_listener)?);
+    }
+    let mut shutdown_rx = shared.shutdown_tx.subscribe();
+    let mut drain_rx = shared.drain_tx.subscribe();
+
+    // 创建任务句柄存储器
+    let mut task_handles: HashMap<u32, tokio::task::JoinHandle<()>> = HashMap::new();
+
+    // 异步处理客户端请求的大循环
+    loop {
+        // 定期清理已完成的任务
+        let completed_tasks: Vec<_> = task_handles
+            .iter()
+            .filter(|(_, handle)| handle.is_finished())
+            .map(|(id, _)| *id)
+            .collect();
+        for id in completed_tasks {
+            if let Some(handle) = task_handles.remove(&id) {
+                // 等待任务完成并处理可能的错误
+                let _ = handle.await;
+                log_info!("[srv] 任务[{}]成功清理", id);
+            }
+        }
+
+        // tokio::select! 允许同时等待多个异步操作，一旦其中任何一个操作完成，就会执行对应的分支。
+        tokio::select! {
+            // 异步操作1 监听新的连接
+            result = accept_any(&listeners) => {
+                match result {
+                    Ok((stream, peer_addr)) => {
+                        log_info!("[srv] client[{}] is accepted!", peer_addr);
+                        let settings = shared.settings();
+                        if settings.at_capacity(tasks.active.load(Ordering::SeqCst)) {
+                            log_info!("[srv] 连接数已达上限，拒绝客户端[{}]", peer_addr);
+                            continue;
+                        }
+
+                        let id = tasks.connection_id.fetch_add(1, Ordering::SeqCst) + 1;
+                        let handler = handler.clone();
+                        let shutdown_rx = shared.shutdown_tx.subscribe();
+                        let drain = shared.drain_tx.subscribe();
+                        let acceptor = acceptor.clone();
+                        let active = ActiveTask::new(tasks.clone());
+
+                        let handle = tokio::spawn(async move {
+                            let _active = active;
+                            let mut ctx = settings.context(id.to_string(), peer_addr, drain);
+                            // 在任务中完成 TLS 握手，避免阻塞 accept 循环
+                            match acceptor {
+                                None => {
+                                    if admit(&ctx, &settings.access) {
+                                        handle_client_async(stream, &ctx, handler.as_ref(), shutdown_rx).await;
+                                    }
+                                }
+                                Some(acceptor) => match acceptor.accept(stream).await {
+                                    Ok(stream) => {
+                                        ctx.peer_identity = tls::peer_identity(stream.get_ref().1);
+                                        log_identity(&ctx);
+                                        if admit(&ctx, &settings.access) {
+                                            handle_client_async(stream, &ctx, handler.as_ref(), shutdown_rx).await;
+                                        }
+                                    }
+                                    Err(e) => eprintln!("[{}] 与客户端 {} 的 TLS 握手失败: {}", ctx.id, ctx.peer_addr, e),
+                                },
+                            }
+                        });
+
+                        // 将任务句柄存储到连接管理器中
+                        task_handles.insert(id, handle);
+                    }
+                    Err(e) => {
+                        eprintln!("接受连接失败: {}", e);
+                    }
+                }
+            }
+            // 异步操作2 等待关闭通知
+            _ = wait_shutdown(&mut shutdown_rx) => {
+                log_info!("[srv] select 收到关闭通知");
+                break;
+            }
+            // 异步操作3 等待优雅关闭通知，已有的任务自己处理完当前请求后退出
+            _ = wait_shutdown(&mut drain_rx) => {
+                log_info!("[srv] select 收到优雅关闭通知");
+                break;
+            }
+        }
+    }
+
+    // 等待所有任务退出，强制关闭时取消仍在运行的任务
+    log_info!("等待所有通信任务退出...");
+    for (id, mut handle) in task_handles {
+        log_info!("[srv] 等待任务[{}]退出", id);
+        tokio::select! {
+            _ = &mut handle => {}
+            _ = wait_shutdown(&mut shutdown_rx) => handle.abort(), // 取消任务
+        }
+        log_info!("[srv] 任务[{}]已退出", id)
+    }
+    Ok(())
+}
+
+/// 记录 mTLS 连接上客户端证书的身份
+fn log_identity(ctx: &ConnContext) {
+    if let Some(identity) = &ctx.peer_identity {
+        log_info!("[{}] 客户端 {} 的证书: {}", ctx.id, ctx.peer_addr, identity);
+    }
+}
+
+/// 按允许/拒绝列表检查对端身份，拒绝时记录原因
+fn admit(ctx: &ConnContext, access: &AccessList) -> bool {
+    if access.is_allowed(ctx.peer_identity.as_ref()) {
+        return true;
+    }
+    match &ctx.peer_identity {
+        Some(identity) => eprintln!("[{}] 拒绝客户端 {} 的连接: {}", ctx.id, ctx.peer_addr, identity),
+        None => eprintln!("[{}] 拒绝客户端 {} 的连接: 没有客户端证书", ctx.id, ctx.peer_addr),
+    }
+    false
+}
+
+/// 各个线程之间共享的服务器状态
+struct Shared {
+    shutdown: AtomicBool,
+    /// 正在等待热升级的新进程就绪
+    upgrading: AtomicBool,
+    /// 已经把监听套接字交给了新进程，关闭时不再通知 systemd 服务正在停止
+    handed_over: AtomicBool,
+    /// 热升级时交给新进程的监听套接字
+    listen_fds: Vec<RawFd>,
+    /// 关闭时写入一个字节，唤醒等待连接的 accept()
+    waker: UnixStream,
+    /// 活动连接，为了在关闭时唤醒阻塞中的线程
+    connections: Mutex<HashMap<u64, TcpStream>>,
+    /// 多进程模型下的子进程ID
+    child_pids: Mutex<HashSet<i32>>,
+    /// tokio 模型下通知所有任务退出
+    shutdown_tx: watch::Sender<bool>,
+    /// 开始优雅关闭时变为 true，通知所有连接处理完当前请求后退出
+    drain_tx: watch::Sender<bool>,
+    /// 当前的运行期设置，新连接建立时取一份快照
+    settings: RwLock<Arc<Settings>>,
+    reload: Option<ReloadFn>,
+}
+
+impl Shared {
+    fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+
+    fn is_draining(&self) -> bool {
+        *self.drain_tx.borrow()
+    }
+
+    /// 正在优雅关闭或已经关闭，不再接受新连接
+    fn is_stopping(&self) -> bool {
+        self.is_shutdown() || self.is_draining()
+    }
+
+    /// 开始优雅关闭：停止接受新连接，关闭所有连接的读端，超过 drain_timeout 后强制关闭
+    fn drain(self: &Arc<Self>) {
+        if self.is_shutdown() || self.drain_tx.send_replace(true) {
+            return;
+        }
+        let timeout = self.settings().drain_timeout;
+        log_info!("[srv] 停止接受新连接，最多等待 {:?} 后关闭剩余连接", timeout);
+        if !self.handed_over.load(Ordering::SeqCst) {
+            notify_systemd("STOPPING=1");
+        }
+
+        // 阻塞在 read() 上的连接会立即返回，正在处理的请求可以继续写回响应
+        for stream in self.connections.lock().unwrap().values() {
+            let _ = stream.shutdown(Shutdown::Read);
+        }
+        for pid in self.child_pids.lock().unwrap().iter() {
+            unsafe { libc::kill(*pid, SIGTERM); }
+        }
+        self.wake();
+
+        // 服务器在期限之前退出时 run() 会设置 shutdown 标志，线程随之结束
+        let shared = self.clone();
+        std::thread::spawn(move || {
+            let deadline = Instant::now() + timeout;
+            while !shared.is_shutdown() {
+                let now = Instant::now();
+                if now >= deadline {
+                    log_info!("[srv] 等待超时，强制关闭剩余连接");
+                    shared.shutdown();
+                    break;
+                }
+                std::thread::sleep((deadline - now).min(Duration::from_millis(50)));
+            }
+        });
+    }
+
+    fn shutdown(&self) {
+        if self.shutdown.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        // 优雅关闭时已经通知过
+        if !self.is_draining() && !self.handed_over.load(Ordering::SeqCst) {
+            notify_systemd("STOPPING=1");
+        }
+
+        log_info!("[srv] 关闭所有活动连接");
+        for stream in self.connections.lock().unwrap().values() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+
+        let child_pids = self.child_pids.lock().unwrap();
+        if !child_pids.is_empty() {
+            log_info!("[srv] 向所有子进程发送SIGINT");
+            for pid in child_pids.iter() {
+                unsafe { libc::kill(*pid, SIGINT); }
+            }
+        }
+        drop(child_pids);
+
+        self.shutdown_tx.send_replace(true);
+        self.wake();
+    }
+
+    fn wake(&self) {
+        // 已经有未读的字节时写入失败也同样能唤醒
+        let _ = (&self.waker).write(&[1]);
+    }
+
+    /// 启动新进程并交出监听套接字，新进程就绪后优雅关闭，失败时继续运行
+    fn upgrade(self: &Arc<Self>) {
+        if self.is_stopping() || self.upgrading.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        // 等待新进程就绪可能需要一段时间，不阻塞信号处理
+        let shared = self.clone();
+        std::thread::spawn(move || {
+            log_info!("[srv] 启动新进程接管监听套接字");
+            match upgrade::spawn(&shared.listen_fds) {
+                Ok(pid) => {
+                    log_info!("[srv] 新进程[{}]已就绪", pid);
+                    shared.handed_over.store(true, Ordering::SeqCst);
+                    shared.drain();
+                }
+                Err(e) => eprintln!("[srv] 热升级失败，继续运行: {}", e),
+            }
+            shared.upgrading.store(false, Ordering::SeqCst);
+        });
+    }
+
+    /// 登记一个活动连接，若服务器已在关闭则立即关闭该连接
+    fn track(&self, id: u64, stream: &TcpStream) -> io::Result<()> {
+        self.connections.lock().unwrap().insert(id, stream.try_clone()?);
+        // shutdown() 与 drain() 先置位再遍历连接，这里先登记再检查，保证不会漏掉
+        if self.is_shutdown() {
+            let _ = stream.shutdown(Shutdown::Both);
+        } else if self.is_draining() {
+            let _ = stream.shutdown(Shutdown::Read);
+        }
+        Ok(())
+    }
+
+    fn settings(&self) -> Arc<Settings> {
+        self.settings.read().unwrap().clone()
+    }
+
+    fn update(&self, settings: Settings) {
+        if let Some(level) = settings.log_level {
+            log::set_level(level);
+        }
+        *self.settings.write().unwrap() = Arc::new(settings);
+        // 预派生模型的主进程需要据此替换工作进程
+        self.wake();
+    }
+
+    fn reload(&self) -> Result<(), ConfigError> {
+        let Some(reload) = &self.reload else {
+            log_info!("[srv] 没有可以重新加载的配置");
+            return Ok(());
+        };
+        let settings = reload()?;
+        settings.validate().map_err(ConfigError::Invalid)?;
+        self.update(settings);
+        log_info!("[srv] 配置已重新加载");
+        Ok(())
+    }
+
+    fn untrack(&self, id: u64) {
+        self.connections.lock().unwrap().remove(&id);
+    }
+
+    fn reap_children(&self) {
+        let mut child_pids = self.child_pids.lock().unwrap();
+        loop {
+            let pid = unsafe { libc::waitpid(-1, std::ptr::null_mut(), libc::WNOHANG) };
+            if pid <= 0 {
+                break;
+            }
+            log_info!("[srv] 收到子进程[{}]的退出信号", pid);
+            child_pids.remove(&pid);
+        }
+    }
+}
+
+/// 可嵌入的 TCP 服务器
+///
+/// `run()` 会阻塞直到服务器关闭，可以在其他线程中通过 `shutdown()` 关闭服务器：
+///
+/// ```no_run
+/// use std::sync::Arc;
+/// use socket::network_handler::Echo;
+/// use socket::server::{Model, Server};
+///
+/// let server = Arc::new(Server::builder().bind("127.0.0.1:0").model(Model::ThreadPerConnection).handler(Echo).build().unwrap());
+/// let runner = { let server = server.clone(); std::thread::spawn(move || server.run()) };
+/// server.shutdown();
+/// runner.join().unwrap().unwrap();
+/// ```
+pub struct Server {
+    listeners: Vec<TcpListener>,
+    model: Model,
+    handler: HandlerKind,
+    handle_signals: bool,
+    tls: Option<Arc<ServerConfig>>,
+    backlog: i32,
+    workers: usize,
+    queue_depth: usize,
+    reuse_port: bool,
+    /// [`Shared::wake`] 写入的另一端
+    wake_rx: UnixStream,
+    shared: Arc<Shared>,
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    /// 第一个监听地址
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listeners[0].local_addr()
+    }
+
+    /// 所有监听地址，顺序与 [`ServerBuilder::bind`] 的调用顺序一致
+    pub fn local_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        self.listeners.iter().map(TcpListener::local_addr).collect()
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    /// 当前的运行期设置
+    pub fn settings(&self) -> Arc<Settings> {
+        self.shared.settings()
+    }
+
+    /// 替换运行期设置，只影响之后接受的连接
+    pub fn update_settings(&self, settings: Settings) {
+        self.shared.update(settings);
+    }
+
+    /// 用 [`ServerBuilder::reload`] 设置的函数重新读取设置，失败时保持原来的设置
+    pub fn reload(&self) -> Result<(), ConfigError> {
+        self.shared.reload()
+    }
+
+    /// 请求服务器关闭：停止接受新连接并断开所有活动连接
+    pub fn shutdown(&self) {
+        self.shared.shutdown();
+    }
+
+    /// 请求服务器优雅关闭：停止接受新连接，每个连接处理完当前的请求后发送
+    /// [`GOING_AWAY`](crate::network_handler::GOING_AWAY) 并关闭，超过 drain_timeout 后强制关闭剩余连接
+    pub fn drain(&self) {
+        self.shared.drain();
+    }
+
+    /// 热升级：用同样的参数重新执行当前程序并交出监听套接字，新进程就绪后本进程优雅关闭
+    ///
+    /// 在后台完成，新进程启动失败时本进程继续运行。
+    pub fn upgrade(&self) {
+        self.shared.upgrade();
+    }
+
+    /// 运行服务器，直到 `shutdown()` 被调用（或收到 SIGINT）
+    pub fn run(&self) -> io::Result<()> {
+        // 先注册信号处理，启动信息打印之后收到的 SIGINT 都能正常关闭服务器
+        let signals_handle = if self.handle_signals {
+            Some(self.spawn_signal_thread()?)
+        } else {
+            None
+        };
+
+        for addr in self.local_addrs()? {
+            log_info!("[srv] server[{}] is initializing!", addr);
+        }
+        // 由热升级启动时通知旧进程开始优雅关闭
+        upgrade::notify_ready();
+        // 热升级后由新进程接替成为 systemd 的主进程
+        notify_systemd(&format!("READY=1\nMAINPID={}", std::process::id()));
+        if let Some(interval) = systemd::watchdog_interval() {
+            self.spawn_watchdog(interval);
+        }
+
+        let result = match (&self.handler, self.model) {
+            (HandlerKind::Sync(handler), Model::Single) => self.run_single(handler.as_ref()),
+            (HandlerKind::Sync(handler), Model::ThreadPerConnection) => self.run_threads(handler),
+            (HandlerKind::Sync(handler), Model::ProcessPerConnection) => self.run_processes(handler.as_ref()),
+            (HandlerKind::Sync(handler), Model::Tokio) => self.run_tokio(handler.clone()),
+            (HandlerKind::Async(handler), Model::Tokio) => self.run_tokio(handler.clone()),
+            (HandlerKind::Sync(handler), Model::Epoll) => self.run_epoll(handler.as_ref()),
+            (HandlerKind::Sync(handler), Model::Poll) => self.run_multiplexed(handler.as_ref(), reactor::Poll::default()),
+            (HandlerKind::Sync(handler), Model::Select) => self.run_multiplexed(handler.as_ref(), reactor::Select),
+            (HandlerKind::Sync(handler), Model::IoUring) => self.run_io_uring(handler.as_ref()),
+            (HandlerKind::Sync(handler), Model::ThreadPool) => self.run_thread_pool(handler),
+            (HandlerKind::Sync(handler), Model::Prefork) => self.run_prefork(handler.as_ref()),
+            (HandlerKind::Async(_), _) => unreachable!("build() 已经检查过处理器与模型"),
+        };
+
+        // 通知优雅关闭的计时线程服务器已经退出
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        if let Some(handle) = signals_handle {
+            handle.close();
+        }
+        result
+    }
+
+    /// 定期向 systemd 发送看门狗通知，直到服务器关闭
+    fn spawn_watchdog(&self, interval: Duration) {
+        let shared = self.shared.clone();
+        std::thread::spawn(move || {
+            let mut next = Instant::now();
+            while !shared.is_shutdown() {
+                if Instant::now() >= next {
+                    notify_systemd("WATCHDOG=1");
+                    next += interval;
+                }
+                std::thread::sleep(interval.min(Duration::from_millis(50)));
+            }
+        });
+    }
+
+    // rust 中捕获SIGPIPE信号是一个unstable的功能，标准库默认忽略SIGPIPE，写入已关闭的连接会返回 EPIPE 错误
+    // https://github.com/rust-lang/rust/pull/13158 native: Ignore SIGPIPE by default
+    // https://dev-doc.rust-lang.org/beta/unstable-book/language-features/unix-sigpipe.html#unix_sigpipe
+    // https://github.com/rust-lang/rust/issues/62569
+    // https://github.com/rust-lang/rust/pull/124480
+    fn spawn_signal_thread(&self) -> io::Result<signal_hook::iterator::Handle> {
+        let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP, SIGUSR2])?;
+        let handle = signals.handle();
+        let shared = self.shared.clone();
+
+        // 在单独的线程中处理信号，避免阻塞主线程
+        std::thread::spawn(move || {
+            for sig in signals.forever() {
+                match sig {
+                    SIGINT | SIGTERM if shared.is_stopping() => {
+                        log_info!("[srv] 关闭过程中再次收到信号，立即退出");
+                        shared.shutdown();
+                        std::process::exit(1);
+                    }
+                    SIGTERM => {
+                        log_info!("[srv] SIGTERM is coming!");
+                        shared.drain();
+                    }
+                    SIGINT => {
+                        log_info!("[srv] SIGINT is coming!");
+                        shared.shutdown();
+                    }
+                    SIGHUP => {
+                        log_info!("[srv] SIGHUP is coming!");
+                        // 已有的连接不受影响，失败时保持原来的设置
+                        if let Err(e) = shared.reload() {
+                            eprintln!("[srv] 重新加载配置失败，继续使用原来的配置: {}", e);
+                        }
+                    }
+                    SIGUSR2 => {
+                        log_info!("[srv] SIGUSR2 is coming!");
+                        shared.upgrade();
+                    }
+                    _ => {}
+                }
+            }
+        });
+        Ok(handle)
+    }
+
+    /// 按配置先完成 TLS 握手并检查对端身份，再交给 handle_client 处理
+    fn serve(tls: Option<&Arc<ServerConfig>>, stream: TcpStream, mut ctx: ConnContext, access: &AccessList, handler: &dyn Handler) {
+        let Some(config) = tls else {
+            if admit(&ctx, access) {
+                handle_client(stream, &ctx, handler);
+            }
+            return;
+        };
+        match ServerTlsStream::accept(stream, config.clone()) {
+            Ok(stream) => {
+                ctx.peer_identity = stream.peer_identity();
+                log_identity(&ctx);
+                if admit(&ctx, access) {
+                    handle_client(stream, &ctx, handler);
+                }
+            }
+            Err(e) => eprintln!("[{}] 与客户端 {} 的 TLS 握手失败: {}", ctx.id, ctx.peer_addr, e),
+        }
+    }
+
+    /// 用 poll() 等待任意一个监听器上的连接，被 [`Shared::wake`] 唤醒时返回 None
+    fn accept(&self) -> io::Result<Option<(TcpStream, SocketAddr)>> {
+        self.accept_from(&self.listeners)
+    }
+
+    /// 与 [`Server::accept`] 相同，但等待的是指定的监听器
+    fn accept_from(&self, listeners: &[TcpListener]) -> io::Result<Option<(TcpStream, SocketAddr)>> {
+        let mut fds: Vec<libc::pollfd> = std::iter::once(self.wake_rx.as_raw_fd())
+            .chain(listeners.iter().map(AsRawFd::as_raw_fd))
+            .map(|fd| libc::pollfd { fd, events: libc::POLLIN, revents: 0 })
+            .collect();
+        loop {
+            if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(e);
+            }
+            if fds[0].revents != 0 {
+                // 关闭时不取走唤醒的字节，多个线程同时等待时都能被唤醒
+                if !self.shared.is_stopping() {
+                    let _ = (&self.wake_rx).read(&mut [0_u8; 16]);
+                }
+                return Ok(None);
+            }
+            for (listener, fd) in listeners.iter().zip(&mut fds[1..]) {
+                if std::mem::take(&mut fd.revents) == 0 {
+                    continue;
+                }
+                match listener.accept() {
+                    Ok((stream, peer_addr)) => {
+                        // 监听套接字是非阻塞的，连接需要恢复为阻塞模式
+                        stream.set_nonblocking(false)?;
+                        return Ok(Some((stream, peer_addr)));
+                    }
+                    // 连接被共用监听套接字的其他进程取走了
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+    }
+
+    /// 第 slot 个工作线程、工作进程或 accept 循环使用的 SO_REUSEPORT 监听器
+    ///
+    /// 共用的监听套接字同样参与内核的分配，它由 Server 一直持有以便热升级与 systemd 交接，
+    /// 由第 0 个使用；其余的在每个监听地址上新打开自己的监听器。
+    fn reuse_port_listeners(&self, slot: usize) -> io::Result<Vec<TcpListener>> {
+        if slot == 0 {
+            return self.listeners.iter().map(TcpListener::try_clone).collect();
+        }
+        let mut listeners = Vec::with_capacity(self.listeners.len());
+        for listener in &self.listeners {
+            let shard = bind_addr(listener.local_addr()?, self.backlog, true)?;
+            shard.set_nonblocking(true)?;
+            listeners.push(shard);
+        }
+        Ok(listeners)
+    }
+
+    /// 在 listeners 上逐个接受并处理连接，直到服务器开始关闭；name 用于日志与连接上下文
+    fn accept_serially(&self, name: &str, listeners: &[TcpListener], connection_id: &AtomicU64, handler: &dyn Handler) {
+        loop {
+            if self.shared.is_stopping() {
+                break;
+            }
+            match self.accept_from(listeners) {
+                Ok(None) => continue,
+                Ok(Some((stream, peer_addr))) => {
+                    log_info!("[{}] client[{}] is accepted!", name, peer_addr);
+                    let settings = self.shared.settings();
+                    if settings.at_capacity(self.shared.connections.lock().unwrap().len()) {
+                        log_info!("[{}] 连接数已达上限，拒绝客户端[{}]", name, peer_addr);
+                        continue;
+                    }
+                    let id = connection_id.fetch_add(1, Ordering::SeqCst);
+                    if let Err(e) = self.shared.track(id, &stream) {
+                        eprintln!("[{}] 无法登记客户端[{}]的连接，关闭连接: {}", name, peer_addr, e);
+                        continue;
+                    }
+                    let ctx = settings.context(name, peer_addr, self.shared.drain_tx.subscribe());
+                    Server::serve(self.tls.as_ref(), stream, ctx, &settings.access, handler);
+                    self.shared.untrack(id);
+                }
+                Err(e) => {
+                    eprintln!("[{}] 接受连接失败: {}", name, e);
+                }
+            }
+        }
+    }
+
+    fn run_single(&self, handler: &dyn Handler) -> io::Result<()> {
+        // 处理客户端请求的大循环
+        loop {
+            if self.shared.is_stopping() {
+                log_info!("检测到关闭请求，准备退出...");
+                break;
+            }
+
+            match self.accept() {
+                Ok(None) => continue,
+                Ok(Some((stream, peer_addr))) => {
+                    log_info!("[srv] client[{}] is accepted!", peer_addr);
+                    // 文件描述符耗尽等错误只影响这个连接，关闭它后继续接受
+                    if let Err(e) = self.shared.track(0, &stream) {
+                        eprintln!("[srv] 无法登记客户端[{}]的连接，关闭连接: {}", peer_addr, e);
+                        continue;
+                    }
+                    let settings = self.shared.settings();
+                    let ctx = settings.context("srv", peer_addr, self.shared.drain_tx.subscribe());
+                    Server::serve(self.tls.as_ref(), stream, ctx, &settings.access, handler);
+                    self.shared.untrack(0);
+                }
+                Err(e) => {
+                    eprintln!("接受连接失败: {}", e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn run_threads(&self, handler: &Arc<dyn Handler>) -> io::Result<()> {
+        // 创建线程句柄存储器
+        let mut thread_handles: HashMap<u64, std::thread::JoinHandle<()>> = HashMap::new();
+        let mut connection_id: u64 = 0;
+
+        // 多线程 处理客户端请求的大循环
+        loop {
+            if self.shared.is_stopping() {
+                log_info!("检测到关闭请求，准备退出...");
+                break;
+            }
+
+            // 定期清理已完成的线程
+            let finished_threads: Vec<_> = thread_handles
+                .iter()
+                .filter(|(_, handle)| handle.is_finished())
+                .map(|(id, _)| *id)
+                .collect();
+            for id in finished_threads {
+                if let Some(handle) = thread_handles.remove(&id) {
+                    let tid = handle.thread().id();
+                    match handle.join() {
+                        Ok(_) => log_info!("[srv] 子线程[{:?}]成功join", tid),
+                        Err(e) => eprintln!("[srv] 子线程[{:?}] join失败: {:?}", tid, e),
+                    }
+                }
+            }
+
+            match self.accept() { // 没有连接时阻塞在 poll() 上，被信号中断时自动重试
+                Ok(None) => continue,
+                Ok(Some((stream, peer_addr))) => {
+                    log_info!("[srv] client[{}] is accepted!", peer_addr);
+                    let settings = self.shared.settings();
+                    if settings.at_capacity(self.shared.connections.lock().unwrap().len()) {
+                        log_info!("[srv] 连接数已达上限，拒绝客户端[{}]", peer_addr);
+                        continue;
+                    }
+
+                    connection_id += 1;
+                    let id = connection_id;
+                    if let Err(e) = self.shared.track(id, &stream) {
+                        eprintln!("[srv] 无法登记客户端[{}]的连接，关闭连接: {}", peer_addr, e);
+                        continue;
+                    }
+
+                    // 克隆需要传递给线程的变量
+                    let shared = self.shared.clone();
+                    let handler = handler.clone();
+                    let tls = self.tls.clone();
+                    let drain = self.shared.drain_tx.subscribe();
+
+                    // 创建新线程处理客户端请求
+                    let handle = std::thread::spawn(move || {
+                        let ctx = settings.context(format!("{:?}", std::thread::current().id()), peer_addr, drain);
+                        Server::serve(tls.as_ref(), stream, ctx, &settings.access, handler.as_ref());
+                        // 从连接管理器中移除已处理的连接
+                        shared.untrack(id);
+                    });
+                    thread_handles.insert(id, handle);
+                }
+                Err(e) => {
+                    eprintln!("接受连接失败: {}", e);
+                }
+            }
+        }
+
+        // 等待所有子线程退出
+        log_info!("等待所通信子线程退出...");
+        for (_, handle) in thread_handles {
+            let tid = handle.thread().id();
+            if let Err(e) = handle.join() {
+                eprintln!("线程等待出错: {:?}", e);
+            }
+            log_info!("[srv] 子线程[{:?}]成功join", tid);
+        }
+        Ok(())
+    }
+
+    fn run_thread_pool(&self, handler: &Arc<dyn Handler>) -> io::Result<()> {
+        if self.reuse_port {
+            return self.run_sharded_pool(handler.as_ref());
+        }
+        // 交给线程池但还没有处理完的连接数（正在处理的加上排队的），超过容量时通知客户端服务器繁忙
+        let capacity = self.workers + self.queue_depth;
+        let assigned = Arc::new(AtomicUsize::new(0));
+        let (queue, jobs) = mpsc::sync_channel::<(u64, TcpStream, SocketAddr, Arc<Settings>)>(capacity);
+        let jobs = Arc::new(Mutex::new(jobs));
+        let mut workers = Vec::with_capacity(self.workers);
+        for index in 0..self.workers {
+            let jobs = jobs.clone();
+            let assigned = assigned.clone();
+            let shared = self.shared.clone();
+            let handler = handler.clone();
+            let tls = self.tls.clone();
+            let worker = std::thread::Builder::new().name(format!("worker-{}", index)).spawn(move || {
+                loop {
+                    // 空闲的工作线程排队等锁，持有锁的线程等待下一个连接
+                    let job = jobs.lock().unwrap().recv();
+                    // 发送端已经关闭，线程池退出
+                    let Ok((id, stream, peer_addr, settings)) = job else {
+                        break;
+                    };
+                    let ctx = settings.context(format!("worker-{}", index), peer_addr, shared.drain_tx.subscribe());
+                    Server::serve(tls.as_ref(), stream, ctx, &settings.access, handler.as_ref());
+                    shared.untrack(id);
+                    assigned.fetch_sub(1, Ordering::SeqCst);
+                }
+            })?;
+            workers.push(worker);
+        }
+        log_info!("[srv] 线程池: {} 个工作线程，最多 {} 个连接排队", self.workers, self.queue_depth);
+        let mut connection_id: u64 = 0;
+
+        loop {
+            if self.shared.is_stopping() {
+                log_info!("检测到关闭请求，准备退出...");
+                break;
+            }
+
+            match self.accept() {
+                Ok(None) => continue,
+                Ok(Some((stream, peer_addr))) => {
+                    log_info!("[srv] client[{}] is accepted!", peer_addr);
+                    let settings = self.shared.settings();
+                    if settings.at_capacity(self.shared.connections.lock().unwrap().len()) {
+                        log_info!("[srv] 连接数已达上限，拒绝客户端[{}]", peer_addr);
+                        continue;
+                    }
+
+                    if assigned.load(Ordering::SeqCst) >= capacity {
+                        log_info!("[srv] 线程池已满，通知客户端[{}]服务器繁忙", peer_addr);
+                        self.reject_busy(stream, &settings.format);
+                        continue;
+                    }
+
+                    connection_id += 1;
+                    // 排队中的连接同样要在关闭时断开
+                    if let Err(e) = self.shared.track(connection_id, &stream) {
+                        eprintln!("[srv] 无法登记客户端[{}]的连接，关闭连接: {}", peer_addr, e);
+                        continue;
+                    }
+                    assigned.fetch_add(1, Ordering::SeqCst);
+                    // 只有这里增加计数，队列的长度等于容量，发送不会阻塞
+                    if queue.send((connection_id, stream, peer_addr, settings)).is_err() {
+                        return Err(io::Error::other("线程池的工作线程都已退出"));
+                    }
+                }
+                Err(e) => {
+                    eprintln!("接受连接失败: {}", e);
+                }
+            }
+        }
+
+        // 工作线程处理完排队的连接后退出
+        drop(queue);
+        log_info!("等待工作线程退出...");
+        for worker in workers {
+            let tid = worker.thread().id();
+            if let Err(e) = worker.join() {
+                eprintln!("线程等待出错: {:?}", e);
+            }
+            log_info!("[srv] 工作线程[{:?}]成功join", tid);
+        }
+        Ok(())
+    }
+
+    /// 启用 SO_REUSEPORT 分片的线程池：每个工作线程在自己的监听器上接受并处理连接，连接在内核的监听队列中等待
+    fn run_sharded_pool(&self, handler: &dyn Handler) -> io::Result<()> {
+        let shards = (0..self.workers).map(|slot| self.reuse_port_listeners(slot)).collect::<io::Result<Vec<_>>>()?;
+        log_info!("[srv] 线程池: {} 个工作线程，各自在 SO_REUSEPORT 监听器上接受连接", self.workers);
+        let connection_id = AtomicU64::new(0);
+
+        std::thread::scope(|scope| {
+            let mut workers = Vec::with_capacity(shards.len());
+            for (index, listeners) in shards.into_iter().enumerate() {
+                let connection_id = &connection_id;
+                let worker = std::thread::Builder::new().name(format!("worker-{}", index)).spawn_scoped(scope, move || {
+                    self.accept_serially(&format!("worker-{}", index), &listeners, connection_id, handler);
+                })?;
+                workers.push(worker);
+            }
+
+            // 工作线程在服务器开始关闭后处理完当前连接退出
+            log_info!("等待工作线程退出...");
+            for worker in workers {
+                let tid = worker.thread().id();
+                if let Err(e) = worker.join() {
+                    eprintln!("线程等待出错: {:?}", e);
+                }
+                log_info!("[srv] 工作线程[{:?}]成功join", tid);
+            }
+            Ok(())
+        })
+    }
+
+    /// 没有空闲容量时发送 [`SERVER_BUSY`](crate::network_handler::SERVER_BUSY) 后关闭连接
+    ///
+    /// 不进行 TLS 握手与认证，TLS 连接上直接关闭；v1 格式没有控制消息，同样直接关闭。
+    fn reject_busy(&self, stream: TcpStream, format: &FrameFormat) {
+        if self.tls.is_some() {
+            return;
+        }
+        let Some(pdu) = Pdu::server_busy(format) else {
+            return;
+        };
+        // 新连接的发送缓冲为空，写入不会阻塞接受新连接
+        if let Err(e) = pdu.to_vec(format).and_then(|bytes| Ok((&stream).write_all(&bytes)?)) {
+            eprintln!("[srv] 无法通知客户端服务器繁忙: {}", e);
+            return;
+        }
+        // 关闭时接收缓冲中还有未读的请求会发送 RST，客户端可能来不及读到通知
+        let _ = stream.shutdown(Shutdown::Write);
+        if stream.set_nonblocking(true).is_ok() {
+            while (&stream).read(&mut [0_u8; 1024]).is_ok_and(|size| size > 0) {}
+        }
+    }
+
+    fn run_processes(&self, handler: &dyn Handler) -> io::Result<()> {
+        // 无论是否由服务器处理 SIGINT，都必须回收退出的子进程
+        let mut signals = Signals::new([SIGCHLD,])?;
+        let signals_handle = signals.handle();
+        let shared = self.shared.clone();
+        let reaper = std::thread::spawn(move || {
+            for _ in signals.forever() {
+                log_info!("[srv] SIGCHLD is coming!");
+                shared.reap_children();
+            }
+        });
+
+        // 多进程 处理客户端请求的大循环
+        loop {
+            if self.shared.is_stopping() {
+                log_info!("检测到关闭请求，准备退出...");
+                break;
+            }
+
+            match self.accept() { // 没有连接时阻塞在 poll() 上，被信号中断时自动重试
+                Ok(None) => continue,
+                Ok(Some((stream, peer_addr))) => {
+                    log_info!("[srv] client[{}] is accepted!", peer_addr);
+
+                    // 持有锁直到登记完子进程，避免子进程在登记前退出导致无法回收
+                    let mut child_pids = self.shared.child_pids.lock().unwrap();
+                    if self.shared.settings().at_capacity(child_pids.len()) {
+                        log_info!("[srv] 连接数已达上限，拒绝客户端[{}]", peer_addr);
+                        continue;
+                    }
+
+                    // 创建子进程处理客户端请求
+                    match fork() {
+                        0 => {
+                            // 子进程
+                            self.run_child(stream, peer_addr, handler);
+                        }
+                        pid if pid > 0 => {
+                            // 父进程
+                            // 父进程不需要这个连接，关闭它
+                            drop(stream);
+                            log_info!("[srv] 创建子进程[{}]", pid);
+                            child_pids.insert(pid);
+                            if self.shared.is_shutdown() {
+                                unsafe { libc::kill(pid, SIGINT); }
+                            } else if self.shared.is_draining() {
+                                unsafe { libc::kill(pid, SIGTERM); }
+                            }
+                        }
+                        _ => {
+                            eprintln!("创建子进程失败: {}", io::Error::last_os_error());
+                        }
+                    }
+                }
+                Err(e) => {
+                    eprintln!("接受连接失败: {}", e);
+                }
+            }
+        }
+
+        // 等待所有进程结束
+        log_info!("等待通信子进程退出...");
+        while !self.shared.child_pids.lock().unwrap().is_empty() {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            self.shared.reap_children();
+        }
+
+        signals_handle.close();
+        let _ = reaper.join();
+        Ok(())
+    }
+
+    fn run_child(&self, stream: TcpStream, peer_addr: SocketAddr, handler: &dyn Handler) -> ! {
+        let pid = std::process::id();
+        unsafe {
+            // 关闭不需要的资源
+            for listener in &self.listeners {
+                libc::close(listener.as_raw_fd());
+            }
+        }
+        reset_signal_handlers();
+
+        // 创建子进程的信号处理器
+        let stream_clone = stream.try_clone().expect("无法复制连接");
+        let shared = self.shared.clone();
+        let mut signals = Signals::new([SIGINT, SIGTERM]).expect("无法创建信号处理器");
+        std::thread::spawn(move || {
+            for sig in signals.forever() {
+                if sig == SIGTERM {
+                    // 处理完当前的请求后退出
+                    log_info!("[{}] SIGTERM is coming!", pid);
+                    shared.drain_tx.send_replace(true);
+                    let _ = stream_clone.shutdown(Shutdown::Read);
+                } else {
+                    // 收到 SIGINT 后立即断开连接，随后退出信号监听
+                    log_info!("[{}] SIGINT is coming!", pid);
+                    let _ = stream_clone.shutdown(Shutdown::Both);
+                    break;
+                }
+            }
+        });
+
+        // 子进程使用 fork() 时父进程中的设置
+        let settings = self.shared.settings();
+        let ctx = settings.context(pid.to_string(), peer_addr, self.shared.drain_tx.subscribe());
+        Server::serve(self.tls.as_ref(), stream, ctx, &settings.access, handler);
+
+        // 子进程退出
+        log_info!("[{}] 子进程退出", pid);
+        unsafe { libc::exit(0) }
+    }
+
+    fn run_prefork(&self, handler: &dyn Handler) -> io::Result<()> {
+        // 目标工作进程数：收到 SIGTTIN 时加一，收到 SIGTTOU 时减一，至少保留一个
+        let target = Arc::new(AtomicUsize::new(self.workers));
+        let mut signals = Signals::new([SIGCHLD, SIGTTIN, SIGTTOU])?;
+        let signals_handle = signals.handle();
+        let monitor = std::thread::spawn({
+            let shared = self.shared.clone();
+            let target = target.clone();
+            move || {
+                for sig in signals.forever() {
+                    match sig {
+                        SIGCHLD => {
+                            log_info!("[srv] SIGCHLD is coming!");
+                            shared.reap_children();
+                        }
+                        SIGTTIN => {
+                            let workers = target.fetch_add(1, Ordering::SeqCst) + 1;
+                            log_info!("[srv] SIGTTIN is coming! 工作进程数调整为 {}", workers);
+                        }
+                        SIGTTOU => match target.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| (n > 1).then(|| n - 1)) {
+                            Ok(workers) => log_info!("[srv] SIGTTOU is coming! 工作进程数调整为 {}", workers - 1),
+                            Err(_) => log_info!("[srv] SIGTTOU is coming! 至少保留一个工作进程"),
+                        },
+                        _ => {}
+                    }
+                    // 由主循环补齐或减少工作进程
+                    shared.wake();
+                }
+            }
+        });
+        log_info!("[srv] 预派生: {} 个工作进程", self.workers);
+
+        // 已经要求退出、还没有被回收的工作进程
+        let mut retiring = HashSet::new();
+        // 工作进程的编号，决定 SO_REUSEPORT 分片时使用哪个监听器
+        let mut slots = HashMap::new();
+        // 工作进程在 fork() 时复制了设置，更新后逐个换成新的工作进程
+        let mut settings = self.shared.settings();
+        loop {
+            if self.shared.is_stopping() {
+                log_info!("检测到关闭请求，准备退出...");
+                break;
+            }
+            let current = self.shared.settings();
+            if !Arc::ptr_eq(&settings, &current) {
+                settings = current;
+                self.retire_workers(&mut retiring);
+            }
+            // 创建工作进程失败时稍后重试
+            let timeout = if self.scale_workers(target.load(Ordering::SeqCst), &mut retiring, &mut slots, handler) { -1 } else { 1000 };
+            self.wait_wake(timeout)?;
+        }
+
+        // 关闭请求已经由 shutdown() 或 drain() 转发给所有工作进程
+        log_info!("等待工作进程退出...");
+        while !self.shared.child_pids.lock().unwrap().is_empty() {
+            std::thread::sleep(Duration::from_millis(100));
+            self.shared.reap_children();
+        }
+
+        signals_handle.close();
+        let _ = monitor.join();
+        Ok(())
+    }
+
+    /// 要求所有运行中的工作进程处理完当前连接后退出，随后由 [`scale_workers`](Self::scale_workers) 补齐
+    fn retire_workers(&self, retiring: &mut HashSet<i32>) {
+        let child_pids = self.shared.child_pids.lock().unwrap();
+        retiring.retain(|pid| child_pids.contains(pid));
+        log_info!("[srv] 设置已更新，替换 {} 个工作进程", child_pids.len() - retiring.len());
+        for &pid in child_pids.iter() {
+            if retiring.insert(pid) {
+                log_info!("[srv] 停止工作进程[{}]", pid);
+                unsafe { libc::kill(pid, SIGTERM); }
+            }
+        }
+    }
+
+    /// 让运行中的工作进程数等于 target，多出的工作进程处理完当前连接后退出；创建工作进程失败时返回 false
+    ///
+    /// slots 记录每个工作进程的编号，新工作进程使用运行中的工作进程没有占用的最小编号，减少时先停止编号最大的，
+    /// 使用共用监听套接字的第 0 个一直保留。
+    fn scale_workers(&self, target: usize, retiring: &mut HashSet<i32>, slots: &mut HashMap<i32, usize>, handler: &dyn Handler) -> bool {
+        // 持有锁直到登记完子进程，避免子进程在登记前退出导致无法回收
+        let mut child_pids = self.shared.child_pids.lock().unwrap();
+        retiring.retain(|pid| child_pids.contains(pid));
+        slots.retain(|pid, _| child_pids.contains(pid));
+        let mut running = child_pids.len() - retiring.len();
+
+        while running > target {
+            let Some(&pid) = child_pids.iter().filter(|pid| !retiring.contains(*pid)).max_by_key(|pid| slots.get(*pid)) else {
+                break;
+            };
+            log_info!("[srv] 停止工作进程[{}]", pid);
+            unsafe { libc::kill(pid, SIGTERM); }
+            retiring.insert(pid);
+            running -= 1;
+        }
+
+        while running < target {
+            let slot = (0..)
+                .find(|slot| !slots.iter().any(|(pid, used)| used == slot && !retiring.contains(pid)))
+                .expect("编号不会用完");
+            // 新工作进程的监听器，fork() 之后主进程关闭自己的副本
+            let shard = if self.reuse_port {
+                match self.reuse_port_listeners(slot) {
+                    Ok(listeners) => Some(listeners),
+                    Err(e) => {
+                        eprintln!("创建工作进程的监听器失败: {}", e);
+                        return false;
+                    }
+                }
+            } else {
+                None
+            };
+            match fork() {
+                0 => {
+                    // 子进程不管理其他工作进程
+                    child_pids.clear();
+                    drop(child_pids);
+                    self.run_worker(shard.as_deref().unwrap_or(&self.listeners), handler);
+                }
+                pid if pid > 0 => {
+                    log_info!("[srv] 创建工作进程[{}]", pid);
+                    child_pids.insert(pid);
+                    slots.insert(pid, slot);
+                    running += 1;
+                    if self.shared.is_shutdown() {
+                        unsafe { libc::kill(pid, SIGINT); }
+                    } else if self.shared.is_draining() {
+                        unsafe { libc::kill(pid, SIGTERM); }
+                    }
+                }
+                _ => {
+                    eprintln!("创建工作进程失败: {}", io::Error::last_os_error());
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// 等待 [`Shared::wake`] 的唤醒，timeout 为 poll() 的超时毫秒数，-1 表示一直等待
+    fn wait_wake(&self, timeout: i32) -> io::Result<()> {
+        let mut fd = libc::pollfd { fd: self.wake_rx.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        if unsafe { libc::poll(&mut fd, 1, timeout) } < 0 {
+            let e = io::Error::last_os_error();
+            // 被信号中断时由调用者重新检查状态
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+        while (&self.wake_rx).read(&mut [0_u8; 16]).is_ok_and(|size| size > 0) {}
+        Ok(())
+    }
+
+    /// 预派生模型的工作进程：在 listeners 上逐个接受并处理连接，直到收到 SIGINT 或 SIGTERM
+    fn run_worker(&self, listeners: &[TcpListener], handler: &dyn Handler) -> ! {
+        let pid = std::process::id();
+        // 子进程继承了 signal-hook 的注册信息，重置信号处理器后再次注册并不会重新安装，
+        // 因此屏蔽 SIGINT 与 SIGTERM，由单独的线程用 sigwait() 同步地接收
+        let mut sigset: libc::sigset_t = unsafe { std::mem::zeroed() };
+        unsafe {
+            libc::sigemptyset(&mut sigset);
+            libc::sigaddset(&mut sigset, SIGINT);
+            libc::sigaddset(&mut sigset, SIGTERM);
+            libc::pthread_sigmask(libc::SIG_BLOCK, &sigset, std::ptr::null_mut());
+        }
+        reset_signal_handlers();
+
+        // 唤醒用的 socketpair 与主进程共用，换成本进程独有的一对，shutdown() 与 drain() 只唤醒自己
+        let (waker, wake_rx) = UnixStream::pair().expect("无法创建唤醒套接字");
+        wake_rx.set_nonblocking(true).expect("无法设置唤醒套接字");
+        unsafe {
+            libc::dup2(waker.as_raw_fd(), self.shared.waker.as_raw_fd());
+            libc::dup2(wake_rx.as_raw_fd(), self.wake_rx.as_raw_fd());
+            // 工作进程不代表服务，不向 systemd 发送通知；此时进程中只有当前线程
+            std::env::remove_var(systemd::ENV_NOTIFY_SOCKET);
+        }
+        drop((waker, wake_rx));
+
+        let shared = self.shared.clone();
+        std::thread::spawn(move || loop {
+            let mut sig = 0;
+            if unsafe { libc::sigwait(&sigset, &mut sig) } != 0 {
+                continue;
+            }
+            if sig == SIGTERM {
+                // 处理完当前的请求后退出
+                log_info!("[{}] SIGTERM is coming!", pid);
+                shared.drain();
+            } else {
+                log_info!("[{}] SIGINT is coming!", pid);
+                shared.shutdown();
+            }
+        });
+
+        // 业务处理器 panic 时不能回到主进程的调用栈，由主进程重新创建工作进程
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            self.accept_serially(&pid.to_string(), listeners, &AtomicU64::new(0), handler);
+        }));
+
+        log_info!("[{}] 工作进程退出", pid);
+        unsafe { libc::exit(if result.is_ok() { 0 } else { 1 }) }
+    }
+
+    fn run_epoll(&self, handler: &dyn Handler) -> io::Result<()> {
+        // token 0 为唤醒事件，之后依次为各个监听器，再之后为连接
+        const WAKE: u64 = 0;
+        let epoll = Epoll::new()?;
+        epoll.add(self.wake_rx.as_raw_fd(), WAKE, EDGE_READ)?;
+        for (index, listener) in self.listeners.iter().enumerate() {
+            epoll.add(listener.as_raw_fd(), 1 + index as u64, EDGE_READ)?;
+        }
+        let first_connection = 1 + self.listeners.len() as u64;
+        let mut next_token = first_connection;
+        let mut connections: HashMap<u64, Connection> = HashMap::new();
+        let mut events = vec![libc::epoll_event { events: 0, u64: 0 }; 1024];
+        let mut accepted = Vec::new();
+        let mut scratch = Vec::new();
+        let mut accepting = true;
+        // 描述符用完后监听队列中还有连接，边缘触发不会再通知，有连接关闭后需要重新触发
+        let mut exhausted = false;
+
+        // 单线程处理所有连接的事件循环
+        while accepting || !connections.is_empty() {
+            let active = connections.len();
+            for (token, _) in epoll.wait(&mut events, None)? {
+                match token {
+                    WAKE => {
+                        if self.wake_event_loop(&mut accepting, &mut connections, handler, &mut scratch) {
+                            return Ok(());
+                        }
+                        if !accepting {
+                            for listener in &self.listeners {
+                                // 已经移除过时返回 ENOENT
+                                let _ = epoll.delete(listener.as_raw_fd());
+                            }
+                        }
+                    }
+                    token if token < first_connection => {
+                        if !accepting {
+                            continue;
+                        }
+                        // 边缘触发，一直接受到没有新的连接为止
+                        let listener = &self.listeners[(token - 1) as usize];
+                        if let Err(e) = self.accept_ready(listener, None, &mut next_token, &mut connections, &mut accepted) {
+                            eprintln!("接受连接失败: {}", e);
+                            exhausted = true;
+                        }
+                        for token in accepted.drain(..) {
+                            // 注册时已经可读的连接会立即产生事件；内核资源不足时只关闭这个连接
+                            if let Err(e) = epoll.add(connections[&token].stream().as_raw_fd(), token, EDGE_READ_WRITE) {
+                                let connection = connections.remove(&token).expect("刚接受的连接");
+                                eprintln!("[srv] 无法监听客户端[{}]的事件，关闭连接: {}", connection.ctx().peer_addr, e);
+                                Server::closed(connection.ctx());
+                            }
+                        }
+                    }
+                    token => {
+                        if let Some(connection) = connections.get_mut(&token)
+                            && !connection.drive(handler, &mut scratch) {
+                            // 关闭描述符时 epoll 自动移除它
+                            Server::closed(connection.ctx());
+                            connections.remove(&token);
+                        }
+                    }
+                }
+            }
+            if exhausted && accepting && connections.len() < active {
+                // 重新设置事件时已经就绪的监听器会再产生一次事件
+                exhausted = false;
+                for (index, listener) in self.listeners.iter().enumerate() {
+                    epoll.modify(listener.as_raw_fd(), 1 + index as u64, EDGE_READ)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// poll/select 模型：水平触发，每轮按各连接的读写缓冲重新计算关注的事件
+    fn run_multiplexed(&self, handler: &dyn Handler, mut multiplexer: impl Multiplexer) -> io::Result<()> {
+        let fd_limit = multiplexer.fd_limit();
+        let mut next_token: u64 = 0;
+        let mut connections: HashMap<u64, Connection> = HashMap::new();
+        // interests 中依次为唤醒描述符、各个监听器与连接，tokens 记录连接对应的 token
+        let mut interests = Vec::new();
+        let mut tokens = Vec::new();
+        let mut accepted = Vec::new();
+        let mut scratch = Vec::new();
+        let mut accepting = true;
+        // 描述符用完后暂停接受，否则就绪的监听器会让循环空转
+        let mut exhausted = false;
+
+        while accepting || !connections.is_empty() {
+            let listening = accepting && !exhausted;
+            interests.clear();
+            tokens.clear();
+            interests.push(Interest { fd: self.wake_rx.as_raw_fd(), read: true, write: false });
+            if listening {
+                interests.extend(self.listeners.iter().map(|listener| Interest { fd: listener.as_raw_fd(), read: true, write: false }));
+            }
+            let first_connection = interests.len();
+            for (token, connection) in &connections {
+                interests.push(connection.interest());
+                tokens.push(*token);
+            }
+
+            for index in multiplexer.wait(&interests)? {
+                if index == 0 {
+                    if self.wake_event_loop(&mut accepting, &mut connections, handler, &mut scratch) {
+                        return Ok(());
+                    }
+                } else if index < first_connection {
+                    if !accepting {
+                        continue;
+                    }
+                    let listener = &self.listeners[index - 1];
+                    if let Err(e) = self.accept_ready(listener, fd_limit, &mut next_token, &mut connections, &mut accepted) {
+                        eprintln!("接受连接失败: {}", e);
+                        exhausted = true;
+                    }
+                    accepted.clear();
+                } else {
+                    let token = tokens[index - first_connection];
+                    if let Some(connection) = connections.get_mut(&token)
+                        && !connection.drive(handler, &mut scratch) {
+                        Server::closed(connection.ctx());
+                        connections.remove(&token);
+                        exhausted = false;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// io_uring 模型：单线程提交 multishot accept/recv，接收缓冲由内核从共享的缓冲环中挑选
+    #[cfg(feature = "io-uring")]
+    fn run_io_uring(&self, handler: &dyn Handler) -> io::Result<()> {
+        use std::os::fd::FromRawFd;
+        use io_uring::{cqueue, opcode, types};
+        use crate::uring::{self, BufRing, Op};
+
+        let mut ring = uring::new_ring(uring::RING_ENTRIES)?;
+        let mut buffers = BufRing::register(&ring.submitter(), uring::BUF_COUNT, self.shared.settings().read_buffer)?;
+        let mut connections: HashMap<u64, uring::Connection> = HashMap::new();
+        let mut next_token: u64 = 0;
+        let mut submit = Vec::new();
+        // 已提交但还没有收到最后一个完成事件的操作数
+        let mut in_flight = 0_usize;
+        // 本轮有完成事件的连接
+        let mut changed = HashSet::new();
+        // 描述符用完时暂停的监听器，有连接关闭后再继续接受
+        let mut paused = Vec::new();
+        let mut accepting = true;
+
+        let wake = opcode::PollAdd::new(types::Fd(self.wake_rx.as_raw_fd()), libc::POLLIN as u32)
+            .multi(true)
+            .build()
+            .user_data(Op::Wake.encode());
+        let accept = |index: usize| {
+            opcode::AcceptMulti::new(types::Fd(self.listeners[index].as_raw_fd()))
+                .flags(libc::SOCK_CLOEXEC)
+                .build()
+                .user_data(Op::Accept(index).encode())
+        };
+        submit.push(wake.clone());
+        submit.extend((0..self.listeners.len()).map(accept));
+
+        let result = 'event: loop {
+            if !accepting && connections.is_empty() {
+                break Ok(());
+            }
+            in_flight += submit.len();
+            if let Err(e) = uring::submit_and_wait(&mut ring, &mut submit) {
+                break Err(e);
+            }
+
+            let completions: Vec<cqueue::Entry> = ring.completion().collect();
+            for cqe in completions {
+                let (result, flags) = (cqe.result(), cqe.flags());
+                let more = cqueue::more(flags);
+                if !more {
+                    in_flight -= 1;
+                }
+                match Op::decode(cqe.user_data()) {
+                    Op::Wake => {
+                        if !more {
+                            submit.push(wake.clone());
+                        }
+                        while (&self.wake_rx).read(&mut [0_u8; 16]).is_ok_and(|size| size > 0) {}
+                        if self.shared.is_shutdown() {
+                            log_info!("检测到关闭请求，断开 {} 个连接后退出...", connections.len());
+                            break 'event Ok(());
+                        }
+                        if self.shared.is_draining() && accepting {
+                            log_info!("检测到关闭请求，准备退出...");
+                            accepting = false;
+                            for index in 0..self.listeners.len() {
+                                submit.push(opcode::AsyncCancel::new(Op::Accept(index).encode()).build().user_data(Op::Cancel.encode()));
+                            }
+                            for (token, connection) in connections.iter_mut() {
+                                connection.going_away();
+                                changed.insert(*token);
+                            }
+                        }
+                    }
+                    Op::Accept(index) => {
+                        if result < 0 {
+                            let e = io::Error::from_raw_os_error(-result);
+                            if result != -libc::ECANCELED {
+                                eprintln!("接受连接失败: {}", reactor::fd_exhausted(&e).unwrap_or(e));
+                            }
+                            if !more && accepting {
+                                // 描述符用完时立即重新提交会不停地失败
+                                if matches!(-result, libc::EMFILE | libc::ENFILE) {
+                                    paused.push(index);
+                                } else {
+                                    submit.push(accept(index));
+                                }
+                            }
+                            continue;
+                        }
+                        if !more && accepting {
+                            submit.push(accept(index));
+                        }
+                        let stream = unsafe { TcpStream::from_raw_fd(result) };
+                        if !accepting {
+                            continue;
+                        }
+                        let peer_addr = match stream.peer_addr() {
+                            Ok(peer_addr) => peer_addr,
+                            Err(e) => {
+                                eprintln!("接受连接失败: {}", e);
+                                continue;
+                            }
+                        };
+                        log_info!("[srv] client[{}] is accepted!", peer_addr);
+                        if let Some((token, ctx)) = self.admit_event_loop(&mut next_token, peer_addr, connections.len()) {
+                            connections.insert(token, uring::Connection::new(stream, &ctx));
+                            changed.insert(token);
+                        }
+                    }
+                    Op::Recv(token) => {
+                        if result == -libc::EINVAL {
+                            break 'event Err(io::Error::new(io::ErrorKind::Unsupported, "内核不支持 multishot recv（需要 Linux 6.0 以上）"));
+                        }
+                        let bid = cqueue::buffer_select(flags);
+                        if let Some(connection) = connections.get_mut(&token) {
+                            let bytes = match (result, bid) {
+                                (result, _) if result < 0 => Err(io::Error::from_raw_os_error(-result)),
+                                (result, Some(bid)) => Ok(buffers.buffer(bid, result as usize)),
+                                (_, None) => Ok(&[][..]),
+                            };
+                            connection.received(bytes, more, handler);
+                            changed.insert(token);
+                        }
+                        if let Some(bid) = bid {
+                            buffers.recycle(bid);
+                        }
+                    }
+                    Op::Send(token) => {
+                        if let Some(connection) = connections.get_mut(&token) {
+                            let sent = if result < 0 { Err(io::Error::from_raw_os_error(-result)) } else { Ok(result as usize) };
+                            connection.sent(sent);
+                            changed.insert(token);
+                        }
+                    }
+                    Op::Cancel => {}
+                }
+            }
+
+            for token in changed.drain() {
+                let Some(connection) = connections.get_mut(&token) else {
+                    continue;
+                };
+                connection.advance(token, &mut submit);
+                if connection.is_done() {
+                    Server::closed(connection.ctx());
+                    connections.remove(&token);
+                    if accepting {
+                        submit.extend(paused.drain(..).map(accept));
+                    }
+                }
+            }
+        };
+
+        // 取消所有进行中的操作并等待它们结束，之后内核不再访问连接的发送缓冲与缓冲环
+        submit.clear();
+        submit.push(opcode::AsyncCancel2::new(types::CancelBuilder::any()).build().user_data(Op::Cancel.encode()));
+        while in_flight + submit.len() > 0 {
+            in_flight += submit.len();
+            uring::submit_and_wait(&mut ring, &mut submit)?;
+            in_flight -= ring.completion().filter(|cqe| !cqueue::more(cqe.flags())).count();
+        }
+        drop(connections);
+        drop(ring);
+        drop(buffers);
+        result
+    }
+
+    #[cfg(not(feature = "io-uring"))]
+    fn run_io_uring(&self, _handler: &dyn Handler) -> io::Result<()> {
+        unreachable!("build() 已经检查过 io-uring 特性")
+    }
+
+    /// 事件循环模型收到唤醒：需要立即退出时返回 true，开始优雅关闭时把 accepting 置为 false
+    fn wake_event_loop(
+        &self,
+        accepting: &mut bool,
+        connections: &mut HashMap<u64, Connection>,
+        handler: &dyn Handler,
+        scratch: &mut Vec<u8>,
+    ) -> bool {
+        while (&self.wake_rx).read(&mut [0_u8; 16]).is_ok_and(|size| size > 0) {}
+        if self.shared.is_shutdown() {
+            log_info!("检测到关闭请求，断开 {} 个连接后退出...", connections.len());
+            return true;
+        }
+        if self.shared.is_draining() && *accepting {
+            log_info!("检测到关闭请求，准备退出...");
+            *accepting = false;
+            for connection in connections.values_mut() {
+                connection.going_away();
+            }
+            connections.retain(|_, connection| {
+                let open = connection.drive(handler, scratch);
+                if !open {
+                    Server::closed(connection.ctx());
+                }
+                open
+            });
+        }
+        false
+    }
+
+    /// 事件循环模型：接受非阻塞监听器上所有就绪的连接，新连接的 token 追加到 accepted
+    ///
+    /// 描述符不小于 fd_limit 的连接被拒绝；进程的描述符用完时返回错误，监听队列中剩余的连接留待之后接受。
+    fn accept_ready(
+        &self,
+        listener: &TcpListener,
+        fd_limit: Option<RawFd>,
+        next_token: &mut u64,
+        connections: &mut HashMap<u64, Connection>,
+        accepted: &mut Vec<u64>,
+    ) -> io::Result<()> {
+        loop {
+            let (stream, peer_addr) = match listener.accept() {
+                Ok(accepted) => accepted,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(reactor::fd_exhausted(&e).unwrap_or(e)),
+            };
+            log_info!("[srv] client[{}] is accepted!", peer_addr);
+            if let Some(limit) = fd_limit
+                && stream.as_raw_fd() >= limit {
+                eprintln!("[srv] 拒绝客户端[{}]: {}", peer_addr, reactor::fd_setsize_exceeded(stream.as_raw_fd()));
+                continue;
+            }
+            let Some((token, ctx)) = self.admit_event_loop(next_token, peer_addr, connections.len()) else {
+                continue;
+            };
+            connections.insert(token, Connection::new(stream, &ctx)?);
+            accepted.push(token);
+        }
+    }
+
+    /// 事件循环模型：按连接数上限、认证方式与访问列表决定是否处理新连接，接受时分配 token
+    fn admit_event_loop(&self, next_token: &mut u64, peer_addr: SocketAddr, active: usize) -> Option<(u64, ConnContext)> {
+        let settings = self.shared.settings();
+        if settings.at_capacity(active) {
+            log_info!("[srv] 连接数已达上限，拒绝客户端[{}]", peer_addr);
+            return None;
+        }
+        if settings.psk.is_some() {
+            eprintln!("[srv] {:?} 模型不支持预共享密钥认证，拒绝客户端[{}]", self.model, peer_addr);
+            return None;
+        }
+        let token = *next_token;
+        *next_token += 1;
+        let ctx = settings.context(token.to_string(), peer_addr, self.shared.drain_tx.subscribe());
+        admit(&ctx, &settings.access).then_some((token, ctx))
+    }
+
+    /// 记录事件循环模型中结束的连接
+    fn closed(ctx: &ConnContext) {
+        log_info!("[{}] 与客户端 {} 的连接已关闭", ctx.id, ctx.peer_addr);
+    }
+
+    fn run_tokio<H: AsyncHandler + ?Sized + 'static>(&self, handler: Arc<H>) -> io::Result<()> {
+        let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build()?;
+        runtime.block_on(self.run_async(handler))
+    }
+
+    async fn run_async<H: AsyncHandler + ?Sized + 'static>(&self, handler: Arc<H>) -> io::Result<()> {
+        let acceptor = self.tls.clone().map(TlsAcceptor::from);
+        let tasks = Arc::new(AsyncTasks::default());
+        if !self.reuse_port {
+            let listeners = self.listeners.iter().map(TcpListener::try_clone).collect::<io::Result<_>>()?;
+            return accept_async(listeners, self.shared.clone(), acceptor, handler, tasks).await;
+        }
+
+        // 每个 accept 循环使用自己的 SO_REUSEPORT 监听器，由运行时的多个工作线程同时执行
+        let mut loops = Vec::with_capacity(self.workers);
+        for slot in 0..self.workers {
+            let listeners = self.reuse_port_listeners(slot)?;
+            loops.push(tokio::spawn(accept_async(listeners, self.shared.clone(), acceptor.clone(), handler.clone(), tasks.clone())));
+        }
+        log_info!("[srv] {} 个 accept 循环，各自在 SO_REUSEPORT 监听器上接受连接", self.workers);
+        for result in futures::future::join_all(loops).await {
+            result.map_err(io::Error::other)??;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+use std::io;
+use std::net::TcpListener;
+use std::os::fd::{FromRawFd, IntoRawFd, RawFd};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use socket2::Socket;
+
+/// systemd 传入的第一个描述符，之后的描述符依次递增
+pub const LISTEN_FDS_START: RawFd = 3;
+/// 传入的描述符数量
+pub const ENV_LISTEN_FDS: &str = "LISTEN_FDS";
+/// 描述符的接收者，与当前进程ID不一致时忽略 LISTEN_FDS
+pub const ENV_LISTEN_PID: &str = "LISTEN_PID";
+/// 接收状态通知的 Unix 数据报套接字，以 `@` 开头时为抽象命名空间
+pub const ENV_NOTIFY_SOCKET: &str = "NOTIFY_SOCKET";
+/// 看门狗的超时时间（微秒）
+pub const ENV_WATCHDOG_USEC: &str = "WATCHDOG_USEC";
+/// 看门狗针对的进程，没有设置时针对主进程
+pub const ENV_WATCHDOG_PID: &str = "WATCHDOG_PID";
+
+static LISTENERS_TAKEN: AtomicBool = AtomicBool::new(false);
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// 环境变量中的进程ID是否为当前进程，没有设置时返回 None
+fn is_current_pid(name: &str) -> Option<bool> {
+    let value = std::env::var(name).ok()?;
+    Some(value.trim().parse::<u32>().ok() == Some(std::process::id()))
+}
+
+/// 取出 systemd 套接字激活传入的监听套接字，不是由 systemd 激活时返回 None
+///
+/// 只有第一次调用会取出，之后创建的服务器照常绑定地址。
+pub fn listeners() -> io::Result<Option<Vec<TcpListener>>> {
+    // 由其他进程激活后继承了这些环境变量时不能使用
+    if is_current_pid(ENV_LISTEN_PID) != Some(true) {
+        return Ok(None);
+    }
+    let Ok(value) = std::env::var(ENV_LISTEN_FDS) else {
+        return Ok(None);
+    };
+    let count: RawFd = value.trim().parse().map_err(|_| invalid_input(format!("{} 的取值 {} 非法", ENV_LISTEN_FDS, value)))?;
+    if count <= 0 || LISTENERS_TAKEN.swap(true, Ordering::SeqCst) {
+        return Ok(None);
+    }
+
+    let mut listeners = Vec::new();
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + count {
+        if unsafe { libc::fcntl(fd, libc::F_GETFD) } < 0 {
+            return Err(invalid_input(format!("systemd 传入的描述符 {} 没有打开", fd)));
+        }
+        let socket = unsafe { Socket::from_raw_fd(fd) };
+        if !socket.is_listener().unwrap_or(false) {
+            let _ = socket.into_raw_fd();
+            return Err(invalid_input(format!("systemd 传入的描述符 {} 不是 TCP 监听套接字", fd)));
+        }
+        // 不再传给之后启动的其他程序
+        socket.set_cloexec(true)?;
+        listeners.push(socket.into());
+    }
+    Ok(Some(listeners))
+}
+
+/// 向 NOTIFY_SOCKET 发送状态通知，例如 `READY=1`，没有设置 NOTIFY_SOCKET 时返回 false
+pub fn notify(state: &str) -> io::Result<bool> {
+    let Some(path) = std::env::var_os(ENV_NOTIFY_SOCKET) else {
+        return Ok(false);
+    };
+    let addr = match path.as_encoded_bytes().strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(&path)?,
+    };
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(true)
+}
+
+/// 发送看门狗通知的间隔，为超时时间的一半；没有启用看门狗时返回 None
+pub fn watchdog_interval() -> Option<Duration> {
+    if is_current_pid(ENV_WATCHDOG_PID) == Some(false) {
+        return None;
+    }
+    let usec: u64 = std::env::var(ENV_WATCHDOG_USEC).ok()?.trim().parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}
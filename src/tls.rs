@@ -0,0 +1,289 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, TcpStream};
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{ClientConfig, ClientConnection, ConnectionCommon, RootCertStore, ServerConfig, ServerConnection, SideData, StreamOwned};
+use x509_parser::extensions::GeneralName;
+
+use crate::network_handler::ReadTimeout;
+
+/// 服务器证书链 PEM 文件路径的环境变量
+pub const ENV_TLS_CERT: &str = "SOCKET_TLS_CERT";
+/// 服务器私钥 PEM 文件路径的环境变量
+pub const ENV_TLS_KEY: &str = "SOCKET_TLS_KEY";
+/// 服务器用于校验客户端证书的 CA 证书 PEM 文件路径的环境变量，设置后要求客户端出示证书
+pub const ENV_TLS_CLIENT_CA: &str = "SOCKET_TLS_CLIENT_CA";
+/// 客户端信任的 CA 证书 PEM 文件路径的环境变量
+pub const ENV_TLS_CA: &str = "SOCKET_TLS_CA";
+/// 客户端校验服务器证书时使用的域名的环境变量，默认为 `localhost`
+pub const ENV_TLS_SERVER_NAME: &str = "SOCKET_TLS_SERVER_NAME";
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// 固定使用 ring 作为加密实现，不依赖进程级别的默认配置
+fn provider() -> Arc<rustls::crypto::CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+/// 从 PEM 文件中读取证书链
+pub fn load_certs(path: impl AsRef<Path>) -> io::Result<Vec<CertificateDer<'static>>> {
+    let path = path.as_ref();
+    let mut reader = BufReader::new(File::open(path)?);
+    let certs = rustls_pemfile::certs(&mut reader).collect::<io::Result<Vec<_>>>()?;
+    if certs.is_empty() {
+        return Err(invalid_data(format!("{} 中没有证书", path.display())));
+    }
+    Ok(certs)
+}
+
+/// 从 PEM 文件中读取私钥（PKCS#8、PKCS#1 或 SEC1）
+pub fn load_private_key(path: impl AsRef<Path>) -> io::Result<PrivateKeyDer<'static>> {
+    let path = path.as_ref();
+    let mut reader = BufReader::new(File::open(path)?);
+    rustls_pemfile::private_key(&mut reader)?.ok_or_else(|| invalid_data(format!("{} 中没有私钥", path.display())))
+}
+
+/// 从 PEM 文件中读取 CA 证书作为信任根
+pub fn root_store(ca_path: impl AsRef<Path>) -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(ca_path)? {
+        roots.add(cert).map_err(|e| invalid_data(e.to_string()))?;
+    }
+    Ok(roots)
+}
+
+/// 用证书链与私钥创建服务器端配置
+pub fn server_config(cert_path: impl AsRef<Path>, key_path: impl AsRef<Path>) -> io::Result<Arc<ServerConfig>> {
+    build_server_config(load_certs(cert_path)?, load_private_key(key_path)?, None)
+}
+
+/// 创建要求客户端证书的服务器端配置（mTLS），客户端证书必须由 client_ca_path 中的 CA 签发
+pub fn server_config_with_client_auth(
+    cert_path: impl AsRef<Path>,
+    key_path: impl AsRef<Path>,
+    client_ca_path: impl AsRef<Path>,
+) -> io::Result<Arc<ServerConfig>> {
+    build_server_config(load_certs(cert_path)?, load_private_key(key_path)?, Some(root_store(client_ca_path)?))
+}
+
+/// 用已经读取的证书链、私钥创建服务器端配置，给出 client_roots 时要求客户端出示由其签发的证书
+///
+/// 返回的错误只来自证书与私钥不匹配等 rustls 校验，读取文件的错误由调用方按路径报告。
+pub fn build_server_config(
+    certs: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+    client_roots: Option<RootCertStore>,
+) -> io::Result<Arc<ServerConfig>> {
+    let builder = ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| invalid_data(e.to_string()))?;
+    let builder = match client_roots {
+        Some(roots) => {
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider())
+                .build()
+                .map_err(|e| invalid_data(e.to_string()))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder.with_single_cert(certs, key).map_err(|e| invalid_data(e.to_string()))?;
+    Ok(Arc::new(config))
+}
+
+/// 创建信任指定 CA 证书的客户端配置
+pub fn client_config(ca_path: impl AsRef<Path>) -> io::Result<Arc<ClientConfig>> {
+    let config = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| invalid_data(e.to_string()))?
+        .with_root_certificates(root_store(ca_path)?)
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+/// 创建出示客户端证书的客户端配置，用于 mTLS
+pub fn client_config_with_cert(
+    ca_path: impl AsRef<Path>,
+    cert_path: impl AsRef<Path>,
+    key_path: impl AsRef<Path>,
+) -> io::Result<Arc<ClientConfig>> {
+    let config = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| invalid_data(e.to_string()))?
+        .with_root_certificates(root_store(ca_path)?)
+        .with_client_auth_cert(load_certs(cert_path)?, load_private_key(key_path)?)
+        .map_err(|e| invalid_data(e.to_string()))?;
+    Ok(Arc::new(config))
+}
+
+/// 对端证书中的身份信息
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerIdentity {
+    /// 证书主题，例如 `CN=alice, O=example`
+    pub subject: String,
+    /// 主题中的 CN
+    pub common_name: Option<String>,
+    /// 主题备用名称（DNS 名称、邮箱、URI、IP 地址）
+    pub sans: Vec<String>,
+}
+
+impl PeerIdentity {
+    /// 从 DER 编码的证书中解析身份信息
+    pub fn from_der(cert: &CertificateDer<'_>) -> Option<Self> {
+        let (_, cert) = x509_parser::parse_x509_certificate(cert.as_ref()).ok()?;
+        let common_name = cert.subject().iter_common_name().next().and_then(|cn| cn.as_str().ok()).map(str::to_string);
+        let mut sans = Vec::new();
+        if let Ok(Some(extension)) = cert.subject_alternative_name() {
+            for name in &extension.value.general_names {
+                match name {
+                    GeneralName::DNSName(name) | GeneralName::RFC822Name(name) | GeneralName::URI(name) => sans.push(name.to_string()),
+                    GeneralName::IPAddress(bytes) => {
+                        let ip = match bytes.len() {
+                            4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(*bytes).unwrap())),
+                            16 => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(*bytes).unwrap())),
+                            _ => continue,
+                        };
+                        sans.push(ip.to_string());
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Some(PeerIdentity {
+            subject: cert.subject().to_string(),
+            common_name,
+            sans,
+        })
+    }
+
+    /// name 是否与完整主题、CN 或任意一个备用名称相同
+    pub fn matches(&self, name: &str) -> bool {
+        self.subject == name || self.common_name.as_deref() == Some(name) || self.sans.iter().any(|san| san == name)
+    }
+}
+
+impl fmt::Display for PeerIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.subject)?;
+        if !self.sans.is_empty() {
+            write!(f, " (SAN: {})", self.sans.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// 从握手完成的连接中取出对端证书链第一张证书的身份信息
+pub fn peer_identity<S: SideData>(conn: &ConnectionCommon<S>) -> Option<PeerIdentity> {
+    PeerIdentity::from_der(conn.peer_certificates()?.first()?)
+}
+
+/// 阻塞模式的 TLS 连接
+///
+/// 与 `TcpStream` 一样，`&TlsStream` 也实现了 `Read` 和 `Write`，可以同时交给
+/// [`PduReader`](crate::codec::PduReader) 和 [`PduWriter`](crate::codec::PduWriter)。
+/// 读写共用同一把锁，因此只适合在同一个线程中交替读写。drop 时会发送 close_notify。
+pub struct TlsStream<C: DerefMut + Deref<Target = ConnectionCommon<S>>, S: SideData> {
+    inner: Mutex<StreamOwned<C, TcpStream>>,
+}
+
+pub type ServerTlsStream = TlsStream<ServerConnection, rustls::server::ServerConnectionData>;
+pub type ClientTlsStream = TlsStream<ClientConnection, rustls::client::ClientConnectionData>;
+
+impl ServerTlsStream {
+    /// 在已接受的连接上完成服务器端握手
+    pub fn accept(stream: TcpStream, config: Arc<ServerConfig>) -> io::Result<Self> {
+        let conn = ServerConnection::new(config).map_err(|e| invalid_data(e.to_string()))?;
+        TlsStream::handshake(conn, stream)
+    }
+}
+
+impl ClientTlsStream {
+    /// 在已建立的连接上完成客户端握手，server_name 用于校验服务器证书
+    pub fn connect(stream: TcpStream, server_name: &str, config: Arc<ClientConfig>) -> io::Result<Self> {
+        let name = ServerName::try_from(server_name.to_string())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        let conn = ClientConnection::new(config, name).map_err(|e| invalid_data(e.to_string()))?;
+        TlsStream::handshake(conn, stream)
+    }
+}
+
+impl<C: DerefMut + Deref<Target = ConnectionCommon<S>>, S: SideData> TlsStream<C, S> {
+    fn handshake(mut conn: C, mut stream: TcpStream) -> io::Result<Self> {
+        while conn.is_handshaking() {
+            conn.complete_io(&mut stream)?;
+        }
+        Ok(TlsStream {
+            inner: Mutex::new(StreamOwned::new(conn, stream)),
+        })
+    }
+
+    /// 对端证书中的身份信息，对端没有出示证书时返回 None
+    pub fn peer_identity(&self) -> Option<PeerIdentity> {
+        peer_identity(&self.inner.lock().unwrap().conn)
+    }
+
+    /// 底层 TCP 连接的副本，可用于在其他线程中关闭连接
+    pub fn try_clone_tcp(&self) -> io::Result<TcpStream> {
+        self.inner.lock().unwrap().sock.try_clone()
+    }
+}
+
+impl<C: DerefMut + Deref<Target = ConnectionCommon<S>>, S: SideData> ReadTimeout for TlsStream<C, S> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.lock().unwrap().sock.set_read_timeout(timeout)
+    }
+}
+
+impl<C: DerefMut + Deref<Target = ConnectionCommon<S>>, S: SideData> Read for &TlsStream<C, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.lock().unwrap().read(buf)
+    }
+}
+
+impl<C: DerefMut + Deref<Target = ConnectionCommon<S>>, S: SideData> Write for &TlsStream<C, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.lock().unwrap().flush()
+    }
+}
+
+impl<C: DerefMut + Deref<Target = ConnectionCommon<S>>, S: SideData> Read for TlsStream<C, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&*self).read(buf)
+    }
+}
+
+impl<C: DerefMut + Deref<Target = ConnectionCommon<S>>, S: SideData> Write for TlsStream<C, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&*self).flush()
+    }
+}
+
+impl<C: DerefMut + Deref<Target = ConnectionCommon<S>>, S: SideData> Drop for TlsStream<C, S> {
+    fn drop(&mut self) {
+        let Ok(stream) = self.inner.get_mut() else {
+            return;
+        };
+        // 对端可能已经关闭连接，忽略发送失败；不能用 complete_io，它可能阻塞在读取上
+        stream.conn.send_close_notify();
+        while stream.conn.wants_write() {
+            if stream.conn.write_tls(&mut stream.sock).is_err() {
+                break;
+            }
+        }
+    }
+}
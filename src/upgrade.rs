@@ -0,0 +1,132 @@
+use std::io::{self, Read, Write};
+use std::net::TcpListener;
+use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use socket2::Socket;
+
+use crate::systemd;
+
+/// 新进程继承的监听套接字描述符，多个描述符以逗号分隔
+pub const ENV_INHERITED_FDS: &str = "SOCKET_INHERITED_FDS";
+/// 新进程就绪后用于通知旧进程的描述符
+pub const ENV_UPGRADE_NOTIFY: &str = "SOCKET_UPGRADE_NOTIFY";
+/// 等待新进程就绪的最长时间
+pub const READY_TIMEOUT: Duration = Duration::from_secs(10);
+
+static INHERITED: AtomicBool = AtomicBool::new(false);
+static NOTIFIED: AtomicBool = AtomicBool::new(false);
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// 解析环境变量中的描述符，并确认它在当前进程中是打开的
+fn parse_fd(name: &str, value: &str) -> io::Result<RawFd> {
+    let fd: RawFd = value.trim().parse().map_err(|_| invalid_input(format!("{} 中的描述符 {} 非法", name, value)))?;
+    if fd < 0 || unsafe { libc::fcntl(fd, libc::F_GETFD) } < 0 {
+        return Err(invalid_input(format!("{} 中的描述符 {} 没有打开", name, fd)));
+    }
+    Ok(fd)
+}
+
+/// 取出从旧进程继承的监听套接字，没有继承时返回 None
+///
+/// 只有第一次调用会取出，之后创建的服务器照常绑定地址。
+pub fn inherited_listeners() -> io::Result<Option<Vec<TcpListener>>> {
+    let Ok(value) = std::env::var(ENV_INHERITED_FDS) else {
+        return Ok(None);
+    };
+    if INHERITED.swap(true, Ordering::SeqCst) {
+        return Ok(None);
+    }
+
+    let mut listeners = Vec::new();
+    for fd in value.split(',') {
+        let fd = parse_fd(ENV_INHERITED_FDS, fd)?;
+        let socket = unsafe { Socket::from_raw_fd(fd) };
+        if !socket.is_listener().unwrap_or(false) {
+            // 不是监听套接字时不关闭它，交还给原来的持有者
+            let _ = socket.into_raw_fd();
+            return Err(invalid_input(format!("{} 中的描述符 {} 不是监听套接字", ENV_INHERITED_FDS, fd)));
+        }
+        // 继承时清除了 FD_CLOEXEC，避免再传给之后启动的其他程序
+        socket.set_cloexec(true)?;
+        listeners.push(socket.into());
+    }
+    Ok(Some(listeners))
+}
+
+/// 通知旧进程新进程已经就绪，不是由热升级启动时什么也不做
+pub fn notify_ready() {
+    let Ok(value) = std::env::var(ENV_UPGRADE_NOTIFY) else {
+        return;
+    };
+    if NOTIFIED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    match parse_fd(ENV_UPGRADE_NOTIFY, &value) {
+        Ok(fd) => {
+            let mut stream = unsafe { UnixStream::from_raw_fd(fd) };
+            if let Err(e) = stream.write_all(b"1") {
+                eprintln!("[srv] 通知旧进程失败: {}", e);
+            }
+        }
+        Err(e) => eprintln!("[srv] 通知旧进程失败: {}", e),
+    }
+}
+
+/// 用同样的参数启动新进程并交出监听套接字，等待新进程就绪后返回它的进程ID
+///
+/// 两个进程在交接期间共用同一个监听队列，端口始终可以连接。
+/// 新进程在 [`READY_TIMEOUT`] 内没有就绪或提前退出时结束它并返回错误，旧进程可以继续运行。
+pub fn spawn(fds: &[RawFd]) -> io::Result<u32> {
+    let (mut ready, notify) = UnixStream::pair()?;
+    let notify_fd = notify.as_raw_fd();
+    let inherited = fds.to_vec();
+
+    // 替换了磁盘上的程序后 current_exe() 指向已删除的旧文件，优先按 argv[0] 重新执行
+    let mut args = std::env::args_os();
+    let program = match args.next() {
+        Some(program) => program,
+        None => std::env::current_exe()?.into_os_string(),
+    };
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .env(ENV_INHERITED_FDS, fds.iter().map(RawFd::to_string).collect::<Vec<_>>().join(","))
+        .env(ENV_UPGRADE_NOTIFY, notify_fd.to_string())
+        // 新进程会成为 systemd 的主进程，看门狗应当针对它
+        .env_remove(systemd::ENV_WATCHDOG_PID);
+    unsafe {
+        // 在 fork() 之后、exec() 之前清除 FD_CLOEXEC，只有新进程继承这些描述符
+        command.pre_exec(move || {
+            for &fd in inherited.iter().chain([&notify_fd]) {
+                let flags = libc::fcntl(fd, libc::F_GETFD);
+                if flags < 0 || libc::fcntl(fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    let mut child = command.spawn()?;
+    drop(notify);
+
+    // 新进程就绪后写入一个字节，退出时连接被关闭
+    ready.set_read_timeout(Some(READY_TIMEOUT))?;
+    let result = match ready.read(&mut [0_u8; 1]) {
+        Ok(1) => return Ok(child.id()),
+        Ok(_) => io::Error::other(format!("新进程[{}]在就绪之前退出", child.id())),
+        Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+            io::Error::new(io::ErrorKind::TimedOut, format!("新进程[{}]在 {:?} 内没有就绪", child.id(), READY_TIMEOUT))
+        }
+        Err(e) => e,
+    };
+    let _ = child.kill();
+    let _ = child.wait();
+    Err(result)
+}
@@ -0,0 +1,356 @@
+use std::io;
+use std::io::Write;
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::sync::atomic::{AtomicU16, Ordering};
+
+use io_uring::types::BufRingEntry;
+use io_uring::{opcode, squeue, types, IoUring, Submitter};
+
+use crate::network_handler::{ConnContext, Handler, Session};
+use crate::reactor::WRITE_HIGH_WATER;
+use crate::log_info;
+
+/// 提交队列的长度，完成队列为其两倍
+pub const RING_ENTRIES: u32 = 1024;
+/// 缓冲环中接收缓冲的数量，必须是 2 的幂
+pub const BUF_COUNT: u16 = 1024;
+/// 所有连接共用的缓冲组
+pub const BUF_GROUP: u16 = 0;
+
+/// 内核不支持 io_uring 或者缺少所需的功能
+fn unsupported(what: &str, e: io::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, format!("{}: {}", what, e))
+}
+
+/// 创建 io_uring 实例，内核不支持或被禁用时返回 `ErrorKind::Unsupported`
+pub fn new_ring(entries: u32) -> io::Result<IoUring> {
+    IoUring::new(entries).map_err(|e| match e.raw_os_error() {
+        Some(libc::ENOSYS) => unsupported("内核不支持 io_uring", e),
+        // kernel.io_uring_disabled 或容器的 seccomp 规则禁止了 io_uring
+        Some(libc::EPERM) => unsupported("io_uring 被系统禁用", e),
+        _ => e,
+    })
+}
+
+/// 检查当前环境能否运行 io_uring 模型（需要 Linux 6.0 以上的 multishot recv 与缓冲环）
+///
+/// multishot 与单次的 accept/recv 共用操作码，探测操作码无法区分，因此在一个本地连接上实际提交
+/// multishot accept 与 multishot recv，不支持的内核会以 EINVAL 完成。
+pub fn check_support() -> io::Result<()> {
+    let mut ring = new_ring(8)?;
+    let buffers = BufRing::register(&ring.submitter(), 1, 16)?;
+
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let client = TcpStream::connect(listener.local_addr()?)?;
+    let accept = opcode::AcceptMulti::new(types::Fd(listener.as_raw_fd())).flags(libc::SOCK_CLOEXEC).build();
+    let accepted = unsafe { TcpStream::from_raw_fd(first_completion(&mut ring, accept, "multishot accept")?) };
+    (&client).write_all(b"x")?;
+    let recv = opcode::RecvMulti::new(types::Fd(accepted.as_raw_fd()), BUF_GROUP).build();
+    first_completion(&mut ring, recv, "multishot recv")?;
+
+    // 唯一的缓冲已经用掉，关闭连接与 io_uring 实例后内核不会再写入缓冲环
+    drop((client, accepted, listener));
+    drop(ring);
+    drop(buffers);
+    Ok(())
+}
+
+/// 提交一个操作并返回它的第一个完成事件的结果
+fn first_completion(ring: &mut IoUring, entry: squeue::Entry, name: &str) -> io::Result<i32> {
+    unsafe { ring.submission().push(&entry) }.map_err(|_| io::Error::other("io_uring 提交队列已满"))?;
+    let result = loop {
+        match ring.submit_and_wait(1) {
+            Ok(_) => {}
+            Err(e) if e.raw_os_error() == Some(libc::EINTR) => {}
+            Err(e) => return Err(e),
+        }
+        if let Some(cqe) = ring.completion().next() {
+            break cqe.result();
+        }
+    };
+    match result {
+        result if result == -libc::EINVAL => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("内核的 io_uring 不支持 {}（需要 Linux 6.0 以上）", name),
+        )),
+        result if result < 0 => Err(io::Error::from_raw_os_error(-result)),
+        result => Ok(result),
+    }
+}
+
+/// 提交 entries 中的操作并等待至少一个完成事件
+pub fn submit_and_wait(ring: &mut IoUring, entries: &mut Vec<squeue::Entry>) -> io::Result<()> {
+    for entry in entries.drain(..) {
+        // 提交队列满时先把已有的操作提交给内核
+        while unsafe { ring.submission().push(&entry) }.is_err() {
+            ring.submit()?;
+        }
+    }
+    match ring.submit_and_wait(1) {
+        Ok(_) => Ok(()),
+        // 被信号中断，或者完成队列溢出，需要先取走完成事件
+        Err(e) if matches!(e.raw_os_error(), Some(libc::EINTR | libc::EBUSY)) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// 完成事件中的 user_data，低 3 位为操作类型，其余为 token 或监听器下标
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Wake,
+    Accept(usize),
+    Recv(u64),
+    Send(u64),
+    Cancel,
+}
+
+impl Op {
+    pub fn encode(self) -> u64 {
+        match self {
+            Op::Wake => 0,
+            Op::Accept(index) => (index as u64) << 3 | 1,
+            Op::Recv(token) => token << 3 | 2,
+            Op::Send(token) => token << 3 | 3,
+            Op::Cancel => 4,
+        }
+    }
+
+    pub fn decode(data: u64) -> Op {
+        match data & 0b111 {
+            0 => Op::Wake,
+            1 => Op::Accept((data >> 3) as usize),
+            2 => Op::Recv(data >> 3),
+            3 => Op::Send(data >> 3),
+            _ => Op::Cancel,
+        }
+    }
+}
+
+/// 注册给内核的提供缓冲环：multishot recv 每次从中取一个缓冲，处理完后再放回
+pub struct BufRing {
+    ring: *mut BufRingEntry,
+    entries: u16,
+    buf_size: usize,
+    bufs: Vec<u8>,
+    /// 下一个放回位置，发布后内核才能看到
+    tail: u16,
+}
+
+impl BufRing {
+    pub fn register(submitter: &Submitter, entries: u16, buf_size: usize) -> io::Result<Self> {
+        assert!(entries.is_power_of_two(), "缓冲环的长度必须是 2 的幂");
+        let ring_size = entries as usize * size_of::<BufRingEntry>();
+        // 环的起始地址必须按页对齐
+        let ring = unsafe {
+            libc::mmap(std::ptr::null_mut(), ring_size, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_PRIVATE | libc::MAP_ANONYMOUS, -1, 0)
+        };
+        if ring == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let mut buf_ring = BufRing {
+            ring: ring.cast(),
+            entries,
+            buf_size,
+            bufs: vec![0; entries as usize * buf_size],
+            tail: 0,
+        };
+        unsafe { submitter.register_buf_ring_with_flags(ring as u64, entries, BUF_GROUP, 0) }.map_err(|e| {
+            if e.raw_os_error() == Some(libc::EINVAL) {
+                unsupported("内核不支持提供缓冲环（需要 Linux 5.19 以上）", e)
+            } else {
+                e
+            }
+        })?;
+        for bid in 0..entries {
+            buf_ring.push(bid);
+        }
+        buf_ring.publish();
+        Ok(buf_ring)
+    }
+
+    /// 内核写入数据的缓冲
+    pub fn buffer(&self, bid: u16, len: usize) -> &[u8] {
+        let start = bid as usize * self.buf_size;
+        &self.bufs[start..start + len]
+    }
+
+    /// 把处理完的缓冲放回环中
+    pub fn recycle(&mut self, bid: u16) {
+        self.push(bid);
+        self.publish();
+    }
+
+    fn push(&mut self, bid: u16) {
+        let entry = unsafe { &mut *self.ring.add((self.tail & (self.entries - 1)) as usize) };
+        entry.set_addr(self.bufs[bid as usize * self.buf_size..].as_ptr() as u64);
+        entry.set_len(self.buf_size as u32);
+        entry.set_bid(bid);
+        self.tail = self.tail.wrapping_add(1);
+    }
+
+    fn publish(&self) {
+        // 环尾与第一个条目的保留字段共用内存，内核按 acquire 读取
+        let tail = unsafe { &*BufRingEntry::tail(self.ring).cast::<AtomicU16>() };
+        tail.store(self.tail, Ordering::Release);
+    }
+}
+
+impl Drop for BufRing {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ring.cast(), self.entries as usize * size_of::<BufRingEntry>()) };
+    }
+}
+
+/// io_uring 模型中的一个连接：接收由 multishot recv 完成，发送缓冲在发送完成前交给内核
+pub struct Connection {
+    stream: TcpStream,
+    session: Session,
+    /// 尚未提交的响应
+    out: Vec<u8>,
+    /// 已提交给内核的响应，发送完成前不能修改
+    sending: Vec<u8>,
+    /// sending 中已经发送的字节数
+    sent: usize,
+    recv_armed: bool,
+    send_in_flight: bool,
+    /// 已经请求取消接收，等待取消完成
+    cancelling: bool,
+    /// 写完剩余的响应后关闭
+    closing: bool,
+    /// 已经关闭套接字，等待进行中的操作结束
+    shut: bool,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream, ctx: &ConnContext) -> Self {
+        Connection {
+            stream,
+            session: Session::new(ctx),
+            out: Vec::new(),
+            sending: Vec::new(),
+            sent: 0,
+            recv_armed: false,
+            send_in_flight: false,
+            cancelling: false,
+            closing: false,
+            shut: false,
+        }
+    }
+
+    pub fn ctx(&self) -> &ConnContext {
+        self.session.ctx()
+    }
+
+    fn pending(&self) -> usize {
+        self.out.len() + self.sending.len() - self.sent
+    }
+
+    /// 处理 recv 的完成事件；`more` 为 false 时本次 multishot recv 已经结束
+    pub fn received<H: Handler + ?Sized>(&mut self, result: io::Result<&[u8]>, more: bool, handler: &H) {
+        if !more {
+            self.recv_armed = false;
+        }
+        match result {
+            Ok([]) => {
+                if !self.shut {
+                    // 客户端正常关闭连接
+                    log_info!("[{}] client[{}] is closed!", self.ctx().id, self.ctx().peer_addr);
+                }
+                self.closing = true;
+            }
+            Ok(bytes) => {
+                if !self.closing && !self.session.receive(bytes, handler, &mut self.out) {
+                    self.closing = true;
+                }
+            }
+            // 缓冲暂时用完或者被取消，之后重新开始接收
+            Err(e) if matches!(e.raw_os_error(), Some(libc::ENOBUFS | libc::ECANCELED)) => {}
+            Err(e) => {
+                if !self.shut {
+                    eprintln!("[{}] 读取客户端 {} 数据失败: {}", self.ctx().id, self.ctx().peer_addr, e);
+                }
+                self.abort();
+            }
+        }
+    }
+
+    /// 处理 send 的完成事件
+    pub fn sent(&mut self, result: io::Result<usize>) {
+        self.send_in_flight = false;
+        match result {
+            Ok(size) => {
+                self.sent += size;
+                if self.sent == self.sending.len() {
+                    self.sending.clear();
+                    self.sent = 0;
+                }
+            }
+            Err(e) => {
+                if !self.shut {
+                    eprintln!("[{}] 写入客户端 {} 失败: {}", self.ctx().id, self.ctx().peer_addr, e);
+                }
+                self.abort();
+            }
+        }
+    }
+
+    /// 不再处理新的请求，发送 going-away 后关闭
+    pub fn going_away(&mut self) {
+        if !self.closing {
+            self.session.going_away(&mut self.out);
+            self.closing = true;
+        }
+    }
+
+    /// 丢弃未发送的响应并立即关闭
+    fn abort(&mut self) {
+        self.out.clear();
+        if !self.send_in_flight {
+            self.sending.clear();
+            self.sent = 0;
+        }
+        self.closing = true;
+    }
+
+    /// 按当前状态提交需要的操作：发送积攒的响应、重新开始接收、写缓冲积压时暂停接收、写完后关闭
+    pub fn advance(&mut self, token: u64, submit: &mut Vec<squeue::Entry>) {
+        let fd = types::Fd(self.stream.as_raw_fd());
+        if !self.send_in_flight && !self.shut {
+            if self.sending.is_empty() {
+                std::mem::swap(&mut self.out, &mut self.sending);
+            }
+            if self.sent < self.sending.len() {
+                let remaining = &self.sending[self.sent..];
+                submit.push(
+                    opcode::Send::new(fd, remaining.as_ptr(), remaining.len() as u32)
+                        .flags(libc::MSG_NOSIGNAL)
+                        .build()
+                        .user_data(Op::Send(token).encode()),
+                );
+                self.send_in_flight = true;
+            }
+        }
+
+        if self.closing {
+            if !self.shut && self.pending() == 0 {
+                // 关闭读写两端，进行中的 recv 随之结束
+                let _ = self.stream.shutdown(Shutdown::Both);
+                self.shut = true;
+            }
+        } else if !self.recv_armed && self.pending() < WRITE_HIGH_WATER {
+            submit.push(opcode::RecvMulti::new(fd, BUF_GROUP).build().user_data(Op::Recv(token).encode()));
+            self.recv_armed = true;
+            self.cancelling = false;
+        } else if self.recv_armed && !self.cancelling && self.pending() >= WRITE_HIGH_WATER {
+            // 对端没有及时读取响应，发送完成后再继续接收
+            submit.push(opcode::AsyncCancel::new(Op::Recv(token).encode()).build().user_data(Op::Cancel.encode()));
+            self.cancelling = true;
+        }
+    }
+
+    /// 连接已经关闭且内核中没有进行中的操作，可以释放
+    pub fn is_done(&self) -> bool {
+        self.shut && !self.recv_armed && !self.send_in_flight
+    }
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use std::io::Write;
+use std::net::TcpStream;
+use std::process::{Command, Stdio};
+
+use common::{reader, server_command, spawn_server};
+use socket::codec::FrameAssembler;
+use socket::network_handler::{Checksum, FrameFormat, MsgType, Pdu, PduError};
+
+fn frame(payload: &[u8], format: &FrameFormat) -> Vec<u8> {
+    Pdu::new(payload, format).unwrap().to_vec(format).unwrap()
+}
+
+#[test]
+fn checksum_round_trip() {
+    for checksum in [Checksum::Crc32c, Checksum::XxHash32] {
+        let format = FrameFormat::default().with_checksum(checksum);
+        let bytes = frame(b"hello", &format);
+        assert_eq!(bytes.len(), 7 + 4 + 5 + 4);
+
+        let pdu = Pdu::from_bytes(&bytes, &format).unwrap();
+        assert_eq!(pdu.payload, b"hello");
+        assert_eq!(pdu.checksum().unwrap(), checksum);
+    }
+}
+
+#[test]
+fn corrupted_payload_is_detected() {
+    for checksum in [Checksum::Crc32c, Checksum::XxHash32] {
+        let format = FrameFormat::default().with_checksum(checksum);
+        let mut bytes = frame(b"hello", &format);
+        bytes[12] ^= 0x01;
+
+        assert!(matches!(Pdu::from_bytes(&bytes, &format), Err(PduError::BadChecksum { .. })));
+    }
+}
+
+#[test]
+fn receiver_verifies_by_flags() {
+    // 接收方不需要配置校验和，按每个 PDU 的 flags 校验
+    let sender = FrameFormat::default().with_checksum(Checksum::Crc32c);
+    let receiver = FrameFormat::default();
+    let pdu = Pdu::from_bytes(&frame(b"hello", &sender), &receiver).unwrap();
+    assert_eq!(pdu.payload, b"hello");
+}
+
+#[test]
+fn assembler_skips_corrupted_frame() {
+    let format = FrameFormat::default().with_checksum(Checksum::Crc32c);
+    let mut bad = frame(b"first", &format);
+    let last = bad.len() - 5;
+    bad[last] ^= 0xff;
+
+    let mut assembler = FrameAssembler::new(format);
+    assembler.push(&bad);
+    assembler.push(&frame(b"second", &format));
+
+    assert!(matches!(assembler.next_pdu(), Err(PduError::BadChecksum { .. })));
+    assert_eq!(assembler.next_pdu().unwrap().unwrap().payload, b"second");
+    assert_eq!(assembler.buffered_len(), 0);
+}
+
+#[test]
+fn binaries_use_configured_checksum() {
+    let (mut server, addr, lines) =
+        spawn_server(server_command(env!("CARGO_BIN_EXE_server_muti_thread")).args(["--bind", "127.0.0.1:0", "--checksum", "crc32c"]));
+
+    // 客户端程序发送带校验和的 PDU，服务器校验后原样返回
+    let mut client = Command::new(env!("CARGO_BIN_EXE_client"))
+        .args(["--connect", &addr.to_string(), "--checksum", "crc32c"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    client.stdin.take().unwrap().write_all(b"hello\nEXIT\n").unwrap();
+    let output = client.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("flags=0x01") && stdout.contains("payload=\"hello\n\"]"), "{}", stdout);
+
+    // 服务器自己创建的错误 PDU 也带校验和，之后断开连接
+    let format = FrameFormat::default().with_checksum(Checksum::Crc32c);
+    let stream = TcpStream::connect(addr).unwrap();
+    let mut bad = frame(b"hello", &format);
+    bad[12] ^= 0x01;
+    (&stream).write_all(&bad).unwrap();
+    let mut reader = reader(&stream);
+    let error = reader.read_pdu().unwrap().unwrap();
+    assert_eq!(error.kind, MsgType::Error);
+    assert_eq!(error.checksum().unwrap(), Checksum::Crc32c);
+    assert!(reader.read_pdu().unwrap().is_none());
+
+    unsafe { libc::kill(server.id() as i32, libc::SIGINT) };
+    lines.iter().for_each(drop);
+    assert!(server.wait().unwrap().success());
+}
@@ -0,0 +1,135 @@
+mod common;
+
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::process::{Command, Stdio};
+
+use clap::Parser;
+use common::{server_command, spawn_server};
+use socket::cli::{self, with_port, ServerArgs};
+use socket::codec::{PduReader, PduWriter};
+use socket::network_handler::{FrameFormat, MsgType, Pdu};
+use socket::server::Model;
+
+fn echo(stream: &TcpStream, request_id: u32) {
+    let format = FrameFormat::default();
+    PduWriter::new(stream, format).write_pdu(&Pdu::with_type(MsgType::Data, request_id, b"hello", &format).unwrap()).unwrap();
+    let response = PduReader::new(stream, format).read_pdu().unwrap().unwrap();
+    assert_eq!(response.request_id, request_id);
+    assert_eq!(response.payload, b"hello");
+}
+
+#[test]
+fn port_is_added_when_missing() {
+    assert_eq!(with_port("0.0.0.0", 8080), "0.0.0.0:8080");
+    assert_eq!(with_port("127.0.0.1:9000", 8080), "127.0.0.1:9000");
+    assert_eq!(with_port("::1", 8080), "[::1]:8080");
+    assert_eq!(with_port("[::1]", 8080), "[::1]:8080");
+    assert_eq!(with_port("[::1]:9000", 8080), "[::1]:9000");
+    assert_eq!(with_port("localhost", 8080), "localhost:8080");
+    assert_eq!(with_port("localhost:9000", 8080), "localhost:9000");
+    assert_eq!(with_port(":0", 8080), "0.0.0.0:0");
+}
+
+#[test]
+fn listen_args_are_parsed() {
+    let args = ServerArgs::try_parse_from(["server"]).unwrap();
+    assert_eq!(args.config().unwrap().addrs(), ["0.0.0.0:8080"]);
+
+    let args = ServerArgs::try_parse_from(["server", "--bind", "127.0.0.1", "--bind", "[::1]:9000", "--port", "0", "--backlog", "16"]).unwrap();
+    let config = args.config().unwrap();
+    assert_eq!(config.addrs(), ["127.0.0.1:0", "[::1]:9000"]);
+    assert_eq!(config.server.backlog, Some(16));
+
+    assert!(ServerArgs::try_parse_from(["server", "--port", "70000"]).is_err());
+}
+
+fn multiple_listeners(model: Model) {
+    let args = ServerArgs::try_parse_from(["server", "--bind", "127.0.0.1:0", "--bind", "127.0.0.1:0"]).unwrap();
+    let (server, _, runner) = common::start(args.builder(model).unwrap());
+    let addrs = server.local_addrs().unwrap();
+    assert_eq!(addrs.len(), 2);
+    assert_ne!(addrs[0].port(), addrs[1].port());
+    assert_ne!(addrs[0].port(), 0);
+
+    for (request_id, addr) in addrs.iter().enumerate() {
+        let stream = TcpStream::connect(addr).unwrap();
+        echo(&stream, request_id as u32 + 1);
+    }
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+}
+
+#[test]
+fn single_server_accepts_on_every_listener() {
+    multiple_listeners(Model::Single);
+}
+
+#[test]
+fn thread_server_accepts_on_every_listener() {
+    multiple_listeners(Model::ThreadPerConnection);
+}
+
+#[test]
+fn tokio_server_accepts_on_every_listener() {
+    multiple_listeners(Model::Tokio);
+}
+
+#[test]
+fn connect_tries_addresses_in_order() {
+    // 先绑定再释放，得到一个没有监听的端口
+    let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let open = listener.local_addr().unwrap();
+
+    let stream = cli::connect_any(&[closed, open]).unwrap();
+    assert_eq!(stream.peer_addr().unwrap(), open);
+    assert!(cli::connect_any(&[closed]).is_err());
+
+    let stream = cli::connect(&format!("localhost:{}", open.port())).unwrap();
+    assert_eq!(stream.peer_addr().unwrap().port(), open.port());
+}
+
+#[test]
+fn binary_prints_the_chosen_port() {
+    let (mut child, addr, lines) =
+        spawn_server(server_command(env!("CARGO_BIN_EXE_server_muti_thread")).args(["--bind", "127.0.0.1", "--port", "0"]));
+    assert_ne!(addr.port(), 0);
+
+    let stream = TcpStream::connect(addr).unwrap();
+    echo(&stream, 1);
+    drop(stream);
+
+    unsafe { libc::kill(child.id() as i32, libc::SIGINT) };
+    lines.iter().for_each(drop);
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn binaries_share_frame_options() {
+    // v1 兼容模式：服务器与客户端都使用 1 字节长度字段
+    let frame = ["--protocol-version", "v1", "--length-format", "u8", "--max-payload", "200"];
+    let (mut server, addr, lines) =
+        spawn_server(server_command(env!("CARGO_BIN_EXE_server_muti_thread")).args(["--bind", "127.0.0.1:0"]).args(frame));
+
+    let mut client = Command::new(env!("CARGO_BIN_EXE_client"))
+        .args(["--connect", &addr.to_string()])
+        .args(frame)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    client.stdin.take().unwrap().write_all(b"hello\nEXIT\n").unwrap();
+    let output = client.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("payload=\"hello\n\"]"), "{}", stdout);
+
+    // 超出长度字段范围的上限被拒绝
+    let output = Command::new(env!("CARGO_BIN_EXE_client")).args(["--length-format", "u8", "--max-payload", "256"]).output().unwrap();
+    assert!(String::from_utf8_lossy(&output.stderr).contains("max_payload"));
+
+    unsafe { libc::kill(server.id() as i32, libc::SIGINT) };
+    lines.iter().for_each(drop);
+    assert!(server.wait().unwrap().success());
+}
@@ -0,0 +1,103 @@
+use futures::{SinkExt, StreamExt};
+use tokio::io::AsyncWriteExt;
+use tokio_util::codec::{Framed, FramedRead};
+
+use socket::codec::PduCodec;
+use socket::network_handler::{Checksum, FrameFormat, LengthFormat, MsgType, Pdu, PduError};
+
+fn encode(request_id: u32, payload: &[u8], format: &FrameFormat) -> Vec<u8> {
+    Pdu::with_type(MsgType::Data, request_id, payload, format).unwrap().to_vec(format).unwrap()
+}
+
+#[tokio::test]
+async fn partial_input_waits_for_the_rest() {
+    let format = FrameFormat::default();
+    let (mut writer, reader) = tokio::io::duplex(64);
+    let mut framed = FramedRead::new(reader, PduCodec::new(format));
+
+    // 每次只写入一个字节，解码器要等到整个 PDU 到达才返回
+    let bytes = encode(7, b"partial", &format);
+    tokio::spawn(async move {
+        for byte in bytes {
+            writer.write_all(&[byte]).await.unwrap();
+            tokio::task::yield_now().await;
+        }
+    });
+
+    let pdu = framed.next().await.unwrap().unwrap();
+    assert_eq!(pdu.request_id, 7);
+    assert_eq!(pdu.payload, b"partial");
+    // 写端关闭后流结束，缓冲中没有剩余数据
+    assert!(framed.next().await.is_none());
+}
+
+#[tokio::test]
+async fn several_frames_in_one_buffer() {
+    let format = FrameFormat::new(LengthFormat::Varint, 1024).with_checksum(Checksum::Crc32c);
+    let (mut writer, reader) = tokio::io::duplex(4096);
+    let mut framed = FramedRead::new(reader, PduCodec::new(format));
+
+    let bytes = [encode(1, b"one", &format), encode(2, b"", &format), encode(3, &[0x42; 300], &format)].concat();
+    writer.write_all(&bytes).await.unwrap();
+    drop(writer);
+
+    let mut received = Vec::new();
+    while let Some(pdu) = framed.next().await {
+        let pdu = pdu.unwrap();
+        received.push((pdu.request_id, pdu.payload));
+    }
+    assert_eq!(received, [(1, b"one".to_vec()), (2, Vec::new()), (3, vec![0x42; 300])]);
+}
+
+#[tokio::test]
+async fn oversize_frame_ends_the_stream() {
+    let format = FrameFormat::new(LengthFormat::U32, 16);
+    let (mut writer, reader) = tokio::io::duplex(1024);
+    let mut framed = FramedRead::new(reader, PduCodec::new(format));
+
+    // 发送端允许更大的 payload，接收端只看长度字段就拒绝
+    let sender = FrameFormat::new(LengthFormat::U32, 1024);
+    writer.write_all(&encode(1, &[0; 32], &sender)).await.unwrap();
+    writer.write_all(&encode(2, b"small", &sender)).await.unwrap();
+
+    assert!(matches!(framed.next().await, Some(Err(PduError::Oversize { len: 32, max: 16 }))));
+    assert!(framed.next().await.is_none());
+
+    // 编码时同样检查上限
+    let mut framed = Framed::new(writer, PduCodec::new(format));
+    let pdu = Pdu::with_type(MsgType::Data, 3, &[0; 32], &sender).unwrap();
+    assert!(matches!(framed.send(pdu).await, Err(PduError::Oversize { len: 32, max: 16 })));
+}
+
+#[tokio::test]
+async fn encode_round_trip() {
+    let format = FrameFormat::new(LengthFormat::U16, 4096).with_checksum(Checksum::XxHash32);
+    let (client, server) = tokio::io::duplex(256);
+    let mut client = Framed::new(client, PduCodec::new(format));
+    let mut server = Framed::new(server, PduCodec::new(format));
+
+    // 服务器把收到的 PDU 原样发回
+    let echo = tokio::spawn(async move {
+        while let Some(pdu) = server.next().await {
+            server.send(pdu.unwrap()).await.unwrap();
+        }
+    });
+
+    let requests = [
+        Pdu::with_type(MsgType::Data, 1, b"hello", &format).unwrap(),
+        Pdu::with_type(MsgType::Heartbeat, 2, b"", &format).unwrap(),
+        Pdu::with_type(MsgType::Data, 3, &[0x5a; 1000], &format).unwrap(),
+    ];
+    for request in requests {
+        let (kind, request_id, payload) = (request.kind, request.request_id, request.payload.clone());
+        client.send(request).await.unwrap();
+        let response = client.next().await.unwrap().unwrap();
+        assert_eq!(response.kind, kind);
+        assert_eq!(response.request_id, request_id);
+        assert_eq!(response.payload, payload);
+        assert_eq!(response.checksum().unwrap(), Checksum::XxHash32);
+    }
+
+    drop(client);
+    echo.await.unwrap();
+}
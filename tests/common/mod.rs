@@ -0,0 +1,195 @@
+//! 集成测试共用的辅助函数，每个测试文件只用到其中的一部分
+#![allow(dead_code)]
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use socket::codec::PduReader;
+use socket::config;
+use socket::network_handler::{FrameFormat, MsgType, Pdu};
+use socket::server::{Server, ServerBuilder};
+
+/// 创建服务器并在后台线程中运行，返回服务器、实际的监听地址与运行线程
+pub fn start(builder: ServerBuilder) -> (Arc<Server>, SocketAddr, thread::JoinHandle<io::Result<()>>) {
+    let server = Arc::new(builder.build().unwrap());
+    let addr = server.local_addr().unwrap();
+    let runner = thread::spawn({
+        let server = server.clone();
+        move || server.run()
+    });
+    (server, addr, runner)
+}
+
+/// 按默认帧格式编码的数据 PDU
+pub fn pdu(request_id: u32, payload: &[u8]) -> Vec<u8> {
+    let format = FrameFormat::default();
+    Pdu::with_type(MsgType::Data, request_id, payload, &format).unwrap().to_vec(&format).unwrap()
+}
+
+/// 发送一个 payload 为 `hello` 的请求
+pub fn send(stream: &TcpStream, request_id: u32) {
+    (&*stream).write_all(&pdu(request_id, b"hello")).unwrap();
+}
+
+/// 按默认帧格式读取连接上的 PDU
+///
+/// 每个连接只创建一个：读取时可能一次收到多个 PDU，丢弃 reader 会丢掉已经读入缓冲的数据。
+pub fn reader(stream: &TcpStream) -> PduReader<&TcpStream> {
+    PduReader::new(stream, FrameFormat::default())
+}
+
+/// 每个客户端连接在测试进程与服务器中各占一个描述符，把软上限提高到硬上限
+pub fn raise_fd_limit() {
+    unsafe {
+        let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+        libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit);
+        limit.rlim_cur = limit.rlim_max;
+        libc::setrlimit(libc::RLIMIT_NOFILE, &limit);
+    }
+}
+
+/// 服务器程序启动时输出的监听地址
+pub fn initializing(line: &str) -> Option<SocketAddr> {
+    line.strip_prefix("[srv] server[")?.strip_suffix("] is initializing!")?.parse().ok()
+}
+
+/// 运行服务器程序的命令，不继承可能影响测试的环境变量
+///
+/// 按名字清除 `apply_env` 读取的变量，其他测试稍后才设置的也不会被继承；再清除当前存在的其他 SOCKET_ 变量。
+pub fn server_command(bin: &str) -> Command {
+    let mut command = Command::new(bin);
+    command.env_remove(config::ENV_CONFIG);
+    for name in config::ENV_OVERRIDES {
+        command.env_remove(name);
+    }
+    for (name, _) in std::env::vars_os() {
+        if name.to_string_lossy().starts_with("SOCKET_") {
+            command.env_remove(name);
+        }
+    }
+    command
+}
+
+/// 启动服务器程序并等待它输出监听地址
+///
+/// stdout 由单独的线程一直读到文件末尾并按行转发，服务器不会阻塞在输出上；
+/// 返回的 Receiver 在服务器（以及继承了 stdout 的子进程）全部退出后结束。
+pub fn spawn_server(command: &mut Command) -> (Child, SocketAddr, Receiver<String>) {
+    let mut child = command.stdout(Stdio::piped()).spawn().unwrap();
+    let (tx, rx) = mpsc::channel();
+    let stdout = child.stdout.take().unwrap();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            // 测试不再关心输出时继续读取并丢弃
+            let _ = tx.send(line.unwrap());
+        }
+    });
+    let addr = wait_for(&rx, initializing);
+    (child, addr, rx)
+}
+
+/// 等待服务器输出一行满足条件的日志
+pub fn wait_for<T>(lines: &Receiver<String>, mut matches: impl FnMut(&str) -> Option<T>) -> T {
+    loop {
+        let line = lines.recv_timeout(Duration::from_secs(10)).expect("没有等到预期的输出");
+        if let Some(value) = matches(&line) {
+            return value;
+        }
+    }
+}
+
+/// 服务器程序回显请求，收到 SIGINT 后关闭连接并正常退出
+pub fn binary_exits_on_sigint(bin: &str) {
+    let (mut child, addr, lines) = spawn_server(server_command(bin).args(["--bind", "127.0.0.1:0"]));
+
+    let stream = TcpStream::connect(addr).unwrap();
+    let mut reader = reader(&stream);
+    send(&stream, 1);
+    assert_eq!(reader.read_pdu().unwrap().unwrap().payload, b"hello");
+
+    unsafe { libc::kill(child.id() as i32, libc::SIGINT) };
+    // 连接被关闭
+    assert!(reader.read_pdu().unwrap().is_none());
+    assert!(child.wait().unwrap().success());
+    assert!(lines.iter().any(|line| line == "服务器关闭"));
+}
+
+/// 以 16 个描述符的上限运行服务器程序：超出上限的连接留在监听队列中，
+/// 服务器报告 RLIMIT_NOFILE，有连接关闭后继续接受等待中的连接
+pub fn binary_resumes_after_fd_limit(bin: &str) {
+    let mut command = server_command(bin);
+    command.args(["--bind", "127.0.0.1:0"]).stderr(Stdio::piped());
+    unsafe {
+        command.pre_exec(|| {
+            let limit = libc::rlimit { rlim_cur: 16, rlim_max: 16 };
+            if libc::setrlimit(libc::RLIMIT_NOFILE, &limit) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let (mut child, addr, lines) = spawn_server(&mut command);
+    let errors = BufReader::new(child.stderr.take().unwrap()).lines().map(Result::unwrap);
+    let errors = thread::spawn(move || errors.collect::<Vec<_>>());
+
+    // 等待中的连接之后还要继续读取，每个连接保留自己的读取器
+    let clients: Vec<PduReader<TcpStream>> = (0..12)
+        .map(|id| {
+            let client = TcpStream::connect(addr).unwrap();
+            client.set_read_timeout(Some(Duration::from_millis(300))).unwrap();
+            (&client).write_all(&pdu(id, b"hello")).unwrap();
+            PduReader::new(client, FrameFormat::default())
+        })
+        .collect();
+    let (mut served, mut waiting) = (Vec::new(), Vec::new());
+    for mut client in clients {
+        match client.read_pdu() {
+            Ok(Some(_)) => served.push(client),
+            _ => waiting.push(client),
+        }
+    }
+    assert!(!served.is_empty());
+    assert!(!waiting.is_empty());
+
+    // 已服务的连接关闭后，等待中的连接被接受并得到响应，期间没有新的连接到达
+    drop(served);
+    for client in &mut waiting {
+        client.get_ref().set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(client.read_pdu().unwrap().unwrap().payload, b"hello");
+    }
+
+    drop(waiting);
+    unsafe { libc::kill(child.id() as i32, libc::SIGINT) };
+    assert!(child.wait().unwrap().success());
+    assert!(lines.iter().any(|line| line == "服务器关闭"));
+    assert!(errors.join().unwrap().iter().any(|line| line.contains("RLIMIT_NOFILE (16)")));
+}
+
+/// 测试用的临时文件，离开作用域时删除
+pub struct TempFile(pub PathBuf);
+
+impl TempFile {
+    pub fn new(name: &str, text: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("socket-test-{}-{}", std::process::id(), name));
+        std::fs::write(&path, text).unwrap();
+        TempFile(path)
+    }
+
+    /// 覆盖文件内容
+    pub fn write(&self, text: &str) {
+        std::fs::write(&self.0, text).unwrap();
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
@@ -0,0 +1,183 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::process::{Command, Stdio};
+
+use common::{server_command, spawn_server};
+use socket::codec::FrameAssembler;
+use socket::network_handler::{Checksum, Compression, FrameFormat, MsgType, Pdu, PduError, FLAG_COMPRESSION_MASK};
+use socket::server::{Model, Server};
+
+fn text(len: usize) -> Vec<u8> {
+    b"the quick brown fox jumps over the lazy dog. ".iter().copied().cycle().take(len).collect()
+}
+
+#[test]
+fn round_trip_with_each_algorithm() {
+    for compression in Compression::supported() {
+        let format = FrameFormat::default().with_compression(compression).with_checksum(Checksum::Crc32c);
+        let payload = text(4000);
+        let bytes = Pdu::with_type(MsgType::Data, 9, &payload, &format).unwrap().to_vec(&format).unwrap();
+        assert!(bytes.len() < payload.len() / 2, "{:?}", compression);
+
+        // 接收方按 flags 解压，不依赖自己的默认算法
+        let pdu = Pdu::from_bytes(&bytes, &FrameFormat::default()).unwrap();
+        assert_eq!(pdu.compression().unwrap(), compression);
+        assert_eq!(pdu.length as usize, payload.len());
+        assert_eq!(pdu.payload, payload);
+    }
+}
+
+#[test]
+fn small_payloads_are_sent_raw() {
+    for compression in Compression::supported() {
+        let format = FrameFormat::default().with_compression(compression).compress_threshold(64);
+        let bytes = Pdu::new(&text(63), &format).unwrap().to_vec(&format).unwrap();
+        assert_eq!(bytes[2] & FLAG_COMPRESSION_MASK, 0);
+
+        let bytes = Pdu::new(&text(64), &format).unwrap().to_vec(&format).unwrap();
+        assert_eq!(bytes[2] & FLAG_COMPRESSION_MASK, compression.flag());
+    }
+}
+
+#[test]
+fn incompressible_payloads_are_sent_raw() {
+    let payload: Vec<u8> = (0..1000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+    for compression in Compression::supported() {
+        let format = FrameFormat::default().with_compression(compression);
+        let bytes = Pdu::new(&payload, &format).unwrap().to_vec(&format).unwrap();
+        if bytes[2] & FLAG_COMPRESSION_MASK == 0 {
+            assert_eq!(Pdu::from_bytes(&bytes, &format).unwrap().payload, payload);
+        }
+        assert!(bytes.len() <= payload.len() + 11);
+    }
+}
+
+#[test]
+fn decompressed_size_is_limited() {
+    for compression in Compression::supported() {
+        let sender = FrameFormat::default().with_compression(compression);
+        let bytes = Pdu::new(&text(10_000), &sender).unwrap().to_vec(&sender).unwrap();
+
+        let receiver = FrameFormat::default().compress_threshold(0);
+        let receiver = FrameFormat { max_payload: 4096, ..receiver };
+        assert!(matches!(Pdu::from_bytes(&bytes, &receiver), Err(PduError::Oversize { .. } | PduError::Compression(_))));
+    }
+}
+
+#[test]
+fn unknown_algorithm_is_rejected() {
+    let format = FrameFormat::default();
+    let mut bytes = Pdu::new(b"hello", &format).unwrap().to_vec(&format).unwrap();
+    bytes[2] |= FLAG_COMPRESSION_MASK;
+    assert!(matches!(Pdu::from_bytes(&bytes, &format), Err(PduError::UnsupportedFlags(_))));
+}
+
+#[test]
+fn negotiation_prefers_server_choice() {
+    let supported = Compression::supported();
+    assert!(Compression::negotiate(Compression::None, &[]).is_empty());
+    assert_eq!(Compression::negotiate(Compression::None, &supported), supported);
+
+    let Some(&preferred) = supported.first() else { return };
+    let mut offered = supported.clone();
+    offered.reverse();
+    let accepted = Compression::negotiate(preferred, &offered);
+    assert_eq!(accepted.first(), supported.first());
+    assert_eq!(accepted.len(), supported.len());
+
+    let format = FrameFormat::default();
+    let pdu = Pdu::with_type(MsgType::Control, 0, &Compression::handshake_payload(&offered), &format).unwrap();
+    assert_eq!(Compression::parse_handshake(&pdu).unwrap(), offered);
+}
+
+/// 读取下一个 PDU，同时返回其线上的 flags
+fn read_pdu(stream: &mut TcpStream, assembler: &mut FrameAssembler) -> (Pdu, u8) {
+    let mut buffer = [0; 4096];
+    let mut received = Vec::new();
+    loop {
+        if let Some(pdu) = assembler.next_pdu().unwrap() {
+            let flags = received[2];
+            return (pdu, flags);
+        }
+        let size = stream.read(&mut buffer).unwrap();
+        assert!(size > 0, "服务器关闭了连接");
+        received.extend_from_slice(&buffer[..size]);
+        assembler.push(&buffer[..size]);
+    }
+}
+
+#[test]
+fn echo_uses_request_algorithm() {
+    let Some(&compression) = Compression::supported().last() else { return };
+    let (server, addr, runner) = common::start(Server::builder().bind("127.0.0.1:0").model(Model::ThreadPerConnection));
+
+    let format = FrameFormat::default();
+    let mut stream = TcpStream::connect(addr).unwrap();
+    let mut assembler = FrameAssembler::new(format);
+
+    // 协商前服务器不会压缩响应
+    let payload = text(2000);
+    let compressed_format = format.with_compression(compression);
+    let request = Pdu::with_type(MsgType::Data, 1, &payload, &compressed_format).unwrap();
+    stream.write_all(&request.to_vec(&compressed_format).unwrap()).unwrap();
+    let (response, flags) = read_pdu(&mut stream, &mut assembler);
+    assert_eq!(response.payload, payload);
+    assert_eq!(flags & FLAG_COMPRESSION_MASK, 0);
+
+    let handshake = Pdu::with_type(MsgType::Control, 0, &Compression::handshake_payload(&[compression]), &format).unwrap();
+    stream.write_all(&handshake.to_vec(&format).unwrap()).unwrap();
+    let (response, _) = read_pdu(&mut stream, &mut FrameAssembler::new(format));
+    assert_eq!(Compression::parse_handshake(&response).unwrap(), [compression]);
+
+    let mut assembler = FrameAssembler::new(format);
+    stream.write_all(&request.to_vec(&compressed_format).unwrap()).unwrap();
+    let (response, flags) = read_pdu(&mut stream, &mut assembler);
+    assert_eq!(response.request_id, 1);
+    assert_eq!(response.payload, payload);
+    assert_eq!(flags & FLAG_COMPRESSION_MASK, compression.flag());
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+}
+
+#[test]
+fn binaries_use_configured_compression() {
+    if !Compression::Zstd.is_supported() || !Compression::Lz4.is_supported() {
+        return;
+    }
+    let (mut server, addr, lines) = spawn_server(
+        server_command(env!("CARGO_BIN_EXE_server_muti_thread"))
+            .args(["--bind", "127.0.0.1:0", "--compression", "lz4", "--compress-threshold", "16"]),
+    );
+
+    // 客户端首选 zstd，服务器的首选算法排在最前；超过阈值的 payload 压缩后发送，echo 沿用该算法
+    let mut client = Command::new(env!("CARGO_BIN_EXE_client"))
+        .args(["--connect", &addr.to_string(), "--compression", "zstd", "--compress-threshold", "16"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut input = text(200);
+    input.extend_from_slice(b"\nEXIT\n");
+    client.stdin.take().unwrap().write_all(&input).unwrap();
+    let output = client.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("协商的压缩算法: [Lz4, Zstd]"), "{}", stdout);
+    assert!(stdout.contains(&format!("flags=0x{:02x}", Compression::Lz4.flag())), "{}", stdout);
+
+    unsafe { libc::kill(server.id() as i32, libc::SIGINT) };
+    lines.iter().for_each(drop);
+    assert!(server.wait().unwrap().success());
+}
+
+#[test]
+fn binary_rejects_compression_with_v1() {
+    let output = server_command(env!("CARGO_BIN_EXE_server_muti_thread"))
+        .args(["--bind", "127.0.0.1:0", "--protocol-version", "v1", "--compression", "lz4"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("v2"));
+}
@@ -0,0 +1,242 @@
+mod common;
+
+use std::path::PathBuf;
+use std::process::Command;
+
+use clap::Parser;
+use common::{server_command, spawn_server, TempFile};
+use socket::cli::ServerArgs;
+use socket::config::{self, Config, ConfigError};
+use socket::log::Level;
+use socket::network_handler::{Checksum, Compression, CorruptPolicy, LengthFormat, Version};
+use socket::server::Model;
+
+const FULL: &str = r#"
+[server]
+listen = ["127.0.0.1", "[::1]:9000"]
+port = 7000
+backlog = 64
+model = "tokio"
+workers = 4
+queue_depth = 16
+reuse_port = false
+
+[limits]
+max_connections = 100
+max_payload = 65536
+read_buffer = 4096
+
+[protocol]
+version = "v2"
+length_format = "varint"
+checksum = "xxh32"
+on_corrupt = "skip_frame"
+compression = "none"
+compress_threshold = 64
+
+[timeouts]
+auth_secs = 5
+
+[tls]
+cert = "cert.pem"
+key = "key.pem"
+
+[auth]
+psk_file = "keys.txt"
+
+[log]
+level = "info"
+"#;
+
+fn parse_error(text: &str) -> String {
+    match Config::parse(text) {
+        Err(ConfigError::Parse(e)) => e,
+        other => panic!("期望格式错误，实际为 {:?}", other),
+    }
+}
+
+fn invalid_error(text: &str) -> String {
+    match Config::parse(text) {
+        Err(ConfigError::Invalid(e)) => e,
+        other => panic!("期望取值错误，实际为 {:?}", other),
+    }
+}
+
+#[test]
+fn full_config_is_parsed() {
+    let config = Config::parse(FULL).unwrap();
+    assert_eq!(config.addrs(), ["127.0.0.1:7000", "[::1]:9000"]);
+    assert_eq!(config.server.backlog, Some(64));
+    assert_eq!(config.server.model, Some(Model::Tokio));
+    assert_eq!((config.server.workers, config.server.queue_depth), (Some(4), Some(16)));
+    assert_eq!(config.server.reuse_port, Some(false));
+    assert_eq!(config.limits.max_connections, Some(100));
+    assert_eq!(config.frame_format().max_payload, 65536);
+    assert_eq!(config.frame_format().length, LengthFormat::Varint);
+    assert_eq!(config.frame_format().checksum, Checksum::XxHash32);
+    assert_eq!(config.frame_format().on_corrupt, CorruptPolicy::SkipFrame);
+    assert_eq!((config.frame_format().compression, config.frame_format().compress_threshold), (Compression::None, 64));
+    assert_eq!(config.limits.read_buffer, Some(4096));
+    assert_eq!(config.timeouts.auth_secs, Some(5));
+    assert_eq!(config.tls.cert, Some(PathBuf::from("cert.pem")));
+    assert_eq!(config.auth.psk_file, Some(PathBuf::from("keys.txt")));
+    assert_eq!(config.log.level, Some(Level::Info));
+
+    // 空文件使用全部默认值
+    assert_eq!(Config::parse("").unwrap().addrs(), ["0.0.0.0:8080"]);
+}
+
+#[test]
+fn event_loop_models_are_parsed() {
+    for (name, model) in [("epoll", Model::Epoll), ("poll", Model::Poll), ("select", Model::Select), ("io_uring", Model::IoUring)] {
+        let config = Config::parse(&format!("[server]\nmodel = \"{}\"\n", name)).unwrap();
+        assert_eq!(config.server.model, Some(model));
+    }
+
+    // 没有启用 io-uring 特性时 io_uring 模型无法创建
+    let builder = Config::parse("[server]\nlisten = [\"127.0.0.1:0\"]\nmodel = \"io_uring\"\n").unwrap().builder(Model::Single).unwrap();
+    assert_eq!(builder.build().is_ok(), cfg!(feature = "io-uring"));
+}
+
+#[test]
+fn unknown_keys_are_rejected() {
+    assert!(parse_error("[servre]\nport = 1\n").contains("servre"));
+    assert!(parse_error("[server]\nbind = [\"0.0.0.0\"]\n").contains("bind"));
+    assert!(parse_error("[log]\nlevel = \"info\"\ncolor = true\n").contains("color"));
+}
+
+#[test]
+fn invalid_values_are_rejected() {
+    assert!(parse_error("[server]\nmodel = \"fork\"\n").contains("fork"));
+    assert!(parse_error("[server]\nport = 70000\n").contains("port"));
+    assert!(parse_error("[log]\nlevel = \"verbose\"\n").contains("verbose"));
+    assert!(parse_error("[limits]\nmax_connections = \"many\"\n").contains("max_connections"));
+    assert!(parse_error("[protocol]\nlength_format = \"u64\"\n").contains("u64"));
+    assert!(parse_error("[protocol]\nversion = \"v3\"\n").contains("v3"));
+    assert!(parse_error("[protocol]\nchecksum = \"md5\"\n").contains("md5"));
+    assert!(parse_error("[protocol]\ncompression = \"gzip\"\n").contains("gzip"));
+
+    assert!(invalid_error("[server]\nbacklog = 0\n").contains("backlog"));
+    assert!(invalid_error("[server]\nworkers = 0\n").contains("workers"));
+    assert!(invalid_error("[limits]\nmax_connections = 0\n").contains("max_connections"));
+    assert!(invalid_error("[limits]\nmax_payload = 0\n").contains("max_payload"));
+    assert!(invalid_error("[timeouts]\nauth_secs = 0\n").contains("auth_secs"));
+    assert!(invalid_error("[tls]\ncert = \"cert.pem\"\n").contains("tls.key"));
+    assert!(invalid_error("[tls]\nclient_ca = \"ca.pem\"\n").contains("client_ca"));
+    assert!(invalid_error("[limits]\nread_buffer = 0\n").contains("read_buffer"));
+    assert!(invalid_error("[access]\nallow = [\"client\"]\n").contains("client_ca"));
+    // payload 上限不能超过长度字段能表示的范围
+    assert!(invalid_error("[protocol]\nlength_format = \"u8\"\n\n[limits]\nmax_payload = 256\n").contains("max_payload"));
+    assert!(invalid_error("[protocol]\nversion = \"v1\"\n\n[auth]\npsk_file = \"keys.txt\"\n").contains("v1"));
+    assert!(invalid_error("[protocol]\nversion = \"v1\"\nchecksum = \"crc32c\"\n").contains("v1"));
+    assert!(invalid_error("[protocol]\non_corrupt = \"resync\"\n").contains("checksum"));
+    assert!(Config::parse("[protocol]\non_corrupt = \"resync\"\nchecksum = \"crc32c\"\n").is_ok());
+    assert!(invalid_error("[protocol]\nversion = \"v1\"\ncompression = \"lz4\"\n").contains("v1"));
+    // 没有编译的算法在启动时就被拒绝
+    for compression in [Compression::Zstd, Compression::Lz4] {
+        let text = format!("[protocol]\ncompression = \"{}\"\n", compression.name());
+        if compression.is_supported() {
+            assert_eq!(Config::parse(&text).unwrap().frame_format().compression, compression);
+        } else {
+            assert!(invalid_error(&text).contains(compression.name()));
+        }
+    }
+}
+
+#[test]
+fn missing_referenced_files_are_reported() {
+    let config = Config::parse("[auth]\npsk_file = \"/nonexistent/keys.txt\"\n").unwrap();
+    match config.builder(Model::Single) {
+        Err(ConfigError::Io(path, _)) => assert_eq!(path, PathBuf::from("/nonexistent/keys.txt")),
+        other => panic!("期望读取错误，实际为 {:?}", other.err()),
+    }
+}
+
+#[test]
+fn command_line_overrides_environment_and_file() {
+    let file = TempFile::new("precedence.toml", FULL);
+    let path = file.0.to_str().unwrap();
+
+    // 只有这个测试修改环境变量，避免与其他测试并发读写
+    unsafe {
+        std::env::set_var("SOCKET_PSK_TIMEOUT", "9");
+        std::env::set_var("SOCKET_LOG_LEVEL", "error");
+        std::env::set_var("SOCKET_LISTEN", "127.0.0.2, [::1]:9100");
+        std::env::set_var("SOCKET_PORT", "7200");
+        std::env::set_var("SOCKET_MODEL", "thread_pool");
+        std::env::set_var("SOCKET_WORKERS", "3");
+        std::env::set_var("SOCKET_MAX_CONNECTIONS", "50");
+        std::env::set_var("SOCKET_CHECKSUM", "crc32c");
+        std::env::set_var("SOCKET_COMPRESS_THRESHOLD", "128");
+    }
+
+    let args = ServerArgs::try_parse_from(["server", "--config", path]).unwrap();
+    let config = args.config().unwrap();
+    // 环境变量优先于配置文件
+    assert_eq!(config.addrs(), ["127.0.0.2:7200", "[::1]:9100"]);
+    assert_eq!((config.server.model, config.server.workers), (Some(Model::ThreadPool), Some(3)));
+    assert_eq!(config.limits.max_connections, Some(50));
+    assert_eq!(config.frame_format().checksum, Checksum::Crc32c);
+    assert_eq!(config.frame_format().compress_threshold, 128);
+    assert_eq!(config.timeouts.auth_secs, Some(9));
+    assert_eq!(config.log.level, Some(Level::Error));
+    // 没有设置环境变量的保持配置文件中的值
+    assert_eq!(config.server.backlog, Some(64));
+
+    // 命令行参数优先于环境变量与配置文件
+    let args = ServerArgs::try_parse_from([
+        "server", "--config", path, "--bind", "0.0.0.0", "--port", "7100", "--backlog", "8", "--workers", "2", "--reuse-port",
+        "--log-level", "debug", "--length-format", "u16", "--max-payload", "1000", "--checksum", "none",
+        "--compress-threshold", "32",
+    ])
+    .unwrap();
+    let config = args.config().unwrap();
+    assert_eq!(config.addrs(), ["0.0.0.0:7100"]);
+    assert_eq!(config.server.backlog, Some(8));
+    assert_eq!((config.server.workers, config.server.queue_depth), (Some(2), Some(16)));
+    assert_eq!(config.server.reuse_port, Some(true));
+    assert_eq!(config.log.level, Some(Level::Debug));
+    assert_eq!((config.frame_format().length, config.frame_format().max_payload), (LengthFormat::U16, 1000));
+    assert_eq!(config.frame_format().version, Version::V2);
+    assert_eq!(config.frame_format().checksum, Checksum::None);
+    assert_eq!(config.frame_format().compress_threshold, 32);
+    // 命令行没有指定的设置保持环境变量中的值
+    assert_eq!(config.limits.max_connections, Some(50));
+
+    unsafe {
+        std::env::set_var("SOCKET_PSK_TIMEOUT", "soon");
+    }
+    assert!(matches!(args.config(), Err(ConfigError::Invalid(_))));
+    unsafe {
+        std::env::set_var("SOCKET_PSK_TIMEOUT", "9");
+        std::env::set_var("SOCKET_MODEL", "threads");
+    }
+    match args.config() {
+        Err(ConfigError::Invalid(e)) => assert!(e.contains("SOCKET_MODEL"), "{}", e),
+        other => panic!("期望取值错误，实际为 {:?}", other),
+    }
+
+    unsafe {
+        for name in config::ENV_OVERRIDES {
+            std::env::remove_var(name);
+        }
+    }
+}
+
+#[test]
+fn binary_reads_config_file() {
+    let file = TempFile::new("binary.toml", "[server]\nlisten = [\"127.0.0.1:0\"]\n\n[log]\nlevel = \"info\"\n");
+    // 不继承其他测试临时设置的环境变量
+    let (mut child, addr, lines) = spawn_server(server_command(env!("CARGO_BIN_EXE_server")).arg("--config").arg(&file.0));
+    assert!(addr.ip().is_loopback());
+
+    unsafe { libc::kill(child.id() as i32, libc::SIGINT) };
+    lines.iter().for_each(drop);
+    assert!(child.wait().unwrap().success());
+
+    // 配置错误时以状态码 2 退出，并指出出错的键
+    let bad = TempFile::new("bad.toml", "[server]\nlisten = [\"127.0.0.1:0\"]\nthreads = 4\n");
+    let output = Command::new(env!("CARGO_BIN_EXE_server")).arg("--config").arg(&bad.0).env_remove("SOCKET_PSK_TIMEOUT").output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("threads"));
+}
@@ -0,0 +1,142 @@
+mod common;
+
+use std::net::{SocketAddr, TcpStream};
+use std::process::Child;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{server_command, wait_for};
+use socket::codec::{PduReader, PduWriter};
+use socket::network_handler::{AsyncHandler, BoxFuture, ConnContext, FrameFormat, Handler, MsgType, Pdu};
+use socket::server::{Model, Server};
+
+/// 等待一段时间后原样返回，模拟处理中的请求
+struct Slow(Duration);
+
+impl Handler for Slow {
+    fn handle(&self, pdu: Pdu, _ctx: &ConnContext) -> Vec<Pdu> {
+        thread::sleep(self.0);
+        vec![pdu]
+    }
+}
+
+/// 异步版本的 [`Slow`]，不占用 tokio 的工作线程
+struct AsyncSlow(Duration);
+
+impl AsyncHandler for AsyncSlow {
+    fn handle_async<'a>(&'a self, pdu: Pdu, _ctx: &'a ConnContext) -> BoxFuture<'a, Vec<Pdu>> {
+        Box::pin(async move {
+            tokio::time::sleep(self.0).await;
+            vec![pdu]
+        })
+    }
+}
+
+fn send(stream: &TcpStream, request_id: u32) {
+    let format = FrameFormat::default();
+    PduWriter::new(stream, format).write_pdu(&Pdu::with_type(MsgType::Data, request_id, b"hello", &format).unwrap()).unwrap();
+}
+
+/// 读取服务器关闭连接之前发送的所有 PDU
+fn read_all(stream: &TcpStream) -> Vec<Pdu> {
+    PduReader::new(stream, FrameFormat::default()).map_while(Result::ok).collect()
+}
+
+fn in_flight_request_finishes(model: Model) {
+    let (server, addr, runner) =
+        common::start(Server::builder().bind("127.0.0.1:0").model(model).handler(Slow(Duration::from_millis(300))));
+
+    let busy = TcpStream::connect(addr).unwrap();
+    let idle = TcpStream::connect(addr).unwrap();
+    send(&busy, 1);
+    // 等待服务器开始处理请求
+    thread::sleep(Duration::from_millis(100));
+    server.drain();
+
+    // 先收到处理中请求的响应，再收到 going-away
+    let pdus = read_all(&busy);
+    assert_eq!(pdus.len(), 2);
+    assert_eq!((pdus[0].request_id, pdus[0].payload.as_slice()), (1, b"hello".as_slice()));
+    assert!(pdus[1].is_going_away());
+    let pdus = read_all(&idle);
+    assert_eq!(pdus.len(), 1);
+    assert!(pdus[0].is_going_away());
+
+    runner.join().unwrap().unwrap();
+    drop(server);
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+#[test]
+fn thread_server_drains() {
+    in_flight_request_finishes(Model::ThreadPerConnection);
+}
+
+#[test]
+fn tokio_server_drains() {
+    in_flight_request_finishes(Model::Tokio);
+}
+
+#[test]
+fn remaining_connections_are_closed_after_deadline() {
+    let (server, addr, runner) = common::start(
+        Server::builder()
+            .bind("127.0.0.1:0")
+            .model(Model::Tokio)
+            .async_handler(AsyncSlow(Duration::from_secs(10)))
+            .drain_timeout(Duration::from_millis(200)),
+    );
+
+    let stream = TcpStream::connect(addr).unwrap();
+    send(&stream, 1);
+    thread::sleep(Duration::from_millis(100));
+    let start = Instant::now();
+    server.drain();
+
+    // 处理不完的请求没有响应，连接在期限之后被关闭
+    assert!(read_all(&stream).is_empty());
+    runner.join().unwrap().unwrap();
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+/// 不输出每个 PDU，避免大量无关的输出
+fn spawn_server(bin: &str) -> (Child, SocketAddr, Receiver<String>) {
+    common::spawn_server(server_command(bin).args(["--bind", "127.0.0.1:0", "--log-level", "info"]))
+}
+
+#[test]
+fn binary_drains_on_sigterm() {
+    let (mut child, addr, lines) = spawn_server(env!("CARGO_BIN_EXE_server_muti_thread"));
+    let stream = TcpStream::connect(addr).unwrap();
+    send(&stream, 1);
+    assert_eq!(PduReader::new(&stream, FrameFormat::default()).read_pdu().unwrap().unwrap().request_id, 1);
+
+    unsafe { libc::kill(child.id() as i32, libc::SIGTERM) };
+    let pdus = read_all(&stream);
+    assert!(pdus.len() == 1 && pdus[0].is_going_away());
+    lines.iter().for_each(drop);
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn second_signal_forces_exit() {
+    let (mut child, addr, lines) = spawn_server(env!("CARGO_BIN_EXE_server_muti_process"));
+    let stream = TcpStream::connect(addr).unwrap();
+    let worker: i32 = wait_for(&lines, |line| line.strip_prefix("[srv] 创建子进程[")?.strip_suffix("]")?.parse().ok());
+    // 暂停处理连接的子进程，优雅关闭无法在默认的 30 秒内完成
+    unsafe { libc::kill(worker, libc::SIGSTOP) };
+
+    let pid = child.id() as i32;
+    unsafe { libc::kill(pid, libc::SIGTERM) };
+    wait_for(&lines, |line| line.starts_with("[srv] 停止接受新连接").then_some(()));
+    let start = Instant::now();
+    unsafe { libc::kill(pid, libc::SIGTERM) };
+    assert_eq!(child.wait().unwrap().code(), Some(1));
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    // 子进程继承了 stdout，结束它之后才能读到文件末尾
+    unsafe { libc::kill(worker, libc::SIGKILL) };
+    lines.iter().for_each(drop);
+    drop(stream);
+}
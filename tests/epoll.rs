@@ -0,0 +1,126 @@
+mod common;
+
+use std::collections::HashSet;
+use std::io::Write;
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, ThreadId};
+use std::time::Duration;
+
+use common::{pdu, raise_fd_limit, reader, send};
+use socket::network_handler::{ConnContext, Handler, Pdu};
+use socket::server::{Model, Server};
+
+/// 原样返回，并记录处理请求的线程
+#[derive(Clone, Default)]
+struct Recording(Arc<Mutex<HashSet<ThreadId>>>);
+
+impl Handler for Recording {
+    fn handle(&self, pdu: Pdu, _ctx: &ConnContext) -> Vec<Pdu> {
+        self.0.lock().unwrap().insert(thread::current().id());
+        vec![pdu]
+    }
+}
+
+#[test]
+fn one_thread_serves_thousands_of_clients() {
+    raise_fd_limit();
+    let handler = Recording::default();
+    let (server, addr, runner) = common::start(Server::builder().bind("127.0.0.1:0").backlog(4096).model(Model::Epoll).handler(handler.clone()));
+
+    // 先建立所有连接并发送请求，再逐个读取响应，所有连接同时处于打开状态
+    let clients: Vec<TcpStream> = (0..2000).map(|_| TcpStream::connect(addr).unwrap()).collect();
+    for (id, client) in clients.iter().enumerate() {
+        (&*client).write_all(&pdu(id as u32, format!("client {}", id).as_bytes())).unwrap();
+    }
+    for (id, client) in clients.iter().enumerate() {
+        let response = reader(client).read_pdu().unwrap().unwrap();
+        assert_eq!(response.request_id, id as u32);
+        assert_eq!(response.payload, format!("client {}", id).as_bytes());
+    }
+    assert_eq!(handler.0.lock().unwrap().len(), 1);
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+}
+
+#[test]
+fn partial_and_coalesced_frames_are_reassembled() {
+    let (server, addr, runner) = common::start(Server::builder().bind("127.0.0.1:0").backlog(4096).model(Model::Epoll).handler(Recording::default()));
+    let stream = TcpStream::connect(addr).unwrap();
+    stream.set_nodelay(true).unwrap();
+
+    // 同一次读取可能收到多个响应，整个测试共用一个读取器
+    let mut reader = reader(&stream);
+
+    // 逐字节发送一个 PDU
+    for byte in pdu(1, b"hello") {
+        (&stream).write_all(&[byte]).unwrap();
+        thread::sleep(Duration::from_millis(2));
+    }
+    assert_eq!(reader.read_pdu().unwrap().unwrap().payload, b"hello");
+
+    // 一次写入两个 PDU 与第三个的前半部分
+    let mut bytes = [pdu(2, b"first"), pdu(3, b"second"), pdu(4, b"third")].concat();
+    let tail = bytes.split_off(bytes.len() - 3);
+    (&stream).write_all(&bytes).unwrap();
+    assert_eq!(reader.read_pdu().unwrap().unwrap().request_id, 2);
+    assert_eq!(reader.read_pdu().unwrap().unwrap().request_id, 3);
+    (&stream).write_all(&tail).unwrap();
+    assert_eq!(reader.read_pdu().unwrap().unwrap().payload, b"third");
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+}
+
+#[test]
+fn responses_wait_for_slow_reader() {
+    let (server, addr, runner) = common::start(Server::builder().bind("127.0.0.1:0").backlog(4096).model(Model::Epoll).handler(Recording::default()));
+    let stream = TcpStream::connect(addr).unwrap();
+
+    // 客户端先发送大量请求而不读取响应，服务器的写缓冲写满后暂停读取
+    let payload = vec![7_u8; 64 * 1024];
+    let writer = thread::spawn({
+        let stream = stream.try_clone().unwrap();
+        let payload = payload.clone();
+        move || {
+            for id in 0..200 {
+                (&stream).write_all(&pdu(id, &payload)).unwrap();
+            }
+        }
+    });
+    thread::sleep(Duration::from_millis(200));
+    let mut reader = reader(&stream);
+    for id in 0..200 {
+        let response = reader.read_pdu().unwrap().unwrap();
+        assert_eq!((response.request_id, response.payload.len()), (id, payload.len()));
+    }
+    writer.join().unwrap();
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+}
+
+#[test]
+fn drain_sends_going_away() {
+    let (server, addr, runner) = common::start(Server::builder().bind("127.0.0.1:0").backlog(4096).model(Model::Epoll).handler(Recording::default()));
+    let stream = TcpStream::connect(addr).unwrap();
+    let mut reader = reader(&stream);
+    send(&stream, 1);
+    assert_eq!(reader.read_pdu().unwrap().unwrap().request_id, 1);
+
+    server.drain();
+    assert!(reader.read_pdu().unwrap().unwrap().is_going_away());
+    assert!(reader.read_pdu().unwrap().is_none());
+    runner.join().unwrap().unwrap();
+}
+
+#[test]
+fn binary_resumes_after_fd_limit() {
+    common::binary_resumes_after_fd_limit(env!("CARGO_BIN_EXE_server_epoll"));
+}
+
+#[test]
+fn binary_exits_on_sigint() {
+    common::binary_exits_on_sigint(env!("CARGO_BIN_EXE_server_epoll"));
+}
@@ -0,0 +1,145 @@
+use socket::codec::FrameAssembler;
+use socket::network_handler::{FrameFormat, LengthFormat, MsgType, Pdu, PduError, Version};
+
+fn encode(payloads: &[&[u8]], format: &FrameFormat) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for (i, payload) in payloads.iter().enumerate() {
+        let pdu = Pdu::with_type(MsgType::Data, i as u32, payload, format).unwrap();
+        bytes.extend(pdu.to_vec(format).unwrap());
+    }
+    bytes
+}
+
+fn drain(assembler: &mut FrameAssembler) -> Vec<Pdu> {
+    let mut pdus = Vec::new();
+    while let Some(pdu) = assembler.next_pdu().unwrap() {
+        pdus.push(pdu);
+    }
+    pdus
+}
+
+fn all_formats() -> Vec<FrameFormat> {
+    let mut formats = Vec::new();
+    for length in [LengthFormat::U8, LengthFormat::U16, LengthFormat::U32, LengthFormat::Varint] {
+        formats.push(FrameFormat::new(length, 4096));
+        formats.push(FrameFormat::legacy(length, 4096));
+    }
+    formats
+}
+
+#[test]
+fn split_header() {
+    for format in all_formats() {
+        let bytes = encode(&[b"hello"], &format);
+        let mut assembler = FrameAssembler::new(format);
+
+        // 头部的每个字节单独到达
+        for byte in &bytes[..bytes.len() - 5] {
+            assembler.push(std::slice::from_ref(byte));
+            assert!(assembler.next_pdu().unwrap().is_none(), "{:?}", format);
+        }
+        assembler.push(&bytes[bytes.len() - 5..]);
+
+        let pdus = drain(&mut assembler);
+        assert_eq!(pdus.len(), 1, "{:?}", format);
+        assert_eq!(pdus[0].payload, b"hello");
+        assert_eq!(assembler.buffered_len(), 0);
+    }
+}
+
+#[test]
+fn frame_split_across_many_reads() {
+    let payload: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+    for format in all_formats().into_iter().filter(|format| format.max_payload >= payload.len()) {
+        let bytes = encode(&[&payload], &format);
+        let mut assembler = FrameAssembler::new(format);
+
+        let mut pdus = Vec::new();
+        for chunk in bytes.chunks(7) {
+            assembler.push(chunk);
+            pdus.extend(drain(&mut assembler));
+        }
+
+        assert_eq!(pdus.len(), 1, "{:?}", format);
+        assert_eq!(pdus[0].payload, payload);
+    }
+}
+
+#[test]
+fn many_frames_per_read() {
+    for format in all_formats() {
+        let payloads: Vec<Vec<u8>> = (0..100).map(|i| format!("message {}", i).into_bytes()).collect();
+        let refs: Vec<&[u8]> = payloads.iter().map(|p| p.as_slice()).collect();
+        let bytes = encode(&refs, &format);
+
+        let mut assembler = FrameAssembler::new(format);
+        assembler.push(&bytes);
+        let pdus = drain(&mut assembler);
+
+        assert_eq!(pdus.len(), payloads.len(), "{:?}", format);
+        for (i, (pdu, payload)) in pdus.iter().zip(&payloads).enumerate() {
+            assert_eq!(&pdu.payload, payload);
+            if format.version == Version::V2 {
+                assert_eq!(pdu.request_id, i as u32);
+            }
+        }
+    }
+}
+
+#[test]
+fn frames_straddling_read_boundaries() {
+    let format = FrameFormat::default();
+    let payloads: Vec<Vec<u8>> = (0..50).map(|i| vec![b'x'; i * 3]).collect();
+    let refs: Vec<&[u8]> = payloads.iter().map(|p| p.as_slice()).collect();
+    let bytes = encode(&refs, &format);
+
+    // 每次读取的大小不同，使帧边界落在读取的任意位置
+    let mut assembler = FrameAssembler::new(format);
+    let mut pdus = Vec::new();
+    let mut offset = 0;
+    let mut size = 1;
+    while offset < bytes.len() {
+        let end = (offset + size).min(bytes.len());
+        assembler.push(&bytes[offset..end]);
+        pdus.extend(drain(&mut assembler));
+        offset = end;
+        size = size % 13 + 5;
+    }
+
+    let received: Vec<Vec<u8>> = pdus.into_iter().map(|pdu| pdu.payload).collect();
+    assert_eq!(received, payloads);
+    assert_eq!(assembler.buffered_len(), 0);
+}
+
+#[test]
+fn empty_payload() {
+    let format = FrameFormat::default();
+    let mut assembler = FrameAssembler::new(format);
+    assembler.push(&encode(&[b"", b"", b"x"], &format));
+
+    let pdus = drain(&mut assembler);
+    assert_eq!(pdus.len(), 3);
+    assert!(pdus[0].payload.is_empty());
+    assert_eq!(pdus[2].payload, b"x");
+}
+
+#[test]
+fn oversize_frame_is_rejected() {
+    let format = FrameFormat::new(LengthFormat::U16, 16);
+    let bytes = encode(&[&[0; 32]], &FrameFormat::new(LengthFormat::U16, 64));
+
+    let mut assembler = FrameAssembler::new(format);
+    assembler.push(&bytes);
+    assert!(matches!(assembler.next_pdu(), Err(PduError::Oversize { len: 32, max: 16 })));
+}
+
+#[test]
+fn from_bytes_reports_missing_bytes() {
+    let format = FrameFormat::default();
+    let bytes = encode(&[b"hello"], &format);
+
+    assert!(matches!(Pdu::from_bytes(&bytes[..bytes.len() - 2], &format), Err(PduError::Incomplete { needed: 2 })));
+    assert!(matches!(Pdu::from_bytes(&bytes[..3], &format), Err(PduError::Incomplete { .. })));
+    assert!(matches!(Pdu::from_bytes(&[0xA3], &format), Err(PduError::BadVersion(3))));
+    assert_eq!(Pdu::from_bytes(&bytes, &format).unwrap().payload, b"hello");
+}
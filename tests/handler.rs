@@ -0,0 +1,75 @@
+mod common;
+
+use std::io::Write;
+use std::net::TcpStream;
+
+use common::{pdu, reader};
+use socket::network_handler::{AsyncHandler, BoxFuture, ConnContext, Handler, MsgType, Pdu};
+use socket::server::{Model, Server, ServerBuilder};
+
+/// 回复倒序的 payload 和对端地址两个 PDU，空 payload 不回复
+struct Reverse;
+
+impl Handler for Reverse {
+    fn handle(&self, pdu: Pdu, ctx: &ConnContext) -> Vec<Pdu> {
+        if pdu.payload.is_empty() {
+            return Vec::new();
+        }
+        let reversed: Vec<u8> = pdu.payload.iter().rev().copied().collect();
+        vec![
+            Pdu::with_type(MsgType::Data, pdu.request_id, &reversed, &ctx.format).unwrap(),
+            Pdu::with_type(MsgType::Data, pdu.request_id, ctx.peer_addr.to_string().as_bytes(), &ctx.format).unwrap(),
+        ]
+    }
+}
+
+/// 在异步任务中完成同样处理的 [`Reverse`]
+struct AsyncReverse;
+
+impl AsyncHandler for AsyncReverse {
+    fn handle_async<'a>(&'a self, pdu: Pdu, ctx: &'a ConnContext) -> BoxFuture<'a, Vec<Pdu>> {
+        Box::pin(async move {
+            tokio::task::yield_now().await;
+            Reverse.handle(pdu, ctx)
+        })
+    }
+}
+
+fn hosts_custom_handler(builder: ServerBuilder) {
+    let (server, addr, runner) = common::start(builder.bind("127.0.0.1:0"));
+
+    let stream = TcpStream::connect(addr).unwrap();
+    let mut reader = reader(&stream);
+    // 没有响应的请求之后紧跟着一个有两个响应的请求
+    (&stream).write_all(&[pdu(1, b""), pdu(2, b"abc")].concat()).unwrap();
+
+    let reversed = reader.read_pdu().unwrap().unwrap();
+    assert_eq!(reversed.request_id, 2);
+    assert_eq!(reversed.payload, b"cba");
+    let peer = reader.read_pdu().unwrap().unwrap();
+    assert_eq!(peer.request_id, 2);
+    assert_eq!(peer.payload, stream.local_addr().unwrap().to_string().as_bytes());
+    drop(reader);
+    drop(stream);
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+}
+
+#[test]
+fn every_model_runs_the_same_handler() {
+    for model in [Model::Single, Model::ThreadPerConnection, Model::ProcessPerConnection, Model::Tokio, Model::Epoll, Model::Poll] {
+        hosts_custom_handler(Server::builder().model(model).handler(Reverse));
+    }
+}
+
+#[test]
+fn tokio_runs_async_handler() {
+    hosts_custom_handler(Server::builder().model(Model::Tokio).async_handler(AsyncReverse));
+}
+
+#[test]
+fn async_handler_requires_tokio() {
+    let result = Server::builder().bind("127.0.0.1:0").model(Model::ThreadPerConnection).async_handler(AsyncReverse).build();
+    assert_eq!(result.err().unwrap().kind(), std::io::ErrorKind::InvalidInput);
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use std::io::Write;
+use std::net::TcpStream;
+use std::time::Duration;
+
+use common::{pdu, reader};
+use socket::network_handler::MsgType;
+use socket::server::{Model, Server};
+
+/// 第一个字节不是 v2 头部时，服务器回复错误 PDU 后关闭连接
+fn bad_first_byte_is_rejected(model: Model) {
+    let (server, addr, runner) = common::start(Server::builder().bind("127.0.0.1:0").model(model));
+
+    // 魔数正确但版本未知，以及按 v1 格式发送的 `[len][payload]`
+    for (first, expected) in [(0xA3, "协议版本"), (0x00, "魔数")] {
+        let mut bytes = pdu(1, b"hello");
+        bytes[0] = first;
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        (&stream).write_all(&bytes).unwrap();
+
+        let mut reader = reader(&stream);
+        let response = reader.read_pdu().unwrap().unwrap();
+        assert_eq!(response.kind, MsgType::Error);
+        let message = String::from_utf8_lossy(&response.payload);
+        assert!(message.contains(expected), "{:?}: {}", model, message);
+        assert!(reader.read_pdu().unwrap().is_none(), "{:?} 没有关闭连接", model);
+    }
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+}
+
+#[test]
+fn thread_server_rejects_bad_first_byte() {
+    bad_first_byte_is_rejected(Model::ThreadPerConnection);
+}
+
+#[test]
+fn epoll_server_rejects_bad_first_byte() {
+    bad_first_byte_is_rejected(Model::Epoll);
+}
+
+#[test]
+fn tokio_server_rejects_bad_first_byte() {
+    bad_first_byte_is_rejected(Model::Tokio);
+}
@@ -0,0 +1,133 @@
+#![cfg(feature = "io-uring")]
+
+mod common;
+
+use std::io::Write;
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+use common::{pdu, reader};
+use socket::network_handler::Echo;
+use socket::server::{Model, Server};
+
+/// 当前环境不能运行 io_uring 模型时跳过测试
+fn io_uring_available() -> bool {
+    match socket::uring::check_support() {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("跳过 io_uring 测试: {}", e);
+            false
+        }
+    }
+}
+
+#[test]
+fn one_thread_serves_many_clients() {
+    if !io_uring_available() {
+        return;
+    }
+    let (server, addr, runner) = common::start(Server::builder().bind("127.0.0.1:0").backlog(4096).model(Model::IoUring).handler(Echo));
+
+    let clients: Vec<TcpStream> = (0..500).map(|_| TcpStream::connect(addr).unwrap()).collect();
+    for (id, client) in clients.iter().enumerate() {
+        (&*client).write_all(&pdu(id as u32, format!("client {}", id).as_bytes())).unwrap();
+    }
+    let mut readers: Vec<_> = clients.iter().map(reader).collect();
+    for (id, reader) in readers.iter_mut().enumerate() {
+        let response = reader.read_pdu().unwrap().unwrap();
+        assert_eq!(response.request_id, id as u32);
+        assert_eq!(response.payload, format!("client {}", id).as_bytes());
+    }
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+    // 关闭时断开所有连接
+    assert!(readers[0].read_pdu().unwrap().is_none());
+}
+
+#[test]
+fn frames_larger_than_receive_buffer_are_reassembled() {
+    if !io_uring_available() {
+        return;
+    }
+    let (server, addr, runner) = common::start(Server::builder().bind("127.0.0.1:0").backlog(4096).model(Model::IoUring).handler(Echo));
+    let stream = TcpStream::connect(addr).unwrap();
+    let mut reader = reader(&stream);
+
+    // 一个 PDU 分布在多个接收缓冲中，多个 PDU 也可能落在同一个缓冲中
+    let payload = vec![3_u8; 10 * 1024];
+    (&stream).write_all(&[pdu(1, &payload), pdu(2, b"small"), pdu(3, b"tiny")].concat()).unwrap();
+    assert_eq!(reader.read_pdu().unwrap().unwrap().payload, payload);
+    assert_eq!(reader.read_pdu().unwrap().unwrap().payload, b"small");
+    assert_eq!(reader.read_pdu().unwrap().unwrap().payload, b"tiny");
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+}
+
+#[test]
+fn responses_wait_for_slow_reader() {
+    if !io_uring_available() {
+        return;
+    }
+    let (server, addr, runner) = common::start(Server::builder().bind("127.0.0.1:0").backlog(4096).model(Model::IoUring).handler(Echo));
+    let stream = TcpStream::connect(addr).unwrap();
+
+    // 客户端先发送大量请求而不读取响应，服务器的写缓冲积压后暂停接收
+    let payload = vec![7_u8; 64 * 1024];
+    let writer = thread::spawn({
+        let stream = stream.try_clone().unwrap();
+        let payload = payload.clone();
+        move || {
+            for id in 0..200 {
+                (&stream).write_all(&pdu(id, &payload)).unwrap();
+            }
+        }
+    });
+    thread::sleep(Duration::from_millis(200));
+    let mut reader = reader(&stream);
+    for id in 0..200 {
+        let response = reader.read_pdu().unwrap().unwrap();
+        assert_eq!((response.request_id, response.payload.len()), (id, payload.len()));
+    }
+    writer.join().unwrap();
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+}
+
+#[test]
+fn drain_sends_going_away() {
+    if !io_uring_available() {
+        return;
+    }
+    let (server, addr, runner) = common::start(Server::builder().bind("127.0.0.1:0").backlog(4096).model(Model::IoUring).handler(Echo));
+    let stream = TcpStream::connect(addr).unwrap();
+    let mut reader = reader(&stream);
+    (&stream).write_all(&pdu(1, b"hello")).unwrap();
+    assert_eq!(reader.read_pdu().unwrap().unwrap().request_id, 1);
+
+    server.drain();
+    assert!(reader.read_pdu().unwrap().unwrap().is_going_away());
+    assert!(reader.read_pdu().unwrap().is_none());
+    runner.join().unwrap().unwrap();
+}
+
+#[test]
+fn binary_exits_on_sigint() {
+    if !io_uring_available() {
+        return;
+    }
+    common::binary_exits_on_sigint(env!("CARGO_BIN_EXE_server_io_uring"));
+}
+
+#[test]
+fn build_checks_kernel_support() {
+    // 内核不支持 multishot 时在接受连接之前失败
+    let result = Server::builder().bind("127.0.0.1:0").model(Model::IoUring).build();
+    match socket::uring::check_support() {
+        Ok(()) => assert!(result.is_ok()),
+        Err(e) => assert_eq!(result.err().unwrap().kind(), e.kind()),
+    }
+}
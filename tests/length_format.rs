@@ -0,0 +1,70 @@
+use socket::network_handler::{FrameFormat, LengthFormat, Pdu, PduError};
+
+/// v1 格式只有 `[len][payload]`，可以直接检查长度字段的编码
+fn encode(payload_len: usize, format: &FrameFormat) -> Vec<u8> {
+    Pdu::new(&vec![0x42; payload_len], format).unwrap().to_vec(format).unwrap()
+}
+
+#[test]
+fn varint_length_crosses_one_byte_at_128() {
+    let format = FrameFormat::legacy(LengthFormat::Varint, 1 << 20);
+
+    let bytes = encode(127, &format);
+    assert_eq!(bytes.len(), 1 + 127);
+    assert_eq!(bytes[0], 0x7f);
+
+    // 第一个字节的最高位表示后面还有长度字节
+    let bytes = encode(128, &format);
+    assert_eq!(bytes.len(), 2 + 128);
+    assert_eq!(&bytes[..2], [0x80, 0x01]);
+
+    let bytes = encode(16384, &format);
+    assert_eq!(&bytes[..3], [0x80, 0x80, 0x01]);
+
+    for len in [0, 127, 128, 16383, 16384] {
+        let bytes = encode(len, &format);
+        assert_eq!(Pdu::payload_size(&bytes, &format), Some(bytes.len()));
+        assert_eq!(Pdu::from_bytes(&bytes, &format).unwrap().payload.len(), len);
+    }
+}
+
+#[test]
+fn fixed_width_lengths_are_big_endian() {
+    let bytes = encode(300, &FrameFormat::legacy(LengthFormat::U16, 65535));
+    assert_eq!(&bytes[..2], [0x01, 0x2c]);
+    let bytes = encode(65535, &FrameFormat::legacy(LengthFormat::U16, 65535));
+    assert_eq!(&bytes[..2], [0xff, 0xff]);
+    let bytes = encode(70000, &FrameFormat::legacy(LengthFormat::U32, 1 << 20));
+    assert_eq!(&bytes[..4], 70000u32.to_be_bytes());
+}
+
+#[test]
+fn payload_larger_than_255_bytes() {
+    // 最初的 u8 长度字段最多只能表示 255 字节
+    let u8_format = FrameFormat::legacy(LengthFormat::U8, 255);
+    assert!(Pdu::new(&[0; 255], &u8_format).is_ok());
+    assert!(matches!(Pdu::new(&[0; 256], &u8_format), Err(PduError::Oversize { len: 256, max: 255 })));
+
+    for length in [LengthFormat::U16, LengthFormat::U32, LengthFormat::Varint] {
+        for format in [FrameFormat::new(length, 4096), FrameFormat::legacy(length, 4096)] {
+            let bytes = Pdu::new(&[7; 1000], &format).unwrap().to_vec(&format).unwrap();
+            assert!(Pdu::is_complete_pdu(&bytes, &format));
+            assert!(!Pdu::is_complete_pdu(&bytes[..bytes.len() - 1], &format));
+            assert_eq!(Pdu::payload_size(&bytes[..bytes.len() - 1], &format), Some(bytes.len()));
+            assert!(matches!(Pdu::from_bytes(&bytes[..bytes.len() - 1], &format), Err(PduError::Incomplete { needed: 1 })));
+            assert_eq!(Pdu::from_bytes(&bytes, &format).unwrap().payload, [7; 1000]);
+        }
+    }
+}
+
+#[test]
+fn max_payload_is_enforced_on_both_sides() {
+    let format = FrameFormat::new(LengthFormat::Varint, 128);
+    assert!(matches!(Pdu::new(&[0; 129], &format), Err(PduError::Oversize { len: 129, max: 128 })));
+
+    // 接收端只看长度字段就拒绝，不等待 payload 到达
+    let sender = FrameFormat::new(LengthFormat::Varint, 4096);
+    let bytes = Pdu::new(&[0; 129], &sender).unwrap().to_vec(&sender).unwrap();
+    let header = &bytes[..bytes.len() - 129];
+    assert!(matches!(Pdu::validate_header(header, &format), Err(PduError::Oversize { len: 129, max: 128 })));
+}
@@ -0,0 +1,189 @@
+mod common;
+
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa, KeyPair};
+use socket::codec::{PduReader, PduWriter};
+use socket::network_handler::{AccessList, Authorize, ConnContext, FrameFormat, Handler, MsgType, Pdu};
+use socket::server::{Model, Server, ServerBuilder, Settings};
+use socket::tls::{self, ClientTlsStream};
+
+/// 测试时生成的 CA、服务器证书与客户端证书，PEM 文件写入临时目录
+struct Pki {
+    dir: PathBuf,
+    ca: Certificate,
+    ca_key: KeyPair,
+}
+
+impl Pki {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("socket-mtls-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.distinguished_name.push(DnType::CommonName, "test ca");
+        let ca_key = KeyPair::generate().unwrap();
+        let ca = params.self_signed(&ca_key).unwrap();
+        std::fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+
+        let pki = Pki { dir, ca, ca_key };
+        pki.issue("server", "localhost", &["localhost"]);
+        pki
+    }
+
+    /// 签发证书，写入 <name>.pem 与 <name>.key
+    fn issue(&self, name: &str, common_name: &str, sans: &[&str]) {
+        let mut params = CertificateParams::new(sans.iter().map(|san| san.to_string()).collect::<Vec<_>>()).unwrap();
+        params.distinguished_name.push(DnType::CommonName, common_name);
+        let key = KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key, &self.ca, &self.ca_key).unwrap();
+        std::fs::write(self.path(&format!("{}.pem", name)), cert.pem()).unwrap();
+        std::fs::write(self.path(&format!("{}.key", name)), key.serialize_pem()).unwrap();
+    }
+
+    fn path(&self, file: &str) -> PathBuf {
+        self.dir.join(file)
+    }
+
+    fn client_config(&self, name: &str) -> Arc<rustls::ClientConfig> {
+        tls::client_config_with_cert(self.path("ca.pem"), self.path(&format!("{}.pem", name)), self.path(&format!("{}.key", name))).unwrap()
+    }
+}
+
+impl Drop for Pki {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// 把对端证书的身份作为响应返回
+struct WhoAmI;
+
+impl Handler for WhoAmI {
+    fn handle(&self, pdu: Pdu, ctx: &ConnContext) -> Vec<Pdu> {
+        let identity = match &ctx.peer_identity {
+            Some(identity) => format!("{}|{}", identity.common_name.as_deref().unwrap_or(""), identity.sans.join(",")),
+            None => "anonymous".to_string(),
+        };
+        vec![Pdu::with_type(MsgType::Data, pdu.request_id, identity.as_bytes(), &ctx.format).unwrap()]
+    }
+}
+
+fn mtls_server(model: Model, pki: &Pki, handler: impl Handler + 'static) -> ServerBuilder {
+    let config = tls::server_config_with_client_auth(pki.path("server.pem"), pki.path("server.key"), pki.path("ca.pem")).unwrap();
+    Server::builder().bind("127.0.0.1:0").model(model).handler(handler).tls(config)
+}
+
+/// 发送一个请求并返回响应；握手或读取失败时返回 None
+fn request(server: &Server, config: Arc<rustls::ClientConfig>) -> Option<Pdu> {
+    let tcp = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+    // TLS 1.3 中服务器在客户端握手完成之后才校验客户端证书，失败会体现在之后的读取上
+    let stream = ClientTlsStream::connect(tcp, "localhost", config).ok()?;
+    let format = FrameFormat::default();
+    PduWriter::new(&stream, format).write_pdu(&Pdu::with_type(MsgType::Data, 7, b"ping", &format).unwrap()).ok()?;
+    PduReader::new(&stream, format).read_pdu().ok()?
+}
+
+fn identity_reaches_handler(model: Model) {
+    let pki = Pki::new(&format!("identity-{:?}", model));
+    pki.issue("alice", "alice", &["alice.example.com"]);
+    let (server, _, runner) = common::start(mtls_server(model, &pki, WhoAmI));
+
+    let response = request(&server, pki.client_config("alice")).unwrap();
+    assert_eq!(response.request_id, 7);
+    assert_eq!(response.payload, b"alice|alice.example.com");
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+}
+
+#[test]
+fn identity_reaches_thread_handler() {
+    identity_reaches_handler(Model::ThreadPerConnection);
+}
+
+#[test]
+fn identity_reaches_tokio_handler() {
+    identity_reaches_handler(Model::Tokio);
+}
+
+#[test]
+fn client_without_certificate_is_rejected() {
+    let pki = Pki::new("anonymous");
+    let (server, _, runner) = common::start(mtls_server(Model::ThreadPerConnection, &pki, WhoAmI));
+
+    assert!(request(&server, tls::client_config(pki.path("ca.pem")).unwrap()).is_none());
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+}
+
+#[test]
+fn certificate_from_other_ca_is_rejected() {
+    let pki = Pki::new("trusted");
+    let other = Pki::new("untrusted");
+    other.issue("mallory", "mallory", &[]);
+    let (server, _, runner) = common::start(mtls_server(Model::ThreadPerConnection, &pki, WhoAmI));
+
+    // 信任服务器的 CA，但出示的证书由另一个 CA 签发
+    let config = tls::client_config_with_cert(pki.path("ca.pem"), other.path("mallory.pem"), other.path("mallory.key")).unwrap();
+    assert!(request(&server, config).is_none());
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+}
+
+#[test]
+fn authorization_by_identity() {
+    let pki = Pki::new("authorize");
+    pki.issue("alice", "alice", &["alice.example.com"]);
+    pki.issue("bob", "bob", &["bob.example.com"]);
+    pki.issue("carol", "carol", &[]);
+    let handler = Authorize::new(WhoAmI).allow("alice.example.com").allow("bob").deny("bob.example.com");
+    let (server, _, runner) = common::start(mtls_server(Model::ThreadPerConnection, &pki, handler));
+
+    let response = request(&server, pki.client_config("alice")).unwrap();
+    assert_eq!(response.kind, MsgType::Data);
+
+    // 拒绝列表优先于允许列表
+    let response = request(&server, pki.client_config("bob")).unwrap();
+    assert_eq!(response.kind, MsgType::Error);
+    assert_eq!(response.request_id, 7);
+
+    // 不在允许列表中
+    let response = request(&server, pki.client_config("carol")).unwrap();
+    assert_eq!(response.kind, MsgType::Error);
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+}
+
+fn access_list_can_be_updated(model: Model) {
+    let pki = Pki::new(&format!("access-{:?}", model));
+    pki.issue("alice", "alice", &[]);
+    pki.issue("bob", "bob", &[]);
+    let (server, _, runner) = common::start(mtls_server(model, &pki, WhoAmI));
+    assert!(request(&server, pki.client_config("bob")).is_some());
+
+    // 运行中更新允许列表，之后的连接按新的列表检查
+    let access = AccessList { allow: vec!["alice".to_string()], deny: Vec::new() };
+    server.update_settings(Settings { access, ..Settings::default() });
+    assert_eq!(request(&server, pki.client_config("alice")).unwrap().payload, b"alice|");
+    assert!(request(&server, pki.client_config("bob")).is_none());
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+}
+
+#[test]
+fn access_list_is_updated_for_thread_server() {
+    access_list_can_be_updated(Model::ThreadPerConnection);
+}
+
+#[test]
+fn access_list_is_updated_for_tokio_server() {
+    access_list_can_be_updated(Model::Tokio);
+}
@@ -0,0 +1,66 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+use common::{pdu, raise_fd_limit};
+use socket::codec::PduReader;
+use socket::network_handler::{Echo, Pdu};
+use socket::server::{Model, Server};
+
+/// 读取一个响应；连接被关闭或超时时返回 None
+fn try_read_pdu(reader: &mut PduReader<impl Read>) -> Option<Pdu> {
+    reader.read_pdu().ok().flatten()
+}
+
+#[test]
+fn poll_and_select_serve_concurrent_clients() {
+    for model in [Model::Poll, Model::Select] {
+        let (server, addr, runner) = common::start(Server::builder().bind("127.0.0.1:0").backlog(4096).model(model).handler(Echo));
+
+        let clients: Vec<TcpStream> = (0..300).map(|_| TcpStream::connect(addr).unwrap()).collect();
+        for (id, client) in clients.iter().enumerate() {
+            (&*client).write_all(&pdu(id as u32, format!("client {}", id).as_bytes())).unwrap();
+        }
+        for (id, client) in clients.iter().enumerate() {
+            let response = try_read_pdu(&mut common::reader(client)).unwrap();
+            assert_eq!(response.request_id, id as u32, "{:?}", model);
+            assert_eq!(response.payload, format!("client {}", id).as_bytes());
+        }
+
+        server.shutdown();
+        runner.join().unwrap().unwrap();
+    }
+}
+
+#[test]
+fn select_rejects_descriptors_beyond_fd_setsize() {
+    raise_fd_limit();
+    let (server, addr, runner) = common::start(Server::builder().bind("127.0.0.1:0").backlog(4096).model(Model::Select).handler(Echo));
+
+    // 客户端与服务器在同一个进程中，服务器接受的描述符很快超过 FD_SETSIZE
+    let clients: Vec<TcpStream> = (0..libc::FD_SETSIZE).map(|_| TcpStream::connect(addr).unwrap()).collect();
+    for (id, client) in clients.iter().enumerate() {
+        (&*client).write_all(&pdu(id as u32, b"hello")).unwrap();
+    }
+    let served = clients.iter().filter(|client| try_read_pdu(&mut common::reader(client)).is_some()).count();
+    assert!(served > 0);
+    assert!(served < clients.len());
+
+    // 描述符释放后可以继续接受连接
+    drop(clients);
+    thread::sleep(Duration::from_millis(200));
+    let stream = TcpStream::connect(addr).unwrap();
+    (&stream).write_all(&pdu(1, b"again")).unwrap();
+    assert_eq!(try_read_pdu(&mut common::reader(&stream)).unwrap().payload, b"again");
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+}
+
+#[test]
+fn poll_binary_reports_fd_limit_and_resumes() {
+    common::binary_resumes_after_fd_limit(env!("CARGO_BIN_EXE_server_poll"));
+}
@@ -0,0 +1,202 @@
+mod common;
+
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+use std::os::unix::process::CommandExt;
+use std::process::Child;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use common::{reader, send, server_command, wait_for};
+use socket::config::Config;
+use socket::network_handler::{ConnContext, Handler, Pdu};
+use socket::server::{Model, Server};
+
+fn spawn_server(workers: usize) -> (Child, SocketAddr, Receiver<String>) {
+    common::spawn_server(
+        server_command(env!("CARGO_BIN_EXE_server_prefork"))
+            .args(["--bind", "127.0.0.1:0", "--log-level", "info", "--workers", &workers.to_string()])
+            // 单独的进程组，用于测试发给整个进程组的信号
+            .process_group(0),
+    )
+}
+
+fn created_worker(line: &str) -> Option<i32> {
+    line.strip_prefix("[srv] 创建工作进程[")?.strip_suffix("]")?.parse().ok()
+}
+
+/// 建立连接并确认已经由某个工作进程处理，连接保持打开时该工作进程一直被占用
+fn served(addr: SocketAddr, request_id: u32) -> TcpStream {
+    let stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    send(&stream, request_id);
+    let response = reader(&stream).read_pdu().unwrap().unwrap();
+    assert_eq!(response.request_id, request_id);
+    stream
+}
+
+/// 没有被任何工作进程处理的连接读取超时
+fn waiting(addr: SocketAddr) -> TcpStream {
+    let stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_millis(300))).unwrap();
+    send(&stream, 0);
+    assert!(reader(&stream).read_pdu().is_err());
+    stream
+}
+
+/// 连接收到了 going-away，没有收到任何消息时返回 false
+fn going_away(stream: &TcpStream) -> bool {
+    reader(stream).read_pdu().ok().flatten().is_some_and(|pdu| pdu.is_going_away())
+}
+
+#[test]
+fn prefork_model_is_parsed() {
+    let config = Config::parse("[server]\nmodel = \"prefork\"\nworkers = 2\n").unwrap();
+    assert_eq!(config.server.model, Some(Model::Prefork));
+}
+
+#[test]
+fn dead_workers_are_restarted() {
+    let (mut child, addr, lines) = spawn_server(2);
+    let workers = [wait_for(&lines, created_worker), wait_for(&lines, created_worker)];
+
+    // 两个工作进程各自处理一个连接
+    let first = served(addr, 1);
+    let second = served(addr, 2);
+    drop(waiting(addr));
+
+    // 被杀死的工作进程由主进程重新创建
+    unsafe { libc::kill(workers[0], libc::SIGKILL) };
+    let restarted = wait_for(&lines, created_worker);
+    assert!(!workers.contains(&restarted));
+    drop((first, second));
+    let third = served(addr, 3);
+
+    // SIGINT 转发给所有工作进程，连接随之关闭
+    unsafe { libc::kill(child.id() as i32, libc::SIGINT) };
+    assert!(reader(&third).read_pdu().unwrap().is_none());
+    assert!(child.wait().unwrap().success());
+    wait_for(&lines, |line| (line == "服务器关闭").then_some(()));
+}
+
+#[test]
+fn sigttin_and_sigttou_adjust_worker_count() {
+    let (mut child, addr, lines) = spawn_server(1);
+    let pid = child.id() as i32;
+    wait_for(&lines, created_worker);
+    let first = served(addr, 1);
+    let queued = waiting(addr);
+
+    // 增加一个工作进程后，排队的连接得到处理
+    unsafe { libc::kill(pid, libc::SIGTTIN) };
+    wait_for(&lines, created_worker);
+    queued.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    assert_eq!(reader(&queued).read_pdu().unwrap().unwrap().request_id, 0);
+
+    // 减少一个工作进程：被停止的工作进程处理完当前请求后发送 going-away 并退出，且不会被重新创建
+    unsafe { libc::kill(pid, libc::SIGTTOU) };
+    let stopped: i32 = wait_for(&lines, |line| line.strip_prefix("[srv] 停止工作进程[")?.strip_suffix("]")?.parse().ok());
+    let reaped = format!("[srv] 收到子进程[{}]的退出信号", stopped);
+    wait_for(&lines, |line| (line == reaped).then_some(()));
+    first.set_read_timeout(Some(Duration::from_millis(300))).unwrap();
+    queued.set_read_timeout(Some(Duration::from_millis(300))).unwrap();
+    let (retired, remaining) = if going_away(&first) {
+        (first, queued)
+    } else {
+        assert!(going_away(&queued));
+        (queued, first)
+    };
+    assert!(reader(&retired).read_pdu().unwrap().is_none());
+    remaining.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    drop(waiting(addr));
+
+    // 至少保留一个工作进程
+    unsafe { libc::kill(pid, libc::SIGTTOU) };
+    wait_for(&lines, |line| line.contains("至少保留一个工作进程").then_some(()));
+
+    // SIGTERM 转发给所有工作进程，剩下的连接收到 going-away 后关闭
+    unsafe { libc::kill(pid, libc::SIGTERM) };
+    let mut reader = reader(&remaining);
+    assert!(reader.read_pdu().unwrap().unwrap().is_going_away());
+    assert!(reader.read_pdu().unwrap().is_none());
+    assert!(child.wait().unwrap().success());
+    wait_for(&lines, |line| (line == "服务器关闭").then_some(()));
+}
+
+#[test]
+fn process_group_signals_do_not_stop_workers() {
+    let (mut child, addr, lines) = spawn_server(1);
+    let pid = child.id() as i32;
+    wait_for(&lines, created_worker);
+    let first = served(addr, 1);
+
+    // 发给整个进程组的 SIGTTIN 只由主进程处理，工作进程不会被暂停
+    unsafe { libc::kill(-pid, libc::SIGTTIN) };
+    wait_for(&lines, created_worker);
+    send(&first, 2);
+    assert_eq!(reader(&first).read_pdu().unwrap().unwrap().request_id, 2);
+
+    unsafe { libc::kill(pid, libc::SIGINT) };
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn reload_replaces_workers() {
+    let (mut child, addr, lines) = spawn_server(1);
+    let pid = child.id() as i32;
+    let old = wait_for(&lines, created_worker);
+    let first = served(addr, 1);
+
+    // 重新加载后旧的工作进程处理完当前连接后退出，由使用新设置的工作进程代替
+    unsafe { libc::kill(pid, libc::SIGHUP) };
+    let stopped: i32 = wait_for(&lines, |line| line.strip_prefix("[srv] 停止工作进程[")?.strip_suffix("]")?.parse().ok());
+    assert_eq!(stopped, old);
+    assert_ne!(wait_for(&lines, created_worker), old);
+    assert!(going_away(&first));
+    drop(served(addr, 2));
+
+    unsafe { libc::kill(pid, libc::SIGINT) };
+    assert!(child.wait().unwrap().success());
+}
+
+/// 在子进程中先取得标准输出与标准错误的锁再回显
+struct Output;
+
+impl Handler for Output {
+    fn handle(&self, pdu: Pdu, _ctx: &ConnContext) -> Vec<Pdu> {
+        drop((io::stdout().lock(), io::stderr().lock()));
+        vec![pdu]
+    }
+}
+
+#[test]
+fn forked_children_can_write_output() {
+    // 其他线程几乎一直持有输出的锁，fork() 时没有等它释放的子进程会卡在第一次输出上
+    let stop = Arc::new(AtomicBool::new(false));
+    let holder = thread::spawn({
+        let stop = stop.clone();
+        move || {
+            while !stop.load(Ordering::Relaxed) {
+                let output = (io::stdout().lock(), io::stderr().lock());
+                thread::sleep(Duration::from_millis(2));
+                drop(output);
+                thread::yield_now();
+            }
+        }
+    });
+
+    for model in [Model::ProcessPerConnection, Model::Prefork] {
+        let (server, addr, runner) = common::start(Server::builder().bind("127.0.0.1:0").model(model).workers(4).handler(Output));
+        for request_id in 1..=8 {
+            drop(served(addr, request_id));
+        }
+        server.shutdown();
+        runner.join().unwrap().unwrap();
+    }
+
+    stop.store(true, Ordering::Relaxed);
+    holder.join().unwrap();
+}
@@ -0,0 +1,181 @@
+mod common;
+
+use std::io::Write;
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use socket::auth::{self, PskAuth, PskKeys};
+use socket::codec::{PduReader, PduWriter};
+use socket::network_handler::{FrameFormat, LengthFormat, MsgType, Pdu};
+use socket::server::{Model, Server, ServerBuilder};
+
+const KEYS: &str = "
+# 轮换期间同时保留新旧两个密钥
+old = 000102030405060708090a0b0c0d0e0f
+new = 101112131415161718191a1b1c1d1e1f
+";
+
+fn psk_server(model: Model, timeout: Duration) -> ServerBuilder {
+    let auth = PskAuth::new(PskKeys::parse(KEYS).unwrap()).timeout(timeout);
+    Server::builder().bind("127.0.0.1:0").model(model).psk(auth)
+}
+
+/// 连接服务器并读取挑战，返回连接与 nonce
+fn connect(server: &Server) -> (TcpStream, Vec<u8>) {
+    let stream = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+    let challenge = PduReader::new(&stream, FrameFormat::default()).read_pdu().unwrap().unwrap();
+    let nonce = auth::parse_challenge(&challenge).expect("第一个 PDU 应当是挑战");
+    (stream, nonce)
+}
+
+fn authenticate_and_echo(model: Model) {
+    let (server, _, runner) = common::start(psk_server(model, Duration::from_secs(5)));
+    let format = FrameFormat::default();
+    let keys = PskKeys::parse(KEYS).unwrap();
+
+    for name in ["old", "new"] {
+        let (stream, nonce) = connect(&server);
+        let mut writer = PduWriter::new(&stream, format);
+        let mut reader = PduReader::new(&stream, format);
+        writer.write_pdu(&auth::response_pdu(name, keys.get(name).unwrap(), &nonce, &format).unwrap()).unwrap();
+        let ok = reader.read_pdu().unwrap().unwrap();
+        assert_eq!(auth::parse_ok(&ok), Some(name));
+
+        writer.write_pdu(&Pdu::with_type(MsgType::Data, 1, b"hello", &format).unwrap()).unwrap();
+        let response = reader.read_pdu().unwrap().unwrap();
+        assert_eq!(response.request_id, 1);
+        assert_eq!(response.payload, b"hello");
+    }
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+}
+
+#[test]
+fn rotated_keys_authenticate_on_thread_server() {
+    authenticate_and_echo(Model::ThreadPerConnection);
+}
+
+#[test]
+fn rotated_keys_authenticate_on_tokio_server() {
+    authenticate_and_echo(Model::Tokio);
+}
+
+#[test]
+fn wrong_key_is_rejected() {
+    let (server, _, runner) = common::start(psk_server(Model::ThreadPerConnection, Duration::from_secs(5)));
+    let format = FrameFormat::default();
+
+    let (stream, nonce) = connect(&server);
+    PduWriter::new(&stream, format).write_pdu(&auth::response_pdu("old", b"wrong key", &nonce, &format).unwrap()).unwrap();
+    let mut reader = PduReader::new(&stream, format);
+    let response = reader.read_pdu().unwrap().unwrap();
+    assert_eq!(response.kind, MsgType::Error);
+    // 认证失败后连接被关闭
+    assert!(reader.read_pdu().unwrap().is_none());
+
+    // 未知的密钥名同样被拒绝
+    let (stream, nonce) = connect(&server);
+    PduWriter::new(&stream, format).write_pdu(&auth::response_pdu("retired", b"key", &nonce, &format).unwrap()).unwrap();
+    let unknown = PduReader::new(&stream, format).read_pdu().unwrap().unwrap();
+    assert_eq!(unknown.kind, MsgType::Error);
+    // 两种失败的错误信息相同，且不回显密钥名
+    assert_eq!(unknown.payload, response.payload);
+    assert!(!String::from_utf8_lossy(&unknown.payload).contains("retired"));
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+}
+
+#[test]
+fn data_before_authentication_is_not_echoed() {
+    let (server, _, runner) = common::start(psk_server(Model::ThreadPerConnection, Duration::from_secs(5)));
+    let format = FrameFormat::default();
+
+    let (stream, _) = connect(&server);
+    PduWriter::new(&stream, format).write_pdu(&Pdu::with_type(MsgType::Data, 1, b"hello", &format).unwrap()).unwrap();
+    let mut reader = PduReader::new(&stream, format);
+    let response = reader.read_pdu().unwrap().unwrap();
+    assert_eq!(response.kind, MsgType::Error);
+    assert!(reader.read_pdu().unwrap().is_none());
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+}
+
+fn silent_client_times_out(model: Model) {
+    let (server, _, runner) = common::start(psk_server(model, Duration::from_millis(200)));
+
+    let started = Instant::now();
+    let (stream, _) = connect(&server);
+    let mut reader = PduReader::new(&stream, FrameFormat::default());
+    let response = reader.read_pdu().unwrap().unwrap();
+    assert_eq!(response.kind, MsgType::Error);
+    assert!(reader.read_pdu().unwrap().is_none());
+    assert!(started.elapsed() >= Duration::from_millis(200));
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+}
+
+#[test]
+fn silent_client_times_out_on_thread_server() {
+    silent_client_times_out(Model::ThreadPerConnection);
+}
+
+#[test]
+fn silent_client_times_out_on_tokio_server() {
+    silent_client_times_out(Model::Tokio);
+}
+
+#[test]
+fn slow_client_times_out_at_the_deadline() {
+    let (server, _, runner) = common::start(psk_server(Model::ThreadPerConnection, Duration::from_millis(300)));
+    let format = FrameFormat::default();
+    let keys = PskKeys::parse(KEYS).unwrap();
+
+    // 每次只发送一个字节，单次读取不会超时，但整个认证过程超过了截止时间
+    let started = Instant::now();
+    let (stream, nonce) = connect(&server);
+    let bytes = auth::response_pdu("old", keys.get("old").unwrap(), &nonce, &format).unwrap().to_vec(&format).unwrap();
+    for byte in &bytes[..bytes.len() - 1] {
+        if (&stream).write_all(std::slice::from_ref(byte)).is_err() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    let mut reader = PduReader::new(&stream, format);
+    let response = reader.read_pdu().unwrap().unwrap();
+    assert_eq!(response.kind, MsgType::Error);
+    assert!(started.elapsed() < Duration::from_millis(50) * bytes.len() as u32);
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+}
+
+#[test]
+fn psk_requires_v2_format() {
+    let auth = PskAuth::new(PskKeys::parse(KEYS).unwrap());
+    let result = Server::builder()
+        .bind("127.0.0.1:0")
+        .model(Model::ThreadPerConnection)
+        .frame_format(FrameFormat::legacy(LengthFormat::U16, 1024))
+        .psk(auth)
+        .build();
+    assert_eq!(result.err().unwrap().kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn key_file_errors_are_reported() {
+    let keys = PskKeys::parse(KEYS).unwrap();
+    assert_eq!(keys.names().collect::<Vec<_>>(), ["new", "old"]);
+    // 调试输出中不包含密钥
+    assert!(!format!("{:?}", keys).contains("0001"));
+
+    assert!(PskKeys::parse("missing-separator").is_err());
+    assert!(PskKeys::parse("a:b = 00").is_err());
+    assert!(PskKeys::parse("a = not-hex").is_err());
+    assert!(PskKeys::parse("a = ").is_err());
+    assert!(PskKeys::parse("a = 00\na = 01").is_err());
+}
@@ -0,0 +1,110 @@
+mod common;
+
+use std::io::{BufRead, BufReader};
+use std::net::TcpStream;
+use std::process::Stdio;
+
+use common::{server_command, spawn_server, wait_for, TempFile};
+use socket::codec::{PduReader, PduWriter};
+use socket::config::ConfigError;
+use socket::log::{self, Level};
+use socket::network_handler::{FrameFormat, MsgType, Pdu};
+use socket::server::{Model, Server, Settings};
+
+/// 发送一个 PDU 并等待回显，连接被服务器关闭时返回 false
+fn echo(stream: &TcpStream, request_id: u32) -> bool {
+    let format = FrameFormat::default();
+    let pdu = Pdu::with_type(MsgType::Data, request_id, b"hello", &format).unwrap();
+    if PduWriter::new(stream, format).write_pdu(&pdu).is_err() {
+        return false;
+    }
+    match PduReader::new(stream, format).read_pdu() {
+        Ok(Some(response)) => {
+            assert_eq!(response.request_id, request_id);
+            assert_eq!(response.payload, b"hello");
+            true
+        }
+        _ => false,
+    }
+}
+
+#[test]
+fn updated_settings_apply_to_new_connections_only() {
+    let (server, addr, runner) = common::start(Server::builder().bind("127.0.0.1:0").model(Model::ThreadPerConnection));
+
+    let first = TcpStream::connect(addr).unwrap();
+    assert!(echo(&first, 1));
+
+    server.update_settings(Settings { max_connections: Some(1), read_buffer: 16, ..Settings::default() });
+    assert_eq!(server.settings().read_buffer, 16);
+    // 已有的连接不受影响，新连接超出上限被关闭
+    assert!(echo(&first, 2));
+    let second = TcpStream::connect(addr).unwrap();
+    assert!(!echo(&second, 3));
+
+    server.update_settings(Settings { max_connections: Some(2), read_buffer: 16, ..Settings::default() });
+    let third = TcpStream::connect(addr).unwrap();
+    assert!(echo(&third, 4));
+    assert!(echo(&first, 5));
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+}
+
+#[test]
+fn failed_reload_keeps_previous_settings() {
+    let server = Server::builder()
+        .bind("127.0.0.1:0")
+        .max_connections(3)
+        .reload(|| Err(ConfigError::Invalid("limits.max_connections 必须大于 0".to_string())))
+        .build()
+        .unwrap();
+    assert!(matches!(server.reload(), Err(ConfigError::Invalid(_))));
+    assert_eq!(server.settings().max_connections, Some(3));
+
+    // 没有设置重新加载函数时什么也不做
+    let server = Server::builder().bind("127.0.0.1:0").build().unwrap();
+    server.reload().unwrap();
+    assert_eq!(server.settings().max_connections, None);
+}
+
+#[test]
+fn log_level_follows_settings() {
+    let server = Server::builder().bind("127.0.0.1:0").build().unwrap();
+    server.update_settings(Settings { log_level: Some(Level::Info), ..Settings::default() });
+    assert_eq!(log::level(), Level::Info);
+    // None 保持当前级别
+    server.update_settings(Settings::default());
+    assert_eq!(log::level(), Level::Info);
+    server.update_settings(Settings { log_level: Some(log::DEFAULT_LEVEL), ..Settings::default() });
+}
+
+#[test]
+fn binary_reloads_config_on_sighup() {
+    let file = TempFile::new("sighup.toml", "[server]\nlisten = [\"127.0.0.1:0\"]\n\n[log]\nlevel = \"info\"\n");
+    let (mut child, addr, lines) =
+        spawn_server(server_command(env!("CARGO_BIN_EXE_server_muti_thread")).arg("--config").arg(&file.0).stderr(Stdio::piped()));
+    let pid = child.id() as i32;
+    let mut errors = BufReader::new(child.stderr.take().unwrap()).lines().map(Result::unwrap);
+
+    let first = TcpStream::connect(addr).unwrap();
+    assert!(echo(&first, 1));
+
+    file.write("[server]\nlisten = [\"127.0.0.1:0\"]\n\n[limits]\nmax_connections = 1\n\n[log]\nlevel = \"info\"\n");
+    unsafe { libc::kill(pid, libc::SIGHUP) };
+    wait_for(&lines, |line| (line == "[srv] 配置已重新加载").then_some(()));
+    assert!(echo(&first, 2));
+    assert!(!echo(&TcpStream::connect(addr).unwrap(), 3));
+
+    // 配置错误时保留原来的设置
+    file.write("[limits]\nmax_connections = 0\n");
+    unsafe { libc::kill(pid, libc::SIGHUP) };
+    assert!(errors.by_ref().any(|line| line.contains("重新加载配置失败") && line.contains("max_connections")));
+    assert!(echo(&first, 4));
+    assert!(!echo(&TcpStream::connect(addr).unwrap(), 5));
+
+    drop(first);
+    unsafe { libc::kill(pid, libc::SIGINT) };
+    lines.iter().for_each(drop);
+    assert!(child.wait().unwrap().success());
+}
@@ -0,0 +1,181 @@
+mod common;
+
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use common::{server_command, spawn_server};
+use socket::codec::{FrameAssembler, PduReader};
+use socket::network_handler::{Checksum, CorruptPolicy, FrameFormat, LengthFormat, MsgType, Pdu, PduError};
+use socket::server::Server;
+
+fn format() -> FrameFormat {
+    FrameFormat::new(LengthFormat::U16, 4096)
+        .with_checksum(Checksum::Crc32c)
+        .on_corrupt(CorruptPolicy::Resync)
+}
+
+fn encode(request_id: u32, payload: &[u8], format: &FrameFormat) -> Vec<u8> {
+    Pdu::with_type(MsgType::Data, request_id, payload, format).unwrap().to_vec(format).unwrap()
+}
+
+/// 取出所有 PDU，返回收到的请求ID和错误个数
+fn drain(assembler: &mut FrameAssembler) -> (Vec<u32>, usize) {
+    let mut ids = Vec::new();
+    let mut errors = 0;
+    loop {
+        match assembler.next_pdu() {
+            Ok(Some(pdu)) => ids.push(pdu.request_id),
+            Ok(None) => return (ids, errors),
+            Err(e) => {
+                assert!(assembler.format().recovers_from(&e), "{}", e);
+                errors += 1;
+            }
+        }
+    }
+}
+
+#[test]
+fn garbage_between_frames_is_skipped() {
+    let format = format();
+    let garbage = [0x00, 0x13, 0xA2, 0xff, 0x42, 0x99];
+    let mut bytes = encode(1, b"first", &format);
+    bytes.extend_from_slice(&garbage);
+    bytes.extend(encode(2, b"second", &format));
+
+    let mut assembler = FrameAssembler::new(format);
+    assembler.push(&bytes);
+    let (ids, errors) = drain(&mut assembler);
+
+    assert_eq!(ids, [1, 2]);
+    assert_eq!(errors, 1);
+    assert_eq!(assembler.skipped_bytes(), garbage.len() as u64);
+    assert_eq!(assembler.buffered_len(), 0);
+}
+
+#[test]
+fn corrupted_frame_is_skipped() {
+    let format = format();
+    let mut bytes = encode(1, b"first", &format);
+    let corrupted = encode(2, b"second", &format);
+    let corrupted_start = bytes.len();
+    bytes.extend(&corrupted);
+    bytes.extend(encode(3, b"third", &format));
+    // 损坏长度字段，按声明的长度跳过会与后续数据错位
+    bytes[corrupted_start + 8] ^= 0x04;
+
+    let mut assembler = FrameAssembler::new(format);
+    for chunk in bytes.chunks(5) {
+        assembler.push(chunk);
+    }
+    let (ids, errors) = drain(&mut assembler);
+
+    assert_eq!(ids, [1, 3]);
+    assert_eq!(errors, 1);
+    assert_eq!(assembler.skipped_bytes(), corrupted.len() as u64);
+}
+
+#[test]
+fn resync_across_reads() {
+    let format = format();
+    let mut assembler = FrameAssembler::new(format);
+    assembler.push(&[0x55; 10]);
+    assert!(matches!(assembler.next_pdu(), Err(PduError::BadMagic(0x55))));
+    assert!(assembler.next_pdu().unwrap().is_none());
+
+    // 垃圾数据在之后的读取中继续到达，新的帧分多次到达
+    let bytes = encode(7, b"hello", &format);
+    assembler.push(&[0x55; 3]);
+    assembler.push(&bytes[..4]);
+    assert!(assembler.next_pdu().unwrap().is_none());
+    assembler.push(&bytes[4..]);
+
+    assert_eq!(assembler.next_pdu().unwrap().unwrap().request_id, 7);
+    assert_eq!(assembler.skipped_bytes(), 13);
+}
+
+#[test]
+fn frames_without_checksum_are_dropped_while_resyncing() {
+    let format = format();
+    let plain = FrameFormat::new(LengthFormat::U16, 4096);
+    let mut bytes = vec![0x01];
+    bytes.extend(encode(1, b"unchecked", &plain));
+    bytes.extend(encode(2, b"checked", &format));
+    bytes.extend(encode(3, b"unchecked", &plain));
+
+    let mut assembler = FrameAssembler::new(format);
+    assembler.push(&bytes);
+    let (ids, _) = drain(&mut assembler);
+
+    // 恢复同步之后不再要求校验和
+    assert_eq!(ids, [2, 3]);
+}
+
+#[test]
+fn disconnect_policy_does_not_resync() {
+    let format = format().on_corrupt(CorruptPolicy::Disconnect);
+    let mut assembler = FrameAssembler::new(format);
+    assembler.push(&[0x55]);
+
+    let Err(e) = assembler.next_pdu() else { panic!("应当拒绝非法魔数") };
+    assert!(matches!(e, PduError::BadMagic(0x55)));
+    assert!(!format.recovers_from(&e));
+    assert_eq!(assembler.skipped_bytes(), 0);
+}
+
+#[test]
+fn builder_rejects_resync_without_checksum() {
+    let format = FrameFormat::default().on_corrupt(CorruptPolicy::Resync);
+    let result = Server::builder().bind("127.0.0.1:0").frame_format(format).build();
+    assert_eq!(result.err().unwrap().kind(), io::ErrorKind::InvalidInput);
+}
+
+/// 在同一个连接上依次发送，返回收到的 PDU，直到收到 request_id 为 last 的回显
+fn exchange(addr: SocketAddr, bytes: &[u8], last: u32) -> Vec<Pdu> {
+    let stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    (&stream).write_all(bytes).unwrap();
+    let mut reader = PduReader::new(&stream, format());
+    let mut pdus = Vec::new();
+    while !pdus.iter().any(|pdu: &Pdu| pdu.kind == MsgType::Data && pdu.request_id == last) {
+        pdus.push(reader.read_pdu().unwrap().unwrap());
+    }
+    pdus
+}
+
+#[test]
+fn binary_recovers_according_to_on_corrupt() {
+    let format = format();
+    let mut corrupted = encode(2, b"second", &format);
+    let last = corrupted.len() - 5;
+    corrupted[last] ^= 0xff;
+
+    for (policy, bytes) in [
+        ("skip_frame", [encode(1, b"first", &format), corrupted.clone(), encode(3, b"third", &format)].concat()),
+        ("resync", [encode(1, b"first", &format), vec![0x00, 0x13, 0xff], encode(3, b"third", &format)].concat()),
+    ] {
+        let (mut server, addr, lines) = spawn_server(server_command(env!("CARGO_BIN_EXE_server_muti_thread")).args([
+            "--bind", "127.0.0.1:0", "--length-format", "u16", "--max-payload", "4096", "--checksum", "crc32c", "--on-corrupt", policy,
+        ]));
+
+        // 损坏的数据得到错误 PDU，连接保持打开，之后的请求正常处理
+        let pdus = exchange(addr, &bytes, 3);
+        assert_eq!((pdus[0].kind, pdus[0].request_id), (MsgType::Data, 1), "{}", policy);
+        assert!(pdus[1..pdus.len() - 1].iter().all(|pdu| pdu.kind == MsgType::Error), "{}", policy);
+        assert!(pdus.len() > 2, "{}", policy);
+
+        unsafe { libc::kill(server.id() as i32, libc::SIGINT) };
+        lines.iter().for_each(drop);
+        assert!(server.wait().unwrap().success());
+    }
+}
+
+#[test]
+fn binary_rejects_resync_without_checksum() {
+    let output = server_command(env!("CARGO_BIN_EXE_server_muti_thread"))
+        .args(["--bind", "127.0.0.1:0", "--on-corrupt", "resync"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("checksum"));
+}
@@ -0,0 +1,131 @@
+mod common;
+
+use std::collections::HashSet;
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use common::{reader, send, server_command, spawn_server};
+use socket::network_handler::{ConnContext, Handler, MsgType, Pdu};
+use socket::server::{Model, Server};
+
+/// 回复处理该连接的工作线程
+struct WhoAmI;
+
+impl Handler for WhoAmI {
+    fn handle(&self, pdu: Pdu, ctx: &ConnContext) -> Vec<Pdu> {
+        vec![Pdu::with_type(MsgType::Data, pdu.request_id, ctx.id.as_bytes(), &ctx.format).unwrap()]
+    }
+}
+
+/// 发送一个请求并返回响应的 payload
+fn request(addr: SocketAddr, request_id: u32) -> Vec<u8> {
+    let stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    send(&stream, request_id);
+    let response = reader(&stream).read_pdu().unwrap().unwrap();
+    assert_eq!(response.request_id, request_id);
+    response.payload
+}
+
+/// /proc/net/tcp 中监听在 addr 上的套接字数
+fn listening_sockets(addr: SocketAddr) -> usize {
+    let local = format!("0100007F:{:04X}", addr.port());
+    std::fs::read_to_string("/proc/net/tcp")
+        .unwrap()
+        .lines()
+        .skip(1)
+        .filter(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            fields[1] == local && fields[3] == "0A"
+        })
+        .count()
+}
+
+/// 等待 run() 打开各自的监听器，返回最后一次看到的监听套接字数
+fn wait_listening(addr: SocketAddr, expected: usize) -> usize {
+    for _ in 0..50 {
+        if listening_sockets(addr) == expected {
+            break;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    listening_sockets(addr)
+}
+
+#[test]
+fn thread_pool_workers_share_connections_through_their_own_listeners() {
+    let (server, addr, runner) = common::start(
+        Server::builder()
+            .bind("127.0.0.1:0")
+            .model(Model::ThreadPool)
+            .workers(4)
+            .reuse_port(true)
+            .handler(WhoAmI),
+    );
+    // 第一个工作线程使用共用的监听套接字，其余的各自打开一个
+    assert_eq!(wait_listening(addr, 4), 4);
+
+    // 每个连接来自不同的源端口，内核按四元组的哈希把它们分配到不同的监听器
+    let workers: HashSet<Vec<u8>> = (0..64).map(|id| request(addr, id)).collect();
+    assert!(workers.len() > 1, "所有连接都由同一个工作线程处理: {:?}", workers);
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+    // 工作线程的监听器随之关闭，只剩下 Server 持有的监听套接字
+    assert_eq!(listening_sockets(addr), 1);
+}
+
+#[test]
+fn tokio_accept_loops_use_their_own_listeners() {
+    let (server, addr, runner) = common::start(
+        Server::builder()
+            .bind("127.0.0.1:0")
+            .model(Model::Tokio)
+            .workers(3)
+            .reuse_port(true)
+            .handler(socket::network_handler::Echo),
+    );
+    assert_eq!(wait_listening(addr, 3), 3);
+    for id in 0..32 {
+        assert_eq!(request(addr, id), b"hello");
+    }
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+}
+
+#[test]
+fn reuse_port_is_rejected_by_other_models() {
+    let result = Server::builder().bind("127.0.0.1:0").model(Model::Epoll).reuse_port(true).build();
+    assert_eq!(result.err().unwrap().kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn reuse_port_is_rejected_with_queue_depth() {
+    let result = Server::builder().bind("127.0.0.1:0").model(Model::ThreadPool).queue_depth(8).reuse_port(true).build();
+    assert_eq!(result.err().unwrap().kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn prefork_workers_share_connections_through_their_own_listeners() {
+    let (mut child, addr, lines) = spawn_server(
+        server_command(env!("CARGO_BIN_EXE_server_prefork")).args(["--bind", "127.0.0.1:0", "--log-level", "info", "--workers", "3", "--reuse-port"]),
+    );
+
+    assert_eq!(wait_listening(addr, 3), 3);
+    for id in 0..64 {
+        assert_eq!(request(addr, id), b"hello");
+    }
+
+    unsafe { libc::kill(child.id() as i32, libc::SIGINT) };
+    assert!(child.wait().unwrap().success());
+    // 工作进程在日志中用进程ID标识
+    let workers: HashSet<String> = lines
+        .iter()
+        .filter(|line| line.contains("] client[") && line.ends_with("is accepted!"))
+        .filter_map(|line| Some(line.strip_prefix('[')?.split(']').next()?.to_string()))
+        .collect();
+    assert!(workers.len() > 1, "所有连接都由同一个工作进程处理: {:?}", workers);
+}
@@ -0,0 +1,42 @@
+mod common;
+
+use std::io;
+use std::net::{TcpListener, TcpStream};
+
+use common::{reader, send};
+use socket::server::{Model, Server, ServerBuilder};
+
+#[test]
+fn builder_rejects_invalid_settings() {
+    let invalid = |builder: ServerBuilder| builder.build().err().unwrap().kind();
+
+    assert_eq!(invalid(Server::builder().bind("127.0.0.1:0").model(Model::ThreadPool).workers(0)), io::ErrorKind::InvalidInput);
+    assert_eq!(invalid(Server::builder().bind("127.0.0.1:0").model(Model::Single).reuse_port(true)), io::ErrorKind::InvalidInput);
+    assert_eq!(
+        invalid(Server::builder().bind("127.0.0.1:0").model(Model::ThreadPool).reuse_port(true).queue_depth(4)),
+        io::ErrorKind::InvalidInput
+    );
+    // 地址无法解析或已被占用时在 build() 中失败，而不是在 run() 中
+    assert!(Server::builder().bind("not an address").build().is_err());
+    let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+    assert_eq!(invalid(Server::builder().bind(taken.local_addr().unwrap().to_string())), io::ErrorKind::AddrInUse);
+}
+
+#[test]
+fn embedded_server_runs_until_shutdown() {
+    for model in [Model::Single, Model::ThreadPerConnection, Model::ProcessPerConnection, Model::Tokio] {
+        let (server, addr, runner) = common::start(Server::builder().bind("127.0.0.1:0").model(model));
+        assert_eq!(server.model(), model);
+
+        let stream = TcpStream::connect(addr).unwrap();
+        send(&stream, 1);
+        assert_eq!(reader(&stream).read_pdu().unwrap().unwrap().payload, b"hello");
+        drop(stream);
+
+        // 宿主程序调用 shutdown() 后 run() 返回，监听套接字随 Server 一起关闭
+        server.shutdown();
+        runner.join().unwrap().unwrap();
+        drop(server);
+        assert!(TcpStream::connect(addr).is_err(), "{:?}", model);
+    }
+}
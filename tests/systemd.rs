@@ -0,0 +1,151 @@
+mod common;
+
+use std::io::{BufRead, BufReader};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixDatagram;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use common::{server_command, spawn_server};
+use socket::codec::{PduReader, PduWriter};
+use socket::network_handler::{FrameFormat, MsgType, Pdu};
+use socket::systemd;
+
+/// 模拟 systemd 接收状态通知的数据报套接字
+struct NotifySocket {
+    path: PathBuf,
+    socket: UnixDatagram,
+}
+
+impl NotifySocket {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("socket-systemd-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path).unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        NotifySocket { path, socket }
+    }
+
+    /// 等待包含指定状态的通知
+    fn expect(&self, state: &str) -> String {
+        let mut buffer = [0_u8; 256];
+        loop {
+            let size = self.socket.recv(&mut buffer).unwrap_or_else(|e| panic!("没有收到 {}: {}", state, e));
+            let message = String::from_utf8_lossy(&buffer[..size]).into_owned();
+            if message.lines().any(|line| line == state) {
+                return message;
+            }
+        }
+    }
+}
+
+impl Drop for NotifySocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn echo(addr: SocketAddr) {
+    let stream = TcpStream::connect(addr).unwrap();
+    let format = FrameFormat::default();
+    PduWriter::new(&stream, format).write_pdu(&Pdu::with_type(MsgType::Data, 1, b"hello", &format).unwrap()).unwrap();
+    let response = PduReader::new(&stream, format).read_pdu().unwrap().unwrap();
+    assert_eq!((response.request_id, response.payload.as_slice()), (1, b"hello".as_slice()));
+}
+
+/// 像 systemd 一样把监听套接字放在描述符 3，LISTEN_PID 由 shell 在 exec 之前设置为自己的进程ID
+fn activate(bin: &str, listener: &TcpListener, notify: &NotifySocket) -> Command {
+    let fd = listener.as_raw_fd();
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg("export LISTEN_PID=$$; exec \"$0\" \"$@\"")
+        .arg(bin)
+        // 套接字已被占用，服务器自己绑定这个地址一定会失败
+        .args(["--bind", &listener.local_addr().unwrap().to_string(), "--log-level", "info"])
+        .env(systemd::ENV_LISTEN_FDS, "1")
+        .env(systemd::ENV_NOTIFY_SOCKET, &notify.path)
+        .env(systemd::ENV_WATCHDOG_USEC, "200000")
+        .env_remove("SOCKET_CONFIG")
+        .env_remove("SOCKET_LOG_LEVEL")
+        .env_remove("SOCKET_PSK_TIMEOUT")
+        .stdout(Stdio::piped());
+    unsafe {
+        // dup2() 得到的描述符没有设置 FD_CLOEXEC，描述符本来就是 3 时需要单独清除
+        command.pre_exec(move || {
+            let result = if fd == systemd::LISTEN_FDS_START {
+                libc::fcntl(fd, libc::F_SETFD, 0)
+            } else {
+                libc::dup2(fd, systemd::LISTEN_FDS_START)
+            };
+            if result < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    command
+}
+
+fn socket_activation(bin: &str, name: &str) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let notify = NotifySocket::new(name);
+    let mut child = activate(bin, &listener, &notify).spawn().unwrap();
+    drop(listener);
+
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines().map(Result::unwrap);
+    assert!(lines.by_ref().any(|line| line == format!("[srv] server[{}] is initializing!", addr)));
+    let ready = notify.expect("READY=1");
+    assert!(ready.contains(&format!("MAINPID={}", child.id())), "{}", ready);
+    notify.expect("WATCHDOG=1");
+    echo(addr);
+
+    unsafe { libc::kill(child.id() as i32, libc::SIGTERM) };
+    notify.expect("STOPPING=1");
+    lines.for_each(drop);
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn single_server_uses_systemd_socket() {
+    socket_activation(env!("CARGO_BIN_EXE_server"), "single");
+}
+
+#[test]
+fn thread_server_uses_systemd_socket() {
+    socket_activation(env!("CARGO_BIN_EXE_server_muti_thread"), "thread");
+}
+
+#[test]
+fn process_server_uses_systemd_socket() {
+    socket_activation(env!("CARGO_BIN_EXE_server_muti_process"), "process");
+}
+
+#[test]
+fn tokio_server_uses_systemd_socket() {
+    socket_activation(env!("CARGO_BIN_EXE_server_io_multiplexing"), "tokio");
+}
+
+#[test]
+fn listen_fds_for_another_process_are_ignored() {
+    let notify = NotifySocket::new("other");
+    let (mut child, addr, lines) = spawn_server(
+        server_command(env!("CARGO_BIN_EXE_server_muti_thread"))
+            .args(["--bind", "127.0.0.1:0", "--log-level", "info"])
+            .env(systemd::ENV_LISTEN_FDS, "1")
+            .env(systemd::ENV_LISTEN_PID, "1")
+            .env(systemd::ENV_NOTIFY_SOCKET, &notify.path),
+    );
+    // 自己绑定了地址，仍然发送就绪通知
+    notify.expect("READY=1");
+    echo(addr);
+
+    unsafe { libc::kill(child.id() as i32, libc::SIGINT) };
+    notify.expect("STOPPING=1");
+    lines.iter().for_each(drop);
+    assert!(child.wait().unwrap().success());
+}
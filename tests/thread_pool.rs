@@ -0,0 +1,100 @@
+mod common;
+
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use common::send;
+use socket::codec::PduReader;
+use socket::network_handler::{FrameFormat, Pdu};
+use socket::server::{Model, Server};
+
+/// 建立连接，之后的读取都通过返回的读取器，避免丢掉已经读入缓冲的数据
+fn connect(addr: SocketAddr) -> PduReader<TcpStream> {
+    PduReader::new(TcpStream::connect(addr).unwrap(), FrameFormat::default())
+}
+
+fn read_pdu(reader: &mut PduReader<TcpStream>) -> io::Result<Option<Pdu>> {
+    reader.read_pdu().map_err(io::Error::other)
+}
+
+/// 建立连接并确认已经由工作线程处理
+fn served(addr: SocketAddr) -> PduReader<TcpStream> {
+    let mut stream = connect(addr);
+    send(stream.get_ref(), 1);
+    assert_eq!(read_pdu(&mut stream).unwrap().unwrap().request_id, 1);
+    stream
+}
+
+#[test]
+fn overload_is_reported_as_server_busy() {
+    let (server, addr, runner) = common::start(Server::builder().bind("127.0.0.1:0").model(Model::ThreadPool).workers(2).queue_depth(1));
+    let first = served(addr);
+    let _second = served(addr);
+
+    // 工作线程都在忙，第三个连接排队等待
+    let mut queued = connect(addr);
+    send(queued.get_ref(), 3);
+    queued.get_ref().set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+    assert!(read_pdu(&mut queued).is_err());
+
+    // 队列已满，第四个连接收到繁忙通知后被关闭
+    let mut rejected = connect(addr);
+    assert!(read_pdu(&mut rejected).unwrap().unwrap().is_server_busy());
+    assert!(read_pdu(&mut rejected).unwrap().is_none());
+
+    // 有工作线程空闲后处理排队的连接
+    drop(first);
+    queued.get_ref().set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    assert_eq!(read_pdu(&mut queued).unwrap().unwrap().request_id, 3);
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+}
+
+#[test]
+fn zero_queue_depth_accepts_only_idle_capacity() {
+    let (server, addr, runner) = common::start(Server::builder().bind("127.0.0.1:0").model(Model::ThreadPool).workers(1).queue_depth(0));
+    let first = served(addr);
+    let mut rejected = connect(addr);
+    assert!(read_pdu(&mut rejected).unwrap().unwrap().is_server_busy());
+
+    // 工作线程处理完上一个连接后才能接受新的连接
+    drop(first);
+    let stream = (0..100)
+        .find_map(|_| {
+            let mut stream = connect(addr);
+            send(stream.get_ref(), 2);
+            let response = read_pdu(&mut stream).unwrap().unwrap();
+            if response.is_server_busy() {
+                thread::sleep(Duration::from_millis(20));
+                return None;
+            }
+            Some((stream, response))
+        })
+        .unwrap();
+    assert_eq!(stream.1.request_id, 2);
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+}
+
+#[test]
+fn shutdown_closes_active_and_queued_connections() {
+    let (server, addr, runner) = common::start(Server::builder().bind("127.0.0.1:0").model(Model::ThreadPool).workers(1).queue_depth(4));
+    let mut active = served(addr);
+    let mut queued = connect(addr);
+    thread::sleep(Duration::from_millis(100));
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+    assert!(read_pdu(&mut active).unwrap().is_none());
+    assert!(read_pdu(&mut queued).unwrap().is_none());
+}
+
+#[test]
+fn zero_workers_are_rejected() {
+    let e = Server::builder().bind("127.0.0.1:0").model(Model::ThreadPool).workers(0).build().err().unwrap();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+}
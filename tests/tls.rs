@@ -0,0 +1,154 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+
+use socket::codec::{PduReader, PduWriter};
+use socket::config::{Config, ConfigError};
+use socket::network_handler::{FrameFormat, MsgType, Pdu};
+use socket::server::{Model, Server, ServerBuilder};
+use socket::tls::{self, ClientTlsStream};
+
+/// 测试时生成的自签名证书，PEM 文件写入临时目录
+struct Certs {
+    dir: PathBuf,
+}
+
+impl Certs {
+    fn generate(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("socket-tls-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        std::fs::write(dir.join("cert.pem"), certified.cert.pem()).unwrap();
+        std::fs::write(dir.join("key.pem"), certified.key_pair.serialize_pem()).unwrap();
+        Certs { dir }
+    }
+
+    fn cert(&self) -> PathBuf {
+        self.dir.join("cert.pem")
+    }
+
+    fn key(&self) -> PathBuf {
+        self.dir.join("key.pem")
+    }
+}
+
+impl Drop for Certs {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn tls_server(model: Model, certs: &Certs) -> ServerBuilder {
+    Server::builder().bind("127.0.0.1:0").model(model).tls(tls::server_config(certs.cert(), certs.key()).unwrap())
+}
+
+fn echo_over_tls(model: Model) {
+    let certs = Certs::generate(&format!("{:?}", model));
+    let (server, _, runner) = common::start(tls_server(model, &certs));
+
+    let tcp = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+    let stream = ClientTlsStream::connect(tcp, "localhost", tls::client_config(certs.cert()).unwrap()).unwrap();
+    let format = FrameFormat::default();
+    let mut writer = PduWriter::new(&stream, format);
+    let mut reader = PduReader::new(&stream, format);
+
+    for request_id in 1..=3 {
+        let request = Pdu::with_type(MsgType::Data, request_id, b"hello over tls", &format).unwrap();
+        writer.write_pdu(&request).unwrap();
+        let response = reader.read_pdu().unwrap().unwrap();
+        assert_eq!(response.request_id, request_id);
+        assert_eq!(response.payload, b"hello over tls");
+    }
+    drop(stream);
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+}
+
+#[test]
+fn single_threaded_server() {
+    echo_over_tls(Model::Single);
+}
+
+#[test]
+fn thread_per_connection_server() {
+    echo_over_tls(Model::ThreadPerConnection);
+}
+
+#[test]
+fn tokio_server() {
+    echo_over_tls(Model::Tokio);
+}
+
+#[test]
+fn untrusted_certificate_is_rejected() {
+    let certs = Certs::generate("untrusted");
+    let other = Certs::generate("other");
+    let (server, _, runner) = common::start(tls_server(Model::ThreadPerConnection, &certs));
+
+    let tcp = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+    let result = ClientTlsStream::connect(tcp, "localhost", tls::client_config(other.cert()).unwrap());
+    assert!(result.is_err());
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+}
+
+#[test]
+fn plaintext_client_is_disconnected() {
+    let certs = Certs::generate("plaintext");
+    let (server, _, runner) = common::start(tls_server(Model::ThreadPerConnection, &certs));
+
+    let format = FrameFormat::default();
+    let mut tcp = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+    tcp.write_all(&Pdu::new(b"hello", &format).unwrap().to_vec(&format).unwrap()).unwrap();
+
+    // 服务器不会把明文 PDU 当作业务数据回显
+    let mut received = Vec::new();
+    let _ = tcp.read_to_end(&mut received);
+    assert!(!received.windows(5).any(|window| window == b"hello"));
+
+    server.shutdown();
+    runner.join().unwrap().unwrap();
+}
+
+#[test]
+fn missing_key_is_reported() {
+    let certs = Certs::generate("missing-key");
+    assert!(tls::server_config(certs.cert(), certs.dir.join("absent.pem")).is_err());
+    // 证书文件中没有私钥
+    assert!(tls::server_config(certs.cert(), certs.cert()).is_err());
+}
+
+#[test]
+fn config_reports_failing_file() {
+    let certs = Certs::generate("config");
+    let other = Certs::generate("config-other");
+    let config = |cert: PathBuf, key: PathBuf, client_ca: Option<PathBuf>| {
+        let mut config = Config::default();
+        config.tls.cert = Some(cert);
+        config.tls.key = Some(key);
+        config.tls.client_ca = client_ca;
+        config.tls_config()
+    };
+
+    // 读取失败时报告出错的那个文件，而不是证书
+    let absent = certs.dir.join("absent.pem");
+    match config(certs.cert(), absent.clone(), None) {
+        Err(ConfigError::Io(path, _)) => assert_eq!(path, absent),
+        other => panic!("期望读取私钥失败，实际为 {:?}", other.err()),
+    }
+    match config(certs.cert(), certs.cert(), None) {
+        Err(ConfigError::Io(path, _)) => assert_eq!(path, certs.cert()),
+        other => panic!("期望证书文件中没有私钥，实际为 {:?}", other.err()),
+    }
+    match config(certs.cert(), certs.key(), Some(absent.clone())) {
+        Err(ConfigError::Io(path, _)) => assert_eq!(path, absent),
+        other => panic!("期望读取客户端 CA 失败，实际为 {:?}", other.err()),
+    }
+    // 文件都能读取但证书与私钥不匹配
+    assert!(matches!(config(certs.cert(), other.key(), None), Err(ConfigError::Tls(_))));
+    assert!(config(certs.cert(), certs.key(), Some(other.cert())).unwrap().is_some());
+}
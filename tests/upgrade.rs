@@ -0,0 +1,124 @@
+mod common;
+
+use std::io::{BufRead, BufReader};
+use std::net::TcpStream;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use common::{server_command, spawn_server, wait_for, TempFile};
+use socket::codec::{PduReader, PduWriter};
+use socket::network_handler::{FrameFormat, MsgType, Pdu};
+use socket::upgrade;
+
+/// 发送一个请求，收到回显时返回 true，收到 going-away 或连接被关闭时返回 false
+fn request(stream: &TcpStream, request_id: u32) -> bool {
+    let format = FrameFormat::default();
+    let pdu = Pdu::with_type(MsgType::Data, request_id, b"hello", &format).unwrap();
+    if PduWriter::new(stream, format).write_pdu(&pdu).is_err() {
+        return false;
+    }
+    match PduReader::new(stream, format).read_pdu() {
+        Ok(Some(response)) if !response.is_going_away() => {
+            assert_eq!(response.request_id, request_id);
+            true
+        }
+        _ => false,
+    }
+}
+
+fn upgrade_keeps_port_open(bin: &str) {
+    let (mut child, addr, lines) = spawn_server(server_command(bin).args(["--bind", "127.0.0.1:0", "--log-level", "info"]));
+    let idle = TcpStream::connect(addr).unwrap();
+    assert!(request(&idle, 1));
+
+    // 升级期间持续建立新连接，每个连接都必须能建立，拒绝连接时 connect() 会失败
+    let stop = Arc::new(AtomicBool::new(false));
+    let clients = thread::spawn({
+        let stop = stop.clone();
+        move || {
+            let mut served = 0;
+            while !stop.load(Ordering::SeqCst) {
+                let stream = TcpStream::connect(addr).expect("升级期间连接被拒绝");
+                if request(&stream, 1) {
+                    served += 1;
+                }
+            }
+            served
+        }
+    });
+
+    unsafe { libc::kill(child.id() as i32, libc::SIGUSR2) };
+    let pid: i32 = wait_for(&lines, |line| line.strip_prefix("[srv] 新进程[")?.strip_suffix("]已就绪")?.parse().ok());
+
+    // 旧进程上空闲的连接收到 going-away，旧进程处理完连接后退出
+    wait_for(&lines, |line| line.starts_with("[srv] 停止接受新连接").then_some(()));
+    let pdu = PduReader::new(&idle, FrameFormat::default()).read_pdu().unwrap().unwrap();
+    assert!(pdu.is_going_away());
+    drop(idle);
+    assert!(child.wait().unwrap().success());
+
+    // 旧进程退出后由新进程继续服务
+    thread::sleep(Duration::from_millis(100));
+    stop.store(true, Ordering::SeqCst);
+    assert!(clients.join().unwrap() > 0);
+    let stream = TcpStream::connect(addr).unwrap();
+    assert!(request(&stream, 3));
+    drop(stream);
+
+    // 新进程继承了 stdout，退出之后才能读到文件末尾
+    unsafe { libc::kill(pid, libc::SIGINT) };
+    lines.iter().for_each(drop);
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+#[test]
+fn thread_server_upgrades_on_sigusr2() {
+    upgrade_keeps_port_open(env!("CARGO_BIN_EXE_server_muti_thread"));
+}
+
+#[test]
+fn tokio_server_upgrades_on_sigusr2() {
+    upgrade_keeps_port_open(env!("CARGO_BIN_EXE_server_io_multiplexing"));
+}
+
+#[test]
+fn failed_upgrade_keeps_old_process_running() {
+    let file = TempFile::new("failed.toml", "[server]\nlisten = [\"127.0.0.1:0\"]\n");
+    let (mut child, addr, lines) = spawn_server(
+        server_command(env!("CARGO_BIN_EXE_server_muti_thread"))
+            .arg("--config")
+            .arg(&file.0)
+            .args(["--log-level", "info"])
+            .stderr(Stdio::piped()),
+    );
+    let mut errors = BufReader::new(child.stderr.take().unwrap()).lines().map(Result::unwrap);
+    let stream = TcpStream::connect(addr).unwrap();
+    assert!(request(&stream, 1));
+
+    // 新进程读取配置失败后退出，旧进程继续服务
+    file.write("[server]\nthreads = 4\n");
+    unsafe { libc::kill(child.id() as i32, libc::SIGUSR2) };
+    assert!(errors.any(|line| line.starts_with("[srv] 热升级失败，继续运行")));
+    assert!(request(&stream, 2));
+    assert!(request(&TcpStream::connect(addr).unwrap(), 3));
+
+    drop(stream);
+    unsafe { libc::kill(child.id() as i32, libc::SIGINT) };
+    lines.iter().for_each(drop);
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn invalid_inherited_fds_are_rejected() {
+    let output = Command::new(env!("CARGO_BIN_EXE_server_muti_thread"))
+        .args(["--bind", "127.0.0.1:0"])
+        .env(upgrade::ENV_INHERITED_FDS, "not-a-fd")
+        .env_remove("SOCKET_PSK_TIMEOUT")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains(upgrade::ENV_INHERITED_FDS));
+}